@@ -2,7 +2,7 @@ use std::f64::consts::TAU;
 use crate::core::vec3::Vec3;
 
 /// Pose de cámara simple para órbita + zoom (rotación y distancia)
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq)]
 pub struct CameraPose {
     pub eye: Vec3,
     pub target: Vec3,
@@ -15,6 +15,8 @@ pub struct CameraOrbit {
     pub base_radius: f64,
     pub zoom_amp: f64,
     pub height: f64,
+    /// Segundos por vuelta
+    pub period: f64,
 }
 
 impl CameraOrbit {
@@ -24,12 +26,19 @@ impl CameraOrbit {
             base_radius: 18.0,
             zoom_amp: 2.0,
             height: 8.0,
+            period: 10.0,
         }
     }
 
-    /// t en segundos; una vuelta ~10s (ajústalo a tu gusto)
+    pub fn with_radius(mut self, r: f64) -> Self { self.base_radius = r; self }
+    pub fn with_height(mut self, h: f64) -> Self { self.height = h; self }
+    /// Amplitud del acercamiento (dos por vuelta); 0 = radio fijo.
+    pub fn with_zoom(mut self, amp: f64) -> Self { self.zoom_amp = amp; self }
+    pub fn with_period(mut self, seconds: f64) -> Self { self.period = seconds; self }
+
+    /// t en segundos; una vuelta cada `period`
     pub fn pose_at(&self, t: f64) -> CameraPose {
-        let phase = (t / 10.0) * TAU;
+        let phase = (t / self.period) * TAU;
         let radius = self.base_radius + self.zoom_amp * (2.0 * phase).sin();
         let eye = Vec3::new(
             self.center.x + radius * phase.cos(),
@@ -44,3 +53,68 @@ impl CameraOrbit {
         }
     }
 }
+
+/// Vuelta completa alrededor de `center`: `frames` poses a ángulos
+/// equiespaciados, con el ojo a `radius` en horizontal y a altura `height`
+/// (y absoluta, como `CameraOrbit`), mirando al centro.
+pub fn turntable_poses(
+    center: Vec3,
+    radius: f64,
+    height: f64,
+    fov_deg: f64,
+    frames: u32,
+) -> Vec<CameraPose> {
+    (0..frames)
+        .map(|f| {
+            let phase = f as f64 / frames as f64 * TAU;
+            CameraPose {
+                eye: Vec3::new(
+                    center.x + radius * phase.cos(),
+                    height,
+                    center.z + radius * phase.sin(),
+                ),
+                target: center,
+                up: Vec3::new(0.0, 1.0, 0.0),
+                fov_deg,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_orbit_period_sets_angular_speed() {
+        let center = Vec3::new(1.0, 0.0, 1.0);
+        let angle = |orbit: &CameraOrbit, t: f64| {
+            let e = orbit.pose_at(t).eye - center;
+            e.z.atan2(e.x)
+        };
+        let fast = CameraOrbit::new(center).with_zoom(0.0);
+        let slow = CameraOrbit::new(center).with_zoom(0.0).with_period(20.0);
+        assert!((angle(&fast, 1.5) - 2.0 * angle(&slow, 1.5)).abs() < 1e-9);
+
+        let custom = CameraOrbit::new(center).with_radius(5.0).with_height(2.0).with_zoom(0.0);
+        let eye = custom.pose_at(0.0).eye;
+        assert_eq!(eye, Vec3::new(6.0, 2.0, 1.0));
+    }
+
+    #[test]
+    fn test_turntable_eyes_on_circle() {
+        let center = Vec3::new(3.0, 1.0, -2.0);
+        let poses = turntable_poses(center, 12.0, 6.0, 50.0, 24);
+        assert_eq!(poses.len(), 24);
+        for p in &poses {
+            let flat = Vec3::new(p.eye.x - center.x, 0.0, p.eye.z - center.z);
+            assert!((flat.length() - 12.0).abs() < 1e-9);
+            assert_eq!(p.eye.y, 6.0);
+            assert_eq!(p.target, center);
+            assert_eq!(p.fov_deg, 50.0);
+        }
+        // ángulos equiespaciados: el último no repite el primero
+        let step = (poses[1].eye - poses[0].eye).length();
+        assert!(((poses[23].eye - poses[0].eye).length() - step).abs() < 1e-9);
+    }
+}
@@ -1,9 +1,175 @@
-use crate::core::vec3::Vec3;
+use crate::core::vec3::{luminance, Vec3};
 
-pub struct DayNight {}
+/// Colores del cielo. Los valores por defecto son la paleta pastel de
+/// siempre.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+    pub zenith_day: Vec3,
+    pub horizon_day: Vec3,
+    pub zenith_night: Vec3,
+    /// Horizonte en el crepúsculo (también tiñe la noche)
+    pub horizon_twilight: Vec3,
+    /// Tinte cálido del amanecer/atardecer
+    pub sunrise_tint: Vec3,
+    /// (elevación del sol, color) en orden creciente de elevación. Si no
+    /// está vacío reemplaza a los colores de arriba: `sky_color` interpola
+    /// entre los dos más cercanos y se queda en los extremos.
+    pub keyframes: Vec<(f64, Vec3)>,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        // Paletas pastel
+        Self {
+            zenith_day: Vec3::new(0.55, 0.75, 1.00),       // Azul suave pastel
+            horizon_day: Vec3::new(0.90, 0.95, 1.00),      // Azul casi blanco
+            zenith_night: Vec3::new(0.06, 0.08, 0.12),     // Azul marino leve
+            horizon_twilight: Vec3::new(0.68, 0.50, 0.72), // Rosa-morado suave
+            sunrise_tint: Vec3::new(1.00, 0.70, 0.55),     // rosa-dorado soft
+            keyframes: Vec::new(),
+        }
+    }
+}
+
+/// Modelo del color del cielo.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SkyModel {
+    /// Degradado pastel de `Palette` (el de siempre)
+    #[default]
+    Stylized,
+    /// Dispersión simple Rayleigh + Mie según la dirección de vista y la del
+    /// sol. `turbidity` ~2 es aire limpio; más alto, más bruma blanca.
+    Physical { turbidity: f64 },
+}
+
+/// Profundidad óptica de Rayleigh hacia el cénit por canal (RGB ~ 680, 550
+/// y 440 nm): el azul se dispersa unas seis veces más que el rojo.
+const RAYLEIGH_DEPTH: Vec3 = Vec3::new(0.046, 0.108, 0.265);
+/// Profundidad óptica de Mie hacia el cénit por unidad de turbidez.
+const MIE_DEPTH: f64 = 0.005;
+/// Asimetría de Henyey-Greenstein de Mie (halo hacia adelante).
+const MIE_G: f64 = 0.76;
+/// Escala de radiancia para quedar cerca del brillo del cielo estilizado.
+const PHYSICAL_SKY_SCALE: f64 = 20.0;
+
+/// Cielo físico con el sol fijo: la luz que llega del sol ya atenuada por
+/// el aire se dispersa hacia cada dirección de vista.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PhysicalSky {
+    sun_dir: Vec3,
+    mie: f64,
+    /// Transmitancia del aire para la luz del sol (roja al atardecer)
+    sun_transmittance: Vec3,
+    /// Desaturación por nubes (como en `DayNight::sky_color`)
+    desaturation: f64,
+}
+
+impl PhysicalSky {
+    pub fn new(sun_dir: Vec3, turbidity: f64) -> Self {
+        let sun_dir = sun_dir.normalized();
+        let mie = MIE_DEPTH * turbidity.max(1.0);
+        let m = air_mass(sun_dir.y);
+        Self {
+            sun_dir,
+            mie,
+            sun_transmittance: exp3((RAYLEIGH_DEPTH + Vec3::new(mie, mie, mie)) * -m),
+            desaturation: 0.0,
+        }
+    }
+
+    /// Radiancia del cielo en la dirección unitaria `d`.
+    pub fn radiance(&self, d: Vec3) -> Vec3 {
+        let cos = d.dot(self.sun_dir).clamp(-1.0, 1.0);
+        let pi = std::f64::consts::PI;
+        let phase_r = 3.0 / (16.0 * pi) * (1.0 + cos * cos);
+        let g2 = MIE_G * MIE_G;
+        let phase_m = (1.0 - g2) / (4.0 * pi * (1.0 + g2 - 2.0 * MIE_G * cos).powf(1.5));
+
+        let m = air_mass(d.y);
+        let channel = |beta_r: f64, sun_t: f64| {
+            let beta = beta_r + self.mie;
+            // fracción dispersada por el camino de vista, repartida por fase
+            let scattered = 1.0 - (-beta * m).exp();
+            sun_t * scattered * (beta_r * phase_r + self.mie * phase_m) / beta
+        };
+        let t = self.sun_transmittance;
+        let c = Vec3::new(
+            channel(RAYLEIGH_DEPTH.x, t.x),
+            channel(RAYLEIGH_DEPTH.y, t.y),
+            channel(RAYLEIGH_DEPTH.z, t.z),
+        );
+        desaturate(c * PHYSICAL_SKY_SCALE, self.desaturation)
+    }
+}
+
+/// Masa de aire relativa (Kasten-Young) para una dirección de altura `y`:
+/// 1 en el cénit, ~38 en el horizonte.
+fn air_mass(y: f64) -> f64 {
+    let y = y.clamp(0.0, 1.0);
+    let zenith_deg = y.acos().to_degrees();
+    1.0 / (y + 0.50572 * (96.07995 - zenith_deg).powf(-1.6364))
+}
+
+fn exp3(v: Vec3) -> Vec3 {
+    Vec3::new(v.x.exp(), v.y.exp(), v.z.exp())
+}
+
+#[derive(Default)]
+pub struct DayNight {
+    palette: Palette,
+    /// Nublado: 0 = despejado, 1 = cubierto
+    overcast: f64,
+    sky_model: SkyModel,
+}
 
 impl DayNight {
-    pub fn new() -> Self { Self{} }
+    pub fn new() -> Self { Self::default() }
+
+    /// Cambia los colores del cielo (tormenta, cielo alienígena...).
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    /// Cielo cubierto en `amount` (0 = despejado, como siempre; 1 =
+    /// cubierto): sol más débil y gris, sombras más blandas, cielo apagado y
+    /// más luz ambiente.
+    pub fn set_overcast(&mut self, amount: f64) {
+        self.overcast = amount.clamp(0.0, 1.0);
+    }
+
+    pub fn overcast(&self) -> f64 {
+        self.overcast
+    }
+
+    /// Cielo estilizado (por defecto) o físico.
+    pub fn set_sky_model(&mut self, model: SkyModel) {
+        self.sky_model = model;
+    }
+
+    pub fn sky_model(&self) -> SkyModel {
+        self.sky_model
+    }
+
+    /// Cielo físico en `t`; None con el modelo estilizado.
+    pub fn physical_sky(&self, t: f64) -> Option<PhysicalSky> {
+        match self.sky_model {
+            SkyModel::Stylized => None,
+            SkyModel::Physical { turbidity } => Some(PhysicalSky {
+                desaturation: 0.6 * self.overcast,
+                ..PhysicalSky::new(self.sun_direction(t), turbidity)
+            }),
+        }
+    }
+
+    /// Factor sobre la apertura del disco solar en las sombras (1 con el
+    /// cielo despejado).
+    pub fn sun_softness(&self) -> f64 {
+        1.0 + 5.0 * self.overcast
+    }
 
    
     pub fn sun_direction(&self, t: f64) -> Vec3 {
@@ -23,7 +189,7 @@ impl DayNight {
 
         // antes: 1.0
         // soft summer: cálido y suave
-        0.45 * base
+        0.45 * base * (1.0 - 0.85 * self.overcast)
     }
 
   
@@ -39,31 +205,46 @@ impl DayNight {
         let noon = Vec3::new(1.00, 0.95, 0.88); // blanco cálido
 
         let k = elev.clamp(0.0, 1.0);
-        warm * (1.0 - k) + noon * k
+        desaturate(warm * (1.0 - k) + noon * k, 0.8 * self.overcast)
     }
 
   
     pub fn sky_color(&self, t: f64) -> Vec3 {
-        let sun = self.sun_direction(t);
-        let elev = sun.y;
+        match self.physical_sky(t) {
+            // promedio del cénit y de cuatro direcciones a 30° de altura (ya
+            // desaturado por las nubes)
+            Some(sky) => {
+                let (s, c) = (0.5, 0.75f64.sqrt());
+                let dirs = [
+                    Vec3::new(0.0, 1.0, 0.0),
+                    Vec3::new(c, s, 0.0),
+                    Vec3::new(-c, s, 0.0),
+                    Vec3::new(0.0, s, c),
+                    Vec3::new(0.0, s, -c),
+                ];
+                dirs.iter().fold(Vec3::default(), |acc, &d| acc + sky.radiance(d)) / 5.0
+            }
+            None => desaturate(self.sky_color_at(self.sun_direction(t).y), 0.6 * self.overcast),
+        }
+    }
 
-        // Paletas pastel
-        let zenith_day   = Vec3::new(0.55, 0.75, 1.00);  // Azul suave pastel
-        let horizon_day  = Vec3::new(0.90, 0.95, 1.00);  // Azul casi blanco
-        let zenith_night = Vec3::new(0.06, 0.08, 0.12);  // Azul marino leve
-        let horizon_tw   = Vec3::new(0.68, 0.50, 0.72);  // Rosa-morado suave
+    /// Color del cielo con el sol a elevación `elev` (y de su dirección).
+    fn sky_color_at(&self, elev: f64) -> Vec3 {
+        let p = &self.palette;
+        if let Some(c) = interpolate_keyframes(&p.keyframes, elev) {
+            return c;
+        }
 
         // Noche clara estilo verano
         if elev <= -0.03 {
-            return zenith_night * 0.7 + horizon_tw * 0.3;
+            return p.zenith_night * 0.7 + p.horizon_twilight * 0.3;
         }
 
         // Día
-        let f_day = elev.clamp(0.0, 1.0);
-        let base = zenith_day * 0.55 + horizon_day * 0.45;
+        let base = p.zenith_day * 0.55 + p.horizon_day * 0.45;
 
         // Toque cálido de atardecer/amanecer pastel
-        let warm_tint = Vec3::new(1.00, 0.70, 0.55);  // rosa-dorado soft
+        let warm_tint = p.sunrise_tint;
         let horizon_mix = (0.5 - elev).clamp(0.0, 0.5) / 0.5;
 
         // Mezcla final
@@ -74,13 +255,124 @@ impl DayNight {
     pub fn ambient_level(&self, t: f64) -> f64 {
         let elev = self.sun_direction(t).y;
 
-        if elev < -0.2 {
+        let level = if elev < -0.2 {
             0.05  // noche suave de verano
         } else if elev < 0.0 {
             0.05 + ((elev + 0.2) / 0.2) * 0.06
         } else {
             0.12 + elev * 0.06 // más suave que antes
-        }
+        };
+        // las nubes reparten la luz del sol por todo el cielo
+        level + 0.12 * self.overcast
+    }
+}
+
+
+/// Mezcla `c` hacia su gris de igual luminancia en la proporción `k`.
+fn desaturate(c: Vec3, k: f64) -> Vec3 {
+    if k <= 0.0 {
+        return c;
     }
+    let g = luminance(c);
+    c * (1.0 - k) + Vec3::new(g, g, g) * k
 }
 
+/// Color en `x` según los keyframes (x, color) ordenados; None si no hay.
+fn interpolate_keyframes(keys: &[(f64, Vec3)], x: f64) -> Option<Vec3> {
+    let first = keys.first()?;
+    let last = keys[keys.len() - 1];
+    if x <= first.0 {
+        return Some(first.1);
+    }
+    if x >= last.0 {
+        return Some(last.1);
+    }
+    let i = keys.iter().position(|k| k.0 > x)?;
+    let (x0, c0) = keys[i - 1];
+    let (x1, c1) = keys[i];
+    let k = if x1 > x0 { (x - x0) / (x1 - x0) } else { 1.0 };
+    Some(c0 * (1.0 - k) + c1 * k)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_custom_night_color() {
+        let default = DayNight::new();
+        let mut custom = DayNight::new();
+        custom.set_palette(Palette {
+            zenith_night: Vec3::new(0.3, 0.0, 0.0),
+            ..Palette::default()
+        });
+        assert_ne!(custom.sky_color_at(-0.5), default.sky_color_at(-0.5));
+        assert_eq!(custom.sky_color_at(1.0), default.sky_color_at(1.0));
+        // mediodía real del ciclo
+        assert_eq!(custom.sky_color(35.0), default.sky_color(35.0));
+    }
+
+    #[test]
+    fn test_overcast_dims_sun_and_lifts_ambient() {
+        let clear = DayNight::new();
+        let mut cloudy = DayNight::new();
+        cloudy.set_overcast(1.0);
+        let t = 30.0;
+        assert!(cloudy.sun_intensity(t) < clear.sun_intensity(t) * 0.3);
+        assert!(cloudy.ambient_level(t) > clear.ambient_level(t) + 0.05);
+        assert!(cloudy.sun_softness() > clear.sun_softness());
+        // sol más gris: menos diferencia entre rojo y azul
+        let (c0, c1) = (clear.sun_color(t), cloudy.sun_color(t));
+        assert!(c1.x - c1.z < (c0.x - c0.z) * 0.5);
+
+        // 0 es exactamente el cielo de siempre
+        cloudy.set_overcast(0.0);
+        assert_eq!(cloudy.sun_intensity(t), clear.sun_intensity(t));
+        assert_eq!(cloudy.sun_color(t), clear.sun_color(t));
+        assert_eq!(cloudy.sky_color(t), clear.sky_color(t));
+    }
+
+    #[test]
+    fn test_physical_sky_blue_zenith_and_red_sunset() {
+        let mut dn = DayNight::new();
+        assert!(dn.physical_sky(35.0).is_none());
+        dn.set_sky_model(SkyModel::Physical { turbidity: 2.0 });
+        let blueness = |c: Vec3| c.z / c.x;
+
+        // Mediodía: cénit más azul que el horizonte, que es más claro
+        let noon = dn.physical_sky(35.0).unwrap();
+        let away = Vec3::new(-dn.sun_direction(35.0).x, 0.0, -1.0).normalized();
+        let horizon = noon.radiance(Vec3::new(away.x, 0.02, away.z).normalized());
+        let zenith = noon.radiance(Vec3::new(0.0, 1.0, 0.0));
+        assert!(blueness(zenith) > blueness(horizon) * 1.3, "{:?} {:?}", zenith, horizon);
+        assert!(luminance(horizon) > luminance(zenith));
+
+        // Atardecer: el horizonte del lado del sol se enrojece
+        let t = 0.5;
+        let sun = dn.sun_direction(t);
+        assert!(sun.y < 0.05);
+        let dusk = dn.physical_sky(t).unwrap();
+        let toward = Vec3::new(sun.x, 0.05, sun.z).normalized();
+        let noon_toward = noon.radiance(toward);
+        let red = dusk.radiance(toward);
+        assert!(red.x > red.z, "{:?}", red);
+        assert!(blueness(red) < blueness(noon_toward) * 0.5);
+
+        // sky_color sigue el modelo
+        assert_ne!(dn.sky_color(35.0), DayNight::new().sky_color(35.0));
+    }
+
+    #[test]
+    fn test_sky_keyframes_interpolate_and_clamp() {
+        let mut dn = DayNight::new();
+        let (night, day) = (Vec3::new(0.0, 0.0, 0.2), Vec3::new(0.4, 0.6, 1.0));
+        dn.set_palette(Palette {
+            keyframes: vec![(-0.2, night), (0.6, day)],
+            ..Palette::default()
+        });
+        assert_eq!(dn.sky_color_at(-1.0), night);
+        assert_eq!(dn.sky_color_at(0.9), day);
+        let mid = dn.sky_color_at(0.2);
+        assert!((mid.x - 0.2).abs() < 1e-12 && (mid.z - 0.6).abs() < 1e-12);
+    }
+}
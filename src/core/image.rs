@@ -1,9 +1,9 @@
 // src/core/image.rs
 
-use std::fs::File;
-use std::io::{BufWriter, Write};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
 
-use crate::core::vec3::Color;
+use crate::core::vec3::{luminance, Color};
 
 pub struct Image {
     pub w: usize,
@@ -20,6 +20,11 @@ impl Image {
         }
     }
 
+    /// Pone todos los píxeles en negro sin reasignar el buffer.
+    pub fn clear(&mut self) {
+        self.data.fill(Color::new(0.0, 0.0, 0.0));
+    }
+
     #[inline]
     pub fn set(&mut self, x: usize, y: usize, c: Color) {
         if x < self.w && y < self.h {
@@ -32,22 +37,182 @@ impl Image {
         self.data[y * self.w + x]
     }
 
+    /// Mezcla `top` sobre esta imagen con la esquina superior izquierda en
+    /// (x, y) y opacidad global `alpha` (0..1). Lo que cae fuera se recorta.
+    pub fn composite_over(&mut self, top: &Image, x: usize, y: usize, alpha: f64) {
+        let a = alpha.clamp(0.0, 1.0);
+        let x1 = (x + top.w).min(self.w);
+        let y1 = (y + top.h).min(self.h);
+        for dy in y..y1 {
+            for dx in x..x1 {
+                let src = top.get(dx - x, dy - y);
+                let dst = self.get(dx, dy);
+                self.set(dx, dy, dst * (1.0 - a) + src * a);
+            }
+        }
+    }
+
+    /// Reduce la imagen promediando bloques `factor`x`factor` (supersampling).
+    /// Si las dimensiones no son divisibles, el último bloque de cada eje
+    /// promedia solo los píxeles que existen.
+    pub fn downsample(&self, factor: usize) -> Image {
+        let f = factor.max(1);
+        let mut out = Image::new(self.w.div_ceil(f), self.h.div_ceil(f));
+        for oy in 0..out.h {
+            for ox in 0..out.w {
+                let (x0, y0) = (ox * f, oy * f);
+                let (x1, y1) = ((x0 + f).min(self.w), (y0 + f).min(self.h));
+                let mut acc = Color::new(0.0, 0.0, 0.0);
+                for y in y0..y1 {
+                    for x in x0..x1 {
+                        acc = acc + self.get(x, y);
+                    }
+                }
+                out.set(ox, oy, acc / ((x1 - x0) * (y1 - y0)) as f64);
+            }
+        }
+        out
+    }
+
+    /// Copia en escala de grises: la luminancia Rec.709 replicada en RGB.
+    pub fn to_luminance(&self) -> Image {
+        Image {
+            w: self.w,
+            h: self.h,
+            data: self
+                .data
+                .iter()
+                .map(|&c| {
+                    let l = luminance(c);
+                    Color::new(l, l, l)
+                })
+                .collect(),
+        }
+    }
+
+    /// Bytes RGB de 8 bits por canal, fila a fila desde arriba y sin padding
+    /// (el formato `rawvideo rgb24` que espera ffmpeg).
+    pub fn to_rgb24(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.w * self.h * 3);
+        for c in &self.data {
+            out.extend_from_slice(&[f2u8(c.x), f2u8(c.y), f2u8(c.z)]);
+        }
+        out
+    }
+
+    /// Igual que `to_rgb24` pero con 16 bits por canal: menos banding en
+    /// degradados suaves si después se va a corregir color.
+    pub fn to_rgb48(&self) -> Vec<u16> {
+        let mut out = Vec::with_capacity(self.w * self.h * 3);
+        for c in &self.data {
+            out.extend_from_slice(&[f2u16(c.x), f2u16(c.y), f2u16(c.z)]);
+        }
+        out
+    }
+
+    /// Guarda como PNG RGB de 16 bits por canal.
+    pub fn save_png16(&self, path: &str) {
+        let buf = image::ImageBuffer::<image::Rgb<u16>, _>::from_raw(
+            self.w as u32,
+            self.h as u32,
+            self.to_rgb48(),
+        )
+        .expect("buffer de tamaño inválido");
+        buf.save_with_format(path, image::ImageFormat::Png)
+            .expect("No se pudo escribir el PNG");
+    }
+
     /// Guarda como BMP 24-bit (BGR), **bottom-up** con padding de filas a múltiplos de 4 bytes.
     pub fn save_bmp(&self, path: &str) {
         save_bmp24(self, path).expect("No se pudo escribir el BMP");
     }
+
+    /// Lee un BMP 24-bit sin compresión (el formato de `save_bmp`).
+    /// Acepta alto positivo (bottom-up) y negativo (top-down); los valores
+    /// quedan en [0,1] tal como se guardaron.
+    pub fn load_bmp(path: &str) -> io::Result<Image> {
+        load_bmp24(&fs::read(path)?)
+    }
 }
 
 #[inline]
 fn f2u8(v: f64) -> u8 {
-    let c = if v < 0.0 { 0.0 } else if v > 1.0 { 1.0 } else { v };
+    let c = v.clamp(0.0, 1.0);
     (c * 255.0 + 0.5).floor() as u8
 }
 
+#[inline]
+fn f2u16(v: f64) -> u16 {
+    let c = v.clamp(0.0, 1.0);
+    (c * 65535.0 + 0.5).floor() as u16
+}
+
+fn bad_bmp(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+fn le_u16(b: &[u8], off: usize) -> io::Result<u16> {
+    b.get(off..off + 2)
+        .map(|s| u16::from_le_bytes([s[0], s[1]]))
+        .ok_or_else(|| bad_bmp("BMP truncado"))
+}
+
+fn le_u32(b: &[u8], off: usize) -> io::Result<u32> {
+    b.get(off..off + 4)
+        .map(|s| u32::from_le_bytes([s[0], s[1], s[2], s[3]]))
+        .ok_or_else(|| bad_bmp("BMP truncado"))
+}
+
+fn load_bmp24(buf: &[u8]) -> io::Result<Image> {
+    if buf.get(0..2) != Some(b"BM".as_slice()) {
+        return Err(bad_bmp("no es un BMP"));
+    }
+    let pixel_offset = le_u32(buf, 10)? as usize;
+    let w = le_u32(buf, 18)? as i32;
+    let h = le_u32(buf, 22)? as i32;
+    let bpp = le_u16(buf, 28)?;
+    let compression = le_u32(buf, 30)?;
+    if bpp != 24 || compression != 0 {
+        return Err(bad_bmp("solo se soporta BMP 24-bit sin compresión"));
+    }
+    if w <= 0 || h == 0 {
+        return Err(bad_bmp("dimensiones inválidas"));
+    }
+
+    let w = w as usize;
+    let bottom_up = h > 0;
+    let h = h.unsigned_abs() as usize;
+    let row_stride = (w * 3).div_ceil(4) * 4;
+    if buf.len() < pixel_offset + row_stride * h {
+        return Err(bad_bmp("BMP truncado"));
+    }
+
+    let mut img = Image::new(w, h);
+    for row in 0..h {
+        // bottom-up => la primera fila del archivo es la de abajo
+        let y = if bottom_up { h - 1 - row } else { row };
+        let base = pixel_offset + row * row_stride;
+        for x in 0..w {
+            let p = base + x * 3;
+            // almacenado BGR
+            img.set(
+                x,
+                y,
+                Color::new(
+                    buf[p + 2] as f64 / 255.0,
+                    buf[p + 1] as f64 / 255.0,
+                    buf[p] as f64 / 255.0,
+                ),
+            );
+        }
+    }
+    Ok(img)
+}
+
 fn save_bmp24(img: &Image, path: &str) -> std::io::Result<()> {
     let w = img.w as u32;
     let h = img.h as i32; // positivo => bottom-up
-    let row_stride = ((w as usize * 3 + 3) / 4) * 4; // múltiplo de 4
+    let row_stride = (w as usize * 3).div_ceil(4) * 4; // múltiplo de 4
     let img_size = row_stride * (h as usize);
     let file_size = 14 + 40 + img_size;
 
@@ -100,3 +265,136 @@ fn save_bmp24(img: &Image, path: &str) -> std::io::Result<()> {
     f.flush()?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_rgb24_layout() {
+        let mut img = Image::new(3, 2);
+        img.set(0, 0, Color::new(1.0, 0.0, 0.0));
+        img.set(2, 1, Color::new(0.0, 0.0, 1.0));
+        let raw = img.to_rgb24();
+        assert_eq!(raw.len(), 3 * 2 * 3);
+        assert_eq!(&raw[0..3], &[255, 0, 0]);
+        let last = (3 + 2) * 3; // (y=1, x=2) en una imagen de ancho 3
+        assert_eq!(&raw[last..last + 3], &[0, 0, 255]);
+    }
+
+    #[test]
+    fn test_png16_keeps_more_precision_than_8_bit() {
+        let mut img = Image::new(1, 1);
+        img.set(0, 0, Color::new(0.5, 0.0, 1.0));
+        assert_eq!(&img.to_rgb24()[..], &[128, 0, 255]);
+        assert_eq!(&img.to_rgb48()[..], &[32768, 0, 65535]);
+
+        let path = std::env::temp_dir().join(format!("pg_rt_{}.png", std::process::id()));
+        let path = path.to_string_lossy().into_owned();
+        img.save_png16(&path);
+        let back = image::open(&path).unwrap().into_rgb16();
+        let _ = fs::remove_file(&path);
+        assert_eq!(back.get_pixel(0, 0).0, [32768, 0, 65535]);
+    }
+
+    #[test]
+    fn test_composite_over_blends_and_clips() {
+        let mut base = Image::new(4, 4);
+        let mut patch = Image::new(2, 2);
+        for c in patch.data.iter_mut() {
+            *c = Color::new(1.0, 1.0, 1.0);
+        }
+
+        base.composite_over(&patch, 1, 1, 0.5);
+        assert_eq!(base.get(1, 1), Color::new(0.5, 0.5, 0.5));
+        assert_eq!(base.get(2, 2), Color::new(0.5, 0.5, 0.5));
+        assert_eq!(base.get(0, 0), Color::new(0.0, 0.0, 0.0));
+        assert_eq!(base.get(3, 3), Color::new(0.0, 0.0, 0.0));
+
+        // En la esquina: solo (3,3) cae dentro, el resto se ignora
+        let mut base = Image::new(4, 4);
+        base.composite_over(&patch, 3, 3, 0.5);
+        assert_eq!(base.get(3, 3), Color::new(0.5, 0.5, 0.5));
+        assert_eq!(base.get(2, 3), Color::new(0.0, 0.0, 0.0));
+        assert_eq!(base.get(3, 2), Color::new(0.0, 0.0, 0.0));
+
+        // Totalmente fuera: no hace nada ni se sale de rango
+        base.composite_over(&patch, 10, 10, 1.0);
+    }
+
+    #[test]
+    fn test_downsample_block_average() {
+        let mut img = Image::new(4, 4);
+        for y in 0..4 {
+            for x in 0..4 {
+                img.set(x, y, Color::new((y * 4 + x) as f64, 0.0, 1.0));
+            }
+        }
+        let small = img.downsample(2);
+        assert_eq!((small.w, small.h), (2, 2));
+        // bloque (0,0): 0,1,4,5 ; (1,0): 2,3,6,7 ; (0,1): 8,9,12,13 ; (1,1): 10,11,14,15
+        assert_eq!(small.get(0, 0), Color::new(2.5, 0.0, 1.0));
+        assert_eq!(small.get(1, 0), Color::new(4.5, 0.0, 1.0));
+        assert_eq!(small.get(0, 1), Color::new(10.5, 0.0, 1.0));
+        assert_eq!(small.get(1, 1), Color::new(12.5, 0.0, 1.0));
+
+        // 4x4 entre 3: el último bloque de cada eje es de 1 píxel
+        let odd = img.downsample(3);
+        assert_eq!((odd.w, odd.h), (2, 2));
+        assert_eq!(odd.get(1, 1), Color::new(15.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn test_to_luminance() {
+        let mut img = Image::new(2, 1);
+        img.set(0, 0, Color::new(1.0, 0.0, 0.0));
+        img.set(1, 0, Color::new(1.0, 1.0, 1.0));
+        let lum = img.to_luminance();
+        let red = lum.get(0, 0);
+        assert!((red.x - 0.2126).abs() < 1e-9);
+        assert_eq!((red.x, red.x), (red.y, red.z));
+        assert!((lum.get(1, 0) - Color::new(1.0, 1.0, 1.0)).length() < 1e-9);
+    }
+
+    #[test]
+    fn test_bmp_round_trip() {
+        // ancho 3 => filas con padding
+        let mut img = Image::new(3, 2);
+        for y in 0..2 {
+            for x in 0..3 {
+                img.set(x, y, Color::new(x as f64 / 3.0, y as f64 / 2.0, 0.25 + 0.1 * x as f64));
+            }
+        }
+        let path = std::env::temp_dir().join(format!("pg_rt_{}.bmp", std::process::id()));
+        let path = path.to_string_lossy().into_owned();
+        img.save_bmp(&path);
+        let back = Image::load_bmp(&path).unwrap();
+        let _ = fs::remove_file(&path);
+
+        assert_eq!((back.w, back.h), (3, 2));
+        for (a, b) in img.data.iter().zip(&back.data) {
+            assert!((*a - *b).length() <= 3.0_f64.sqrt() / 255.0, "{:?} vs {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn test_load_bmp_top_down() {
+        // 1x2 top-down: fila 0 roja, fila 1 azul (BGR + 1 byte de padding)
+        let mut buf = Vec::new();
+        buf.extend_from_slice(b"BM");
+        buf.extend_from_slice(&(54u32 + 8).to_le_bytes());
+        buf.extend_from_slice(&[0; 4]);
+        buf.extend_from_slice(&54u32.to_le_bytes());
+        buf.extend_from_slice(&40u32.to_le_bytes());
+        buf.extend_from_slice(&1i32.to_le_bytes());
+        buf.extend_from_slice(&(-2i32).to_le_bytes());
+        buf.extend_from_slice(&1u16.to_le_bytes());
+        buf.extend_from_slice(&24u16.to_le_bytes());
+        buf.extend_from_slice(&[0; 24]);
+        buf.extend_from_slice(&[0, 0, 255, 0, 255, 0, 0, 0]);
+
+        let img = load_bmp24(&buf).unwrap();
+        assert_eq!(img.get(0, 0), Color::new(1.0, 0.0, 0.0));
+        assert_eq!(img.get(0, 1), Color::new(0.0, 0.0, 1.0));
+    }
+}
@@ -0,0 +1,181 @@
+use std::ops::Mul;
+
+use super::vec3::Vec3;
+
+/// Matriz 4x4 de transformación afín, por filas: `m[fila][columna]`. Los
+/// puntos son columnas (x, y, z, 1), así que `a * b` aplica primero `b`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mat4 {
+    pub m: [[f64; 4]; 4],
+}
+
+impl Mat4 {
+    pub const fn identity() -> Self {
+        Self {
+            m: [
+                [1.0, 0.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0, 0.0],
+                [0.0, 0.0, 1.0, 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    pub fn translation(t: Vec3) -> Self {
+        let mut r = Self::identity();
+        r.m[0][3] = t.x;
+        r.m[1][3] = t.y;
+        r.m[2][3] = t.z;
+        r
+    }
+
+    pub fn scale(s: Vec3) -> Self {
+        let mut r = Self::identity();
+        r.m[0][0] = s.x;
+        r.m[1][1] = s.y;
+        r.m[2][2] = s.z;
+        r
+    }
+
+    /// Rotación en grados alrededor de +Y (como `Portal::rot_y_deg`).
+    pub fn rotation_y(deg: f64) -> Self {
+        let (s, c) = deg.to_radians().sin_cos();
+        let mut r = Self::identity();
+        r.m[0][0] = c;
+        r.m[0][2] = s;
+        r.m[2][0] = -s;
+        r.m[2][2] = c;
+        r
+    }
+
+    pub fn transform_point(&self, p: Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3],
+            m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3],
+            m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3],
+        )
+    }
+
+    /// Dirección: sin la traslación.
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        )
+    }
+
+    /// Normal con la transpuesta de `self`; pasando la inversa de una
+    /// transformación lleva normales de espacio local a mundo.
+    pub fn transform_normal_transposed(&self, n: Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * n.x + m[1][0] * n.y + m[2][0] * n.z,
+            m[0][1] * n.x + m[1][1] * n.y + m[2][1] * n.z,
+            m[0][2] * n.x + m[1][2] * n.y + m[2][2] * n.z,
+        )
+        .normalized()
+    }
+
+    /// Determinante de la parte 3x3: negativo si la transformación espeja
+    /// (y da vuelta el sentido de giro de los triángulos), ~0 si aplasta.
+    pub fn determinant3(&self) -> f64 {
+        let m = &self.m;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    /// Inversa de una transformación afín (la última fila es 0 0 0 1).
+    /// None si la parte 3x3 es singular.
+    pub fn inverse_affine(&self) -> Option<Self> {
+        let m = &self.m;
+        let cof = |r0: usize, r1: usize, c0: usize, c1: usize| {
+            m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+        };
+        // adjunta de la 3x3
+        let a = [
+            [cof(1, 2, 1, 2), -cof(0, 2, 1, 2), cof(0, 1, 1, 2)],
+            [-cof(1, 2, 0, 2), cof(0, 2, 0, 2), -cof(0, 1, 0, 2)],
+            [cof(1, 2, 0, 1), -cof(0, 2, 0, 1), cof(0, 1, 0, 1)],
+        ];
+        let det = m[0][0] * a[0][0] + m[0][1] * a[1][0] + m[0][2] * a[2][0];
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let mut r = Self::identity();
+        for (row, adj) in r.m.iter_mut().zip(a) {
+            for (v, c) in row.iter_mut().zip(adj) {
+                *v = c / det;
+            }
+        }
+        let t = r.transform_vector(Vec3::new(m[0][3], m[1][3], m[2][3]));
+        r.m[0][3] = -t.x;
+        r.m[1][3] = -t.y;
+        r.m[2][3] = -t.z;
+        Some(r)
+    }
+}
+
+impl Default for Mat4 {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl Mul for Mat4 {
+    type Output = Self;
+    fn mul(self, o: Self) -> Self {
+        let mut r = [[0.0; 4]; 4];
+        for (i, row) in r.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                *v = (0..4).map(|k| self.m[i][k] * o.m[k][j]).sum();
+            }
+        }
+        Self { m: r }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        (a - b).length() < 1e-9
+    }
+
+    #[test]
+    fn test_compose_and_invert() {
+        let t = Mat4::translation(Vec3::new(3.0, -1.0, 2.0))
+            * Mat4::rotation_y(90.0)
+            * Mat4::scale(Vec3::new(2.0, 2.0, 2.0));
+        // escala, luego gira +X hacia -Z, luego traslada
+        let p = t.transform_point(Vec3::new(1.0, 0.0, 0.0));
+        assert!(close(p, Vec3::new(3.0, -1.0, 0.0)), "{:?}", p);
+
+        let inv = t.inverse_affine().unwrap();
+        let q = Vec3::new(0.3, -2.0, 5.0);
+        assert!(close(inv.transform_point(t.transform_point(q)), q));
+        let id = t * inv;
+        for i in 0..4 {
+            for j in 0..4 {
+                let want = if i == j { 1.0 } else { 0.0 };
+                assert!((id.m[i][j] - want).abs() < 1e-12);
+            }
+        }
+        assert!(Mat4::scale(Vec3::new(1.0, 0.0, 1.0)).inverse_affine().is_none());
+    }
+
+    #[test]
+    fn test_normals_stay_perpendicular_under_scale() {
+        let t = Mat4::scale(Vec3::new(4.0, 1.0, 1.0));
+        let inv = t.inverse_affine().unwrap();
+        // Plano x + y = 0 con normal (1, 1, 0)/√2
+        let n = inv.transform_normal_transposed(Vec3::new(1.0, 1.0, 0.0).normalized());
+        let along = t.transform_vector(Vec3::new(1.0, -1.0, 0.0));
+        assert!(n.dot(along).abs() < 1e-12);
+        assert!((n.length() - 1.0).abs() < 1e-12);
+    }
+}
@@ -1,6 +1,8 @@
 // src/core/mod.rs
 
 pub mod vec3;
+pub mod mat4;
 pub mod image;
 pub mod ray;
 pub mod rng; // si tienes rng.rs; si no, quita esta línea
+pub mod video;
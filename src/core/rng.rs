@@ -8,3 +8,12 @@ impl Rng {
     pub fn next_f32(&mut self)->f32{ (self.next_u32() as f32) / (u32::MAX as f32) }
     pub fn next_f64(&mut self)->f64{ (self.next_u32() as f64) / (u32::MAX as f64) }
 }
+
+/// Mezcla tipo splitmix64: deriva una semilla independiente de (semilla, k).
+/// Sirve para semillas por frame/píxel que no dependen del orden de ejecución.
+pub fn mix_seed(seed: u64, k: u64) -> u64 {
+    let mut z = seed ^ k.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
@@ -5,7 +5,7 @@ use std::ops::{Add, Sub, Mul, Div, Neg};
 pub struct Vec3 { pub x: f64, pub y: f64, pub z: f64 }
 
 impl Vec3 {
-    pub fn new(x: f64, y: f64, z: f64) -> Self { Self { x, y, z } }
+    pub const fn new(x: f64, y: f64, z: f64) -> Self { Self { x, y, z } }
     pub fn dot(self, o: Self) -> f64 { self.x*o.x + self.y*o.y + self.z*o.z }
     pub fn cross(self, o: Self) -> Self {
         Self::new(
@@ -47,5 +47,8 @@ impl Mul<Vec3> for f64 {
 
 pub type Color = Vec3;
 
-pub fn clamp01(x: f64) -> f64 { if x < 0.0 { 0.0 } else if x > 1.0 { 1.0 } else { x } }
+pub fn clamp01(x: f64) -> f64 { x.clamp(0.0, 1.0) }
 pub fn to_u8(x: f64) -> u8 { (clamp01(x).powf(1.0/2.2) * 255.0 + 0.5) as u8 }
+
+/// Luminancia relativa (pesos Rec.709) de un color lineal.
+pub fn luminance(c: Color) -> f64 { 0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z }
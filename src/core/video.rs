@@ -0,0 +1,67 @@
+// src/core/video.rs
+
+use std::io::{self, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+
+use crate::core::image::Image;
+
+/// Salida de video: lanza `ffmpeg` y le pasa cada cuadro como RGB crudo por
+/// stdin. El formato (mp4, webm, ...) lo decide ffmpeg por la extensión.
+pub struct VideoSink {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    w: usize,
+    h: usize,
+}
+
+impl VideoSink {
+    /// Devuelve `None` (y avisa por stderr) si no se pudo lanzar `ffmpeg`,
+    /// p.ej. porque no está en el PATH.
+    pub fn open(path: &str, w: usize, h: usize, fps: f64) -> Option<Self> {
+        let spawned = Command::new("ffmpeg")
+            .args(["-y", "-loglevel", "error"])
+            .args(["-f", "rawvideo", "-pix_fmt", "rgb24"])
+            .args(["-s", &format!("{}x{}", w, h)])
+            .args(["-r", &fps.to_string()])
+            .args(["-i", "-"])
+            .args(["-pix_fmt", "yuv420p", path])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn();
+
+        match spawned {
+            Ok(mut child) => {
+                let stdin = child.stdin.take();
+                Some(Self { child, stdin, w, h })
+            }
+            Err(e) => {
+                eprintln!("No se pudo lanzar ffmpeg ({}); solo se guardarán los BMP", e);
+                None
+            }
+        }
+    }
+
+    pub fn push_frame(&mut self, img: &Image) -> io::Result<()> {
+        if img.w != self.w || img.h != self.h {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "el cuadro no coincide con el tamaño del video",
+            ));
+        }
+        match &mut self.stdin {
+            Some(stdin) => stdin.write_all(&img.to_rgb24()),
+            None => Err(io::Error::new(io::ErrorKind::BrokenPipe, "ffmpeg ya fue cerrado")),
+        }
+    }
+
+    /// Cierra stdin y espera a que ffmpeg termine de escribir el archivo.
+    pub fn finish(mut self) -> io::Result<()> {
+        drop(self.stdin.take());
+        let status = self.child.wait()?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(io::Error::other(format!("ffmpeg terminó con {}", status)))
+        }
+    }
+}
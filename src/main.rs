@@ -1,16 +1,13 @@
 use std::fs;
 use std::path::Path;
 
-use crate::app::camera::CameraOrbit;
-use crate::core::image::Image;
-use crate::core::vec3::Vec3;
-use crate::render::renderer::Renderer;
-use crate::scene::builder::build_minecraft_house_scene;
-
-mod app;
-mod core;
-mod render;
-mod scene;
+use proyecto_2_graficas::app::camera::CameraOrbit;
+use proyecto_2_graficas::core::image::Image;
+use proyecto_2_graficas::core::video::VideoSink;
+use proyecto_2_graficas::core::vec3::Vec3;
+use proyecto_2_graficas::render::progress::Progress;
+use proyecto_2_graficas::render::renderer::Renderer;
+use proyecto_2_graficas::scene::builder::build_minecraft_house_scene;
 
 fn main() {
     // Resolución y samples
@@ -29,9 +26,17 @@ fn main() {
         fs::create_dir_all(outdir).expect("no se pudo crear carpeta de salida");
     }
 
+    // Video directo con ffmpeg (además de los BMP), p. ej.
+    // Some("target/timelapse.mp4"); apagado por defecto
+    let video_path: Option<&str> = None;
+    let mut video = video_path.and_then(|p| VideoSink::open(p, width, height, fps));
+
     // Renderer
     let mut renderer = Renderer::new(width, height, spp);
+    // JSON con cámara, hora, spp, semilla y exposición junto a cada BMP
+    renderer.set_sidecars(false);
     renderer.set_use_procedural_sky(true); // usar DayNight (cielo procedural)
+    renderer.set_max_portal_jumps(4); // ver a través de los portales de la casa
 
     // Escena
     let scene = build_minecraft_house_scene();
@@ -39,9 +44,16 @@ fn main() {
 
     // ====== CÁMARA ORBITAL ======
     // Orbitando alrededor del centro de la casa (~8,3,8)
-    let orbit = CameraOrbit::new(Vec3::new(8.0, 3.0, 8.0));
+    let orbit = CameraOrbit::new(Vec3::new(8.0, 3.0, 8.0))
+        .with_radius(18.0)
+        .with_height(8.0)
+        .with_zoom(2.0)
+        .with_period(10.0);
 
     let mut img = Image::new(width, height);
+    // avance + ETA por stderr, por cuadro (el de tiles de `set_verbose` se
+    // mezclaría con este)
+    let progress = Progress::new("frames", nframes as usize);
 
     for f in 0..nframes {
         // Tiempo en segundos desde el inicio
@@ -55,12 +67,31 @@ fn main() {
         renderer.set_camera(&cam_pose);
 
         // Render
-        renderer.render_frame(&mut img, day_time);
+        let stats = renderer.render_frame(&mut img, day_time);
 
         // Guardar frame
         let path = format!("{}/frame_{:04}.bmp", outdir, f);
         img.save_bmp(&path);
+        renderer.save_sidecar(&path, renderer.frame_meta(day_time, None, &stats));
         println!("Saved {}", path);
+        progress.tick();
+
+        if let Some(sink) = &mut video {
+            if let Err(e) = sink.push_frame(&img) {
+                eprintln!("Error enviando cuadro a ffmpeg: {}", e);
+                // cerrar ffmpeg y mostrar por qué se cortó
+                if let Some(Err(e)) = video.take().map(VideoSink::finish) {
+                    eprintln!("ffmpeg falló: {}", e);
+                }
+            }
+        }
+    }
+
+    if let (Some(sink), Some(path)) = (video, video_path) {
+        match sink.finish() {
+            Ok(()) => println!("Video escrito en {}", path),
+            Err(e) => eprintln!("ffmpeg falló: {}", e),
+        }
     }
 
     println!("\nListo. Generados {} frames en {}", nframes, outdir);
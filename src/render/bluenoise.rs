@@ -0,0 +1,109 @@
+use std::sync::OnceLock;
+
+/// Lado de la textura de ruido azul; se repite en mosaico por la imagen.
+pub const BLUE_NOISE_SIZE: usize = 32;
+
+/// Sigma del núcleo gaussiano de void-and-cluster, en píxeles.
+const SIGMA: f64 = 1.5;
+
+/// Valor en [0, 1) del ruido azul en (x, y), repitiendo la textura.
+pub fn blue_noise_at(x: usize, y: usize) -> f64 {
+    let n = BLUE_NOISE_SIZE;
+    texture()[(y % n) * n + x % n]
+}
+
+/// Desplazamiento sub-píxel de la muestra `s` del píxel (x, y): secuencia
+/// R2 rotada (Cranley-Patterson) por dos lecturas del ruido azul. `offset`
+/// corre la textura por frame para no repetir el patrón.
+pub fn pixel_jitter(x: usize, y: usize, s: usize, offset: (usize, usize)) -> (f64, f64) {
+    // 1/g y 1/g² con g la razón plástica
+    const A1: f64 = 0.754_877_666_246_692_7;
+    const A2: f64 = 0.569_840_290_998_053_3;
+    let (x, y) = (x + offset.0, y + offset.1);
+    let half = BLUE_NOISE_SIZE / 2;
+    let bx = blue_noise_at(x, y);
+    let by = blue_noise_at(x + half, y + half);
+    ((bx + A1 * s as f64).fract(), (by + A2 * s as f64).fract())
+}
+
+fn texture() -> &'static [f64] {
+    static TEX: OnceLock<Vec<f64>> = OnceLock::new();
+    TEX.get_or_init(|| void_and_cluster(BLUE_NOISE_SIZE))
+}
+
+/// Void-and-cluster simplificado: se llena la textura de a un píxel, siempre
+/// el hueco más grande (menor energía gaussiana toroidal), y el orden de
+/// llenado es el valor. Determinista: los empates van al primer índice.
+fn void_and_cluster(n: usize) -> Vec<f64> {
+    let total = n * n;
+    // Núcleo por distancia con vuelta en los bordes
+    let mut kernel = vec![0.0; total];
+    for dy in 0..n {
+        for dx in 0..n {
+            let wx = dx.min(n - dx) as f64;
+            let wy = dy.min(n - dy) as f64;
+            kernel[dy * n + dx] = (-(wx * wx + wy * wy) / (2.0 * SIGMA * SIGMA)).exp();
+        }
+    }
+
+    let mut energy = vec![0.0; total];
+    let mut rank = vec![usize::MAX; total];
+    for r in 0..total {
+        let mut best = usize::MAX;
+        for i in 0..total {
+            if rank[i] == usize::MAX && (best == usize::MAX || energy[i] < energy[best]) {
+                best = i;
+            }
+        }
+        rank[best] = r;
+        let (bx, by) = (best % n, best / n);
+        for y in 0..n {
+            for x in 0..n {
+                let k = ((y + n - by) % n) * n + (x + n - bx) % n;
+                energy[y * n + x] += kernel[k];
+            }
+        }
+    }
+    rank.iter().map(|&r| (r as f64 + 0.5) / total as f64).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blue_noise_range_and_tiling() {
+        let n = BLUE_NOISE_SIZE;
+        let mut values: Vec<f64> = (0..n * n).map(|i| blue_noise_at(i % n, i / n)).collect();
+        assert!(values.iter().all(|&v| (0.0..1.0).contains(&v)));
+        // Cada nivel aparece una vez
+        values.sort_by(f64::total_cmp);
+        for (i, v) in values.iter().enumerate() {
+            assert!((v - (i as f64 + 0.5) / (n * n) as f64).abs() < 1e-12);
+        }
+
+        for y in 0..n {
+            for x in 0..n {
+                let v = blue_noise_at(x, y);
+                assert_eq!(blue_noise_at(x + n, y), v);
+                assert_eq!(blue_noise_at(x, y + 3 * n), v);
+            }
+        }
+        // Cruzando el borde los vecinos siguen siendo distintos, como dentro
+        let seam: f64 = (0..n).map(|y| (blue_noise_at(n - 1, y) - blue_noise_at(n, y)).abs()).sum();
+        let inner: f64 = (0..n).map(|y| (blue_noise_at(9, y) - blue_noise_at(10, y)).abs()).sum();
+        assert!(seam / n as f64 > 0.3 && inner / n as f64 > 0.3);
+
+        // Ruido azul: vecinos más distintos que con ruido blanco (1/3)
+        let mut diff = 0.0;
+        for y in 0..n {
+            for x in 0..n {
+                diff += (blue_noise_at(x, y) - blue_noise_at(x + 1, y)).abs();
+            }
+        }
+        assert!(diff / (n * n) as f64 > 0.38, "{}", diff / (n * n) as f64);
+
+        let (jx, jy) = pixel_jitter(5, 7, 3, (11, 2));
+        assert!((0.0..1.0).contains(&jx) && (0.0..1.0).contains(&jy));
+    }
+}
@@ -0,0 +1,136 @@
+use std::f64::consts::PI;
+
+use crate::core::vec3::{Color, Vec3};
+use crate::scene::Material;
+
+/// Modelo de sombreado de sol y luces.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ShadingModel {
+    /// Lambert + brillo Blinn-Phong del sol (el de siempre)
+    #[default]
+    Legacy,
+    /// Cook-Torrance GGX con `metallic` y `roughness` del material
+    Pbr,
+}
+
+/// Superficie lista para evaluar el BRDF metálico/rugosidad.
+#[derive(Clone, Copy, Debug)]
+pub struct PbrSurface {
+    /// Albedo difuso; cero en metales
+    pub diffuse: Color,
+    /// Reflectancia a incidencia normal: `specular` en dieléctricos, el
+    /// albedo en metales
+    pub f0: Color,
+    alpha: f64,
+}
+
+impl PbrSurface {
+    pub fn new(mat: &Material, albedo: Color) -> Self {
+        let m = mat.metallic.clamp(0.0, 1.0);
+        let r = mat.roughness.clamp(0.03, 1.0);
+        let dielectric = Color::new(mat.specular, mat.specular, mat.specular);
+        Self {
+            diffuse: albedo * (1.0 - m),
+            f0: dielectric * (1.0 - m) + albedo * m,
+            alpha: r * r,
+        }
+    }
+
+    /// Color que recibe la luz ambiente: difuso más la reflectancia base.
+    pub fn ambient_albedo(&self) -> Color {
+        self.diffuse + self.f0
+    }
+
+    /// BRDF por coseno por pi: con una luz de irradiancia 1 desde `l` da la
+    /// radiancia hacia `v`. El factor pi deja la parte difusa igual que
+    /// Lambert (`albedo * n·l`), así que las intensidades no cambian.
+    pub fn eval(&self, n: Vec3, v: Vec3, l: Vec3) -> Color {
+        let nl = n.dot(l);
+        // vistas rasantes o por detrás de la normal interpolada
+        let nv = n.dot(v).max(1e-4);
+        if nl <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        let h = (v + l).normalized();
+        let nh = n.dot(h).max(0.0);
+        let vh = v.dot(h).max(0.0);
+
+        // GGX
+        let a2 = self.alpha * self.alpha;
+        let den = nh * nh * (a2 - 1.0) + 1.0;
+        let d = a2 / (PI * den * den);
+
+        // Smith con la aproximación de Schlick
+        let k = self.alpha * 0.5;
+        let g = (nl / (nl * (1.0 - k) + k)) * (nv / (nv * (1.0 - k) + k));
+
+        let fw = (1.0 - vh).powi(5);
+        let f = self.f0 * (1.0 - fw) + Color::new(fw, fw, fw);
+
+        let spec = f * (d * g / (4.0 * nl * nv));
+        let kd = Color::new(1.0 - f.x, 1.0 - f.y, 1.0 - f.z);
+        let diffuse = Color::new(
+            kd.x * self.diffuse.x,
+            kd.y * self.diffuse.y,
+            kd.z * self.diffuse.z,
+        ) / PI;
+        (diffuse + spec) * (nl * PI)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Energía reflejada con la luz en `l`: integral de la radiancia sobre
+    /// el hemisferio de vistas, sobre la irradiancia (n·l).
+    fn reflected_energy(s: &PbrSurface, l: Vec3) -> f64 {
+        let n = Vec3::new(0.0, 1.0, 0.0);
+        let (nt, np) = (400, 400);
+        let dt = 0.5 * PI / nt as f64;
+        let dp = 2.0 * PI / np as f64;
+        let mut sum = 0.0;
+        for i in 0..nt {
+            let theta = (i as f64 + 0.5) * dt;
+            for j in 0..np {
+                let phi = (j as f64 + 0.5) * dp;
+                let v = Vec3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin());
+                let r = s.eval(n, v, l).x / PI;
+                sum += r * theta.cos() * theta.sin() * dt * dp;
+            }
+        }
+        sum / n.dot(l)
+    }
+
+    #[test]
+    fn test_rough_dielectric_spreads_and_loses_energy() {
+        // Albedo negro: solo queda el lóbulo especular
+        let black = Color::new(0.0, 0.0, 0.0);
+        let base = Material::new("d", black, None);
+        let smooth = PbrSurface::new(&base.clone().with_roughness(0.3), black);
+        let rough = PbrSurface::new(&base.with_roughness(0.9), black);
+
+        let n = Vec3::new(0.0, 1.0, 0.0);
+        let l = Vec3::new(0.5, 1.0, 0.0).normalized();
+        let mirror = Vec3::new(-l.x, l.y, l.z);
+        assert!(smooth.eval(n, mirror, l).x > 5.0 * rough.eval(n, mirror, l).x);
+
+        let e_smooth = reflected_energy(&smooth, l);
+        let e_rough = reflected_energy(&rough, l);
+        assert!(e_smooth > 0.03 && e_smooth < 1.0, "smooth {e_smooth}");
+        assert!(e_rough > 0.01 && e_rough < e_smooth, "rough {e_rough} smooth {e_smooth}");
+    }
+
+    #[test]
+    fn test_metal_has_no_diffuse_and_tinted_specular() {
+        let gold = Color::new(1.0, 0.8, 0.3);
+        let mat = Material::new("gold", gold, None).with_metallic(1.0).with_roughness(0.4);
+        let s = PbrSurface::new(&mat, gold);
+        assert_eq!(s.diffuse, Color::new(0.0, 0.0, 0.0));
+
+        let n = Vec3::new(0.0, 1.0, 0.0);
+        let c = s.eval(n, n, n);
+        assert!(c.x > c.y && c.y > c.z);
+        assert!((c.y / c.x - 0.8).abs() < 1e-9);
+    }
+}
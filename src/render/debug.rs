@@ -0,0 +1,73 @@
+use crate::core::vec3::Color;
+
+/// Vistas de depuración: reemplazan el sombreado normal en el impacto y
+/// se escriben tal cual, sin exposición, tonemap ni gamma.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum DebugMode {
+    #[default]
+    Off,
+    /// Aristas de voxels y triángulos en claro sobre fondo oscuro
+    Wireframe,
+    /// Pruebas caja/triángulo por píxel (cámara + sombras): azul = barato,
+    /// rojo = caro
+    RayHeat,
+    /// Normal del impacto de [-1, 1] a RGB [0, 1]
+    Normals,
+    /// Distancia del impacto en grises: blanco en `near`, negro en `far`
+    Depth { near: f64, far: f64 },
+}
+
+/// Color de arista del modo wireframe.
+pub const WIRE_EDGE: Color = Color::new(1.0, 0.85, 0.2);
+/// Relleno de caras y cielo del modo wireframe.
+pub const WIRE_FILL: Color = Color::new(0.02, 0.02, 0.03);
+
+/// Cielo en las vistas de normales y profundidad: magenta, que no sale de
+/// ninguna normal unitaria ni de un gris.
+pub const SKY_SENTINEL: Color = Color::new(1.0, 0.0, 1.0);
+
+pub fn normal_color(n: Color) -> Color {
+    let n = n.normalized();
+    Color::new(n.x * 0.5 + 0.5, n.y * 0.5 + 0.5, n.z * 0.5 + 0.5)
+}
+
+pub fn depth_color(t: f64, near: f64, far: f64) -> Color {
+    let k = 1.0 - ((t - near) / (far - near).max(1e-9)).clamp(0.0, 1.0);
+    Color::new(k, k, k)
+}
+
+/// Pruebas por píxel que saturan el mapa de calor en rojo.
+pub const HEAT_MAX_TESTS: u64 = 1_000_000;
+
+/// Rampa azul -> cian -> verde -> amarillo -> rojo en escala logarítmica
+/// (el costo varía en órdenes de magnitud entre cielo y malla).
+pub fn heat_color(tests: u64) -> Color {
+    let t = ((1.0 + tests as f64).ln() / (1.0 + HEAT_MAX_TESTS as f64).ln()).clamp(0.0, 1.0);
+    let seg = t * 4.0;
+    let f = seg.fract();
+    match seg as u32 {
+        0 => Color::new(0.0, f, 1.0),
+        1 => Color::new(0.0, 1.0, 1.0 - f),
+        2 => Color::new(f, 1.0, 0.0),
+        3 => Color::new(1.0, 1.0 - f, 0.0),
+        _ => Color::new(1.0, 0.0, 0.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heat_ramp_runs_blue_to_red() {
+        assert_eq!(heat_color(0), Color::new(0.0, 0.0, 1.0));
+        assert_eq!(heat_color(HEAT_MAX_TESTS), Color::new(1.0, 0.0, 0.0));
+        // Rojo nunca baja y azul nunca sube al crecer el costo
+        let mut prev = heat_color(0);
+        for k in 1..=60 {
+            let c = heat_color((1.3f64.powi(k)) as u64);
+            assert!(c.x >= prev.x && c.z <= prev.z);
+            prev = c;
+        }
+    }
+}
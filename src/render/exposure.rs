@@ -0,0 +1,68 @@
+use crate::core::vec3::{luminance, Color};
+
+/// Evita ln(0) en píxeles negros.
+const LUM_DELTA: f64 = 1e-4;
+/// Límites de la exposición automática (un frame casi negro no debe
+/// amplificarse sin tope).
+const MIN_EXPOSURE: f64 = 1.0 / 64.0;
+const MAX_EXPOSURE: f64 = 64.0;
+
+/// Luminancia log-promedio (Reinhard): exp(mean(ln(delta + L))).
+pub fn log_average_luminance(fb: &[Color]) -> f64 {
+    if fb.is_empty() {
+        return 0.0;
+    }
+    let sum: f64 = fb
+        .iter()
+        .map(|&c| (LUM_DELTA + luminance(c).max(0.0)).ln())
+        .sum();
+    (sum / fb.len() as f64).exp()
+}
+
+/// Factor que lleva la luminancia log-promedio del frame a `key`.
+pub fn exposure_for(fb: &[Color], key: f64) -> f64 {
+    let lavg = log_average_luminance(fb);
+    if lavg <= 0.0 {
+        return 1.0;
+    }
+    (key / lavg).clamp(MIN_EXPOSURE, MAX_EXPOSURE)
+}
+
+/// Suavizado exponencial entre frames, en espacio logarítmico para que
+/// subir y bajar un paso tarden lo mismo. `k` es el peso del frame nuevo
+/// (1 = sin suavizado).
+pub fn smooth_exposure(prev: Option<f64>, new: f64, k: f64) -> f64 {
+    match prev {
+        Some(p) if p > 0.0 => {
+            let k = k.clamp(0.0, 1.0);
+            (p.ln() + (new.ln() - p.ln()) * k).exp()
+        }
+        _ => new,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bright_and_dim_frames_map_to_key() {
+        let key = 0.18;
+        for level in [4.0, 0.02] {
+            let fb = vec![Color::new(level, level, level); 16];
+            let e = exposure_for(&fb, key);
+            let exposed = log_average_luminance(&fb) * e;
+            assert!((exposed - key).abs() < 1e-9, "nivel {}: {}", level, exposed);
+        }
+        assert!(exposure_for(&[Color::new(4.0, 4.0, 4.0)], key) < 1.0);
+        assert!(exposure_for(&[Color::new(0.02, 0.02, 0.02)], key) > 1.0);
+    }
+
+    #[test]
+    fn test_smoothing_moves_part_way() {
+        assert_eq!(smooth_exposure(None, 4.0, 0.25), 4.0);
+        let e = smooth_exposure(Some(1.0), 4.0, 0.5);
+        assert!((e - 2.0).abs() < 1e-12);
+        assert_eq!(smooth_exposure(Some(1.0), 4.0, 1.0), 4.0);
+    }
+}
@@ -0,0 +1,119 @@
+use crate::app::camera::CameraPose;
+use crate::core::vec3::Vec3;
+use crate::scene::instance::points_bounds;
+use crate::scene::Scene;
+
+/// Pirámide de visión de la cámara: cuatro planos laterales por el ojo y
+/// el plano lejano. Cada plano es (normal hacia dentro, d) con
+/// `n·p + d >= 0` dentro.
+#[derive(Clone, Copy, Debug)]
+pub struct Frustum {
+    planes: [(Vec3, f64); 5],
+}
+
+impl Frustum {
+    /// Mismo encuadre que `make_primary_ray`: `aspect` es ancho/alto de la
+    /// imagen ya con el aspecto de píxel aplicado.
+    pub fn new(cam: &CameraPose, aspect: f64, far: f64) -> Self {
+        let scale = (cam.fov_deg.to_radians() * 0.5).tan();
+        let forward = (cam.target - cam.eye).normalized();
+        let right = forward.cross(cam.up).normalized();
+        let up = right.cross(forward).normalized();
+
+        let (rx, uy) = (right * (aspect * scale), up * scale);
+        let corners = [
+            forward - rx + uy,
+            forward + rx + uy,
+            forward + rx - uy,
+            forward - rx - uy,
+        ];
+        // el último queda como plano lejano, mirando hacia el ojo
+        let mut planes = [(-forward, forward.dot(cam.eye) + far); 5];
+        for i in 0..4 {
+            let mut n = corners[i].cross(corners[(i + 1) % 4]).normalized();
+            if n.dot(forward) < 0.0 {
+                n = -n;
+            }
+            planes[i] = (n, -n.dot(cam.eye));
+        }
+        Self { planes }
+    }
+
+    /// false solo si la caja queda entera fuera de algún plano
+    /// (conservador: puede dejar pasar cajas que no se ven).
+    pub fn intersects_aabb(&self, min: Vec3, max: Vec3) -> bool {
+        self.planes.iter().all(|&(n, d)| {
+            // vértice de la caja más adentro según la normal
+            let p = Vec3::new(
+                if n.x >= 0.0 { max.x } else { min.x },
+                if n.y >= 0.0 { max.y } else { min.y },
+                if n.z >= 0.0 { max.z } else { min.z },
+            );
+            n.dot(p) + d >= 0.0
+        })
+    }
+}
+
+/// Objetos de primer nivel que pueden aparecer en los rayos de cámara.
+/// Las sombras y rebotes siguen viendo toda la escena.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PrimaryVisible {
+    /// Por índice en `Scene::voxels`
+    pub voxels: Vec<bool>,
+    /// La malla `Scene::triangles` como un todo
+    pub mesh: bool,
+    /// Por índice en `Scene::instances`
+    pub instances: Vec<bool>,
+}
+
+impl PrimaryVisible {
+    pub fn new(scene: &Scene, frustum: &Frustum) -> Self {
+        let tri_points: Vec<Vec3> =
+            scene.triangles.iter().flat_map(|t| [t.v0, t.v1, t.v2]).collect();
+        let (lo, hi) = points_bounds(&tri_points);
+        Self {
+            voxels: scene.voxels.iter().map(|v| frustum.intersects_aabb(v.min, v.max)).collect(),
+            mesh: !tri_points.is_empty() && frustum.intersects_aabb(lo, hi),
+            instances: scene
+                .instances
+                .iter()
+                .map(|inst| {
+                    let (lo, hi) = inst.bounds();
+                    frustum.intersects_aabb(lo, hi)
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::voxel::Voxel;
+
+    #[test]
+    fn test_objects_behind_camera_are_culled() {
+        let mut scene = Scene::new();
+        scene.voxels.push(Voxel::from_grid(0, 0, 0, 0));
+        // detrás de la cámara
+        scene.voxels.push(Voxel::from_grid(0, 0, 20, 0));
+        // fuera por la derecha
+        scene.voxels.push(Voxel::from_grid(30, 0, 0, 0));
+        let cam = CameraPose {
+            eye: Vec3::new(0.5, 0.5, 10.0),
+            target: Vec3::new(0.5, 0.5, 0.0),
+            up: Vec3::new(0.0, 1.0, 0.0),
+            fov_deg: 60.0,
+        };
+        let vis = PrimaryVisible::new(&scene, &Frustum::new(&cam, 1.0, 1e6));
+        assert_eq!(vis.voxels, vec![true, false, false]);
+        assert!(!vis.mesh);
+
+        // una caja que envuelve al ojo siempre pasa
+        let f = Frustum::new(&cam, 1.0, 1e6);
+        assert!(f.intersects_aabb(Vec3::new(0.0, 0.0, 9.0), Vec3::new(1.0, 1.0, 11.0)));
+        // y el plano lejano recorta
+        let near_only = Frustum::new(&cam, 1.0, 5.0);
+        assert!(!near_only.intersects_aabb(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 1.0)));
+    }
+}
@@ -1 +1,13 @@
+pub mod bluenoise;
+pub mod brdf;
+pub mod cancel;
+pub mod debug;
+pub mod denoise;
+pub mod exposure;
+pub mod frustum;
+pub mod pattern;
+pub mod post;
+pub mod progress;
 pub mod renderer;
+pub mod sidecar;
+pub mod stats;
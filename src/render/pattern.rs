@@ -0,0 +1,117 @@
+/// Cómo se reparten las muestras de cámara dentro del píxel.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SamplePattern {
+    /// Todas las muestras al centro del píxel, como siempre; las extra del
+    /// antialias de siluetas sí se reparten
+    #[default]
+    Center,
+    /// Jitter aleatorio (ruido azul o blanco, ver `Renderer::set_blue_noise`)
+    Random,
+    /// Centros de una grilla de m x n celdas con m · n = spp, lo más
+    /// cuadrada posible (spp primo queda en una sola fila)
+    Grid,
+    /// La grilla girada atan(1/2) (~26.6°): ninguna fila ni columna se
+    /// repite, mejor en bordes casi horizontales o verticales
+    RotatedGrid,
+    /// Secuencia de Halton en bases 2 y 3 (baja discrepancia)
+    Halton,
+}
+
+/// Desplazamiento en [0, 1)² de la muestra `s` de `spp`; None con `Random`.
+/// Las muestras extra (`s >= spp`, antialias de siluetas) siguen la
+/// secuencia de Halton para no repetir las de la grilla.
+pub fn pattern_offset(pattern: SamplePattern, s: usize, spp: usize) -> Option<(f64, f64)> {
+    // n filas (el mayor divisor de spp que no pasa de √spp) de m columnas:
+    // todas las celdas llenas, así la media de las muestras es el centro
+    let cells = spp.max(1);
+    let n = (1..=cells).take_while(|n| n * n <= cells).filter(|&n| cells.is_multiple_of(n)).last();
+    let n = n.unwrap_or(1);
+    let grid = |s: usize| {
+        let m = cells / n;
+        let (i, j) = (s % m, s / m);
+        ((i as f64 + 0.5) / m as f64, (j as f64 + 0.5) / n as f64)
+    };
+    match pattern {
+        SamplePattern::Random => None,
+        _ if s >= spp => Some(halton2(s)),
+        SamplePattern::Center => Some((0.5, 0.5)),
+        SamplePattern::Grid => Some(grid(s)),
+        SamplePattern::RotatedGrid => {
+            let (x, y) = grid(s);
+            let (sin, cos) = 0.5f64.atan().sin_cos();
+            let (dx, dy) = (x - 0.5, y - 0.5);
+            let wrap = |v: f64| (v + 0.5).rem_euclid(1.0);
+            Some((wrap(dx * cos - dy * sin), wrap(dx * sin + dy * cos)))
+        }
+        SamplePattern::Halton => Some(halton2(s)),
+    }
+}
+
+/// Punto `s` de Halton (2, 3), desde el índice 1 para no empezar en (0, 0).
+fn halton2(s: usize) -> (f64, f64) {
+    (radical_inverse(s + 1, 2), radical_inverse(s + 1, 3))
+}
+
+fn radical_inverse(mut i: usize, base: usize) -> f64 {
+    let inv = 1.0 / base as f64;
+    let (mut f, mut r) = (inv, 0.0);
+    while i > 0 {
+        r += f * (i % base) as f64;
+        i /= base;
+        f *= inv;
+    }
+    r
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grid_and_halton_offsets() {
+        let offsets = |p: SamplePattern, n: usize| -> Vec<(f64, f64)> {
+            (0..n).map(|s| pattern_offset(p, s, n).unwrap()).collect()
+        };
+        let grid = offsets(SamplePattern::Grid, 4);
+        assert_eq!(grid, vec![(0.25, 0.25), (0.75, 0.25), (0.25, 0.75), (0.75, 0.75)]);
+        assert_eq!(pattern_offset(SamplePattern::Random, 0, 4), None);
+        assert_eq!(pattern_offset(SamplePattern::Center, 3, 4), Some((0.5, 0.5)));
+
+        let halton = offsets(SamplePattern::Halton, 4);
+        let third = 1.0 / 3.0;
+        let want = [(0.5, third), (0.25, 2.0 * third), (0.75, 1.0 / 9.0), (0.125, 4.0 / 9.0)];
+        for (h, w) in halton.iter().zip(want) {
+            assert!((h.0 - w.0).abs() < 1e-12 && (h.1 - w.1).abs() < 1e-12, "{:?}", h);
+        }
+
+        // Baja discrepancia: con 64 muestras cada franja de ancho 1/8 recibe
+        // exactamente 8
+        let pts = offsets(SamplePattern::Halton, 64);
+        for k in 0..8 {
+            let lo = k as f64 / 8.0;
+            let in_col = pts.iter().filter(|p| p.0 >= lo && p.0 < lo + 0.125).count();
+            assert_eq!(in_col, 8);
+        }
+
+        // Girada: todas las x distintas (ninguna columna repetida)
+        let mut xs: Vec<f64> = offsets(SamplePattern::RotatedGrid, 4).iter().map(|p| p.0).collect();
+        xs.sort_by(f64::total_cmp);
+        assert!(xs.windows(2).all(|w| w[1] - w[0] > 0.1));
+        assert!(xs.iter().all(|x| (0.0..1.0).contains(x)));
+    }
+
+    #[test]
+    fn test_grid_mean_is_pixel_center_for_any_spp() {
+        for p in [SamplePattern::Grid, SamplePattern::RotatedGrid] {
+            for spp in [2, 3, 5, 6, 9] {
+                let pts: Vec<_> = (0..spp).map(|s| pattern_offset(p, s, spp).unwrap()).collect();
+                let n = spp as f64;
+                let mean = pts.iter().fold((0.0, 0.0), |a, q| (a.0 + q.0 / n, a.1 + q.1 / n));
+                assert!((mean.0 - 0.5).abs() < 1e-12, "{:?} spp {}: {:?}", p, spp, mean);
+                assert!((mean.1 - 0.5).abs() < 1e-12, "{:?} spp {}: {:?}", p, spp, mean);
+            }
+        }
+        // 6 = 3 x 2: dos filas de tres
+        assert_eq!(pattern_offset(SamplePattern::Grid, 3, 6), Some((1.0 / 6.0, 0.75)));
+    }
+}
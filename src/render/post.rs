@@ -0,0 +1,139 @@
+use crate::core::image::Image;
+use crate::core::rng::{mix_seed, Rng};
+use crate::core::vec3::{luminance, Color};
+use crate::render::bluenoise::blue_noise_at;
+
+/// Aberración cromática radial: R se muestrea un poco más afuera y B un
+/// poco más adentro que G, en proporción a la distancia al centro.
+/// `strength` es el desplazamiento relativo (0.005 ya se nota en las
+/// esquinas). Las lecturas fuera del cuadro se recortan al borde.
+pub fn chromatic_aberration(img: &Image, strength: f64) -> Image {
+    let mut out = Image::new(img.w, img.h);
+    let cx = (img.w as f64 - 1.0) * 0.5;
+    let cy = (img.h as f64 - 1.0) * 0.5;
+    for y in 0..img.h {
+        for x in 0..img.w {
+            let (dx, dy) = (x as f64 - cx, y as f64 - cy);
+            let r = sample_bilinear(img, cx + dx * (1.0 + strength), cy + dy * (1.0 + strength));
+            let g = img.get(x, y);
+            let b = sample_bilinear(img, cx + dx * (1.0 - strength), cy + dy * (1.0 - strength));
+            out.set(x, y, Color::new(r.x, g.y, b.z));
+        }
+    }
+    out
+}
+
+/// Grano de película: ruido uniforme de media cero y amplitud `strength`,
+/// igual en los tres canales, más marcado en sombras (x1.5) que en luces
+/// (x0.5). Cada píxel siembra su `Rng` con `seed`, así que el grano es
+/// estable para una semilla y cambia con ella.
+pub fn film_grain(img: &mut Image, strength: f64, seed: u64) {
+    for (i, c) in img.data.iter_mut().enumerate() {
+        let mut rng = Rng::new(mix_seed(seed, i as u64));
+        let noise = (rng.next_f64() * 2.0 - 1.0) * strength;
+        let response = 1.5 - luminance(*c).clamp(0.0, 1.0);
+        *c = *c + Color::new(1.0, 1.0, 1.0) * (noise * response);
+    }
+}
+
+/// Dither antes de cuantizar a 8 bits: suma ruido azul de menos de un
+/// escalón (±0.5/255) para que los degradados suaves (el cielo) alternen
+/// entre niveles vecinos en vez de formar bandas.
+pub fn dither(img: &mut Image) {
+    for y in 0..img.h {
+        for x in 0..img.w {
+            let n = (blue_noise_at(x, y) - 0.5) / 255.0;
+            img.set(x, y, img.get(x, y) + Color::new(n, n, n));
+        }
+    }
+}
+
+fn sample_bilinear(img: &Image, fx: f64, fy: f64) -> Color {
+    let fx = fx.clamp(0.0, (img.w - 1) as f64);
+    let fy = fy.clamp(0.0, (img.h - 1) as f64);
+    let (x0, y0) = (fx.floor() as usize, fy.floor() as usize);
+    let (x1, y1) = ((x0 + 1).min(img.w - 1), (y0 + 1).min(img.h - 1));
+    let (tx, ty) = (fx - x0 as f64, fy - y0 as f64);
+    let top = img.get(x0, y0) * (1.0 - tx) + img.get(x1, y0) * tx;
+    let bottom = img.get(x0, y1) * (1.0 - tx) + img.get(x1, y1) * tx;
+    top * (1.0 - ty) + bottom * ty
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chromatic_aberration_center_and_corner() {
+        // Degradado horizontal en gris
+        let (w, h) = (31, 21);
+        let mut img = Image::new(w, h);
+        for y in 0..h {
+            for x in 0..w {
+                let v = x as f64 / (w - 1) as f64;
+                img.set(x, y, Color::new(v, v, v));
+            }
+        }
+
+        let same = chromatic_aberration(&img, 0.0);
+        assert!(same.data == img.data);
+
+        let out = chromatic_aberration(&img, 0.05);
+        let c = out.get(w / 2, h / 2);
+        assert_eq!(c, img.get(w / 2, h / 2));
+
+        // Esquina derecha: R viene de más afuera (recortado), B de más adentro
+        let corner = out.get(w - 2, 0);
+        assert!(corner.x > corner.y && corner.z < corner.y, "{:?}", corner);
+        // La esquina del borde no se sale del cuadro
+        let edge = out.get(w - 1, h - 1);
+        assert!(edge.x <= 1.0 && edge.x.is_finite());
+    }
+
+    #[test]
+    fn test_film_grain_zero_mean_and_scales() {
+        let base = Color::new(0.5, 0.5, 0.5);
+        let grain = |strength: f64| {
+            let mut img = Image::new(200, 100);
+            img.data.fill(base);
+            film_grain(&mut img, strength, 42);
+            let d: Vec<f64> = img.data.iter().map(|c| c.x - base.x).collect();
+            let mean = d.iter().sum::<f64>() / d.len() as f64;
+            let rms = (d.iter().map(|v| v * v).sum::<f64>() / d.len() as f64).sqrt();
+            (img, mean, rms)
+        };
+
+        let (off, _, _) = grain(0.0);
+        assert!(off.data.iter().all(|&c| c == base));
+
+        let (a, mean_a, rms_a) = grain(0.02);
+        let (_, mean_b, rms_b) = grain(0.04);
+        assert!(mean_a.abs() < 0.001 && mean_b.abs() < 0.002, "{} {}", mean_a, mean_b);
+        assert!((rms_b / rms_a - 2.0).abs() < 1e-6);
+        // Misma semilla, mismo grano
+        let (again, _, _) = grain(0.02);
+        assert!(a.data == again.data);
+    }
+
+    #[test]
+    fn test_dither_splits_flat_gray_into_two_levels() {
+        // 127.3 / 255: sin dither todo redondea a 127
+        let v = 127.3 / 255.0;
+        let mut img = Image::new(16, 16);
+        for y in 0..16 {
+            for x in 0..16 {
+                img.set(x, y, Color::new(v, v, v));
+            }
+        }
+        assert!(img.to_rgb24().iter().all(|&b| b == 127));
+
+        dither(&mut img);
+        let bytes = img.to_rgb24();
+        let lo = bytes.iter().filter(|&&b| b == 127).count();
+        let hi = bytes.iter().filter(|&&b| b == 128).count();
+        assert_eq!(lo + hi, bytes.len());
+        // ~30% arriba: el promedio se conserva
+        let frac = hi as f64 / bytes.len() as f64;
+        assert!((frac - 0.3).abs() < 0.05, "{}", frac);
+    }
+}
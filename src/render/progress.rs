@@ -0,0 +1,77 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+/// Contador de progreso con ETA que se reporta por stderr (stdout queda
+/// libre para quien lo use en un pipe). Se puede avanzar desde varios hilos.
+pub struct Progress {
+    label: &'static str,
+    total: usize,
+    done: AtomicUsize,
+    last_pct: AtomicUsize,
+    start: Instant,
+}
+
+impl Progress {
+    pub fn new(label: &'static str, total: usize) -> Self {
+        Self {
+            label,
+            total,
+            done: AtomicUsize::new(0),
+            last_pct: AtomicUsize::new(usize::MAX),
+            start: Instant::now(),
+        }
+    }
+
+    /// Marca una unidad más como terminada; imprime solo cuando cambia el
+    /// porcentaje para no inundar la terminal.
+    pub fn tick(&self) {
+        let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        let pct = done * 100 / self.total.max(1);
+        if self.last_pct.swap(pct, Ordering::Relaxed) == pct && done < self.total {
+            return;
+        }
+
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let eta = eta_secs(elapsed, done, self.total)
+            .map_or_else(|| "--".to_string(), format_secs);
+        eprint!(
+            "\r{} {}/{} ({:3}%)  ETA {}   ",
+            self.label, done, self.total, pct, eta
+        );
+        if done >= self.total {
+            eprintln!();
+        }
+    }
+}
+
+/// Tiempo restante estimado a ritmo constante: lo que llevó cada unidad
+/// hasta ahora por las unidades que faltan. `None` si aún no hay datos.
+pub fn eta_secs(elapsed_secs: f64, done: usize, total: usize) -> Option<f64> {
+    if done == 0 {
+        return None;
+    }
+    Some(elapsed_secs / done as f64 * total.saturating_sub(done) as f64)
+}
+
+fn format_secs(secs: f64) -> String {
+    let s = secs.round() as u64;
+    if s >= 60 {
+        format!("{}m {:02}s", s / 60, s % 60)
+    } else {
+        format!("{}s", s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eta() {
+        assert_eq!(eta_secs(10.0, 0, 20), None);
+        assert_eq!(eta_secs(10.0, 5, 20), Some(30.0));
+        assert_eq!(eta_secs(10.0, 20, 20), Some(0.0));
+        assert_eq!(format_secs(30.0), "30s");
+        assert_eq!(format_secs(125.4), "2m 05s");
+    }
+}
@@ -1,16 +1,41 @@
-use std::fs::File;
-use std::io::{Read, Seek, SeekFrom};
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fs;
+use std::ops::Range;
 use std::path::Path;
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
 use std::thread;
+use std::time::Instant;
 
-use crate::app::camera::CameraPose;
-use crate::app::daynight::DayNight;
+use crate::app::camera::{turntable_poses, CameraPose};
+use crate::app::daynight::{DayNight, PhysicalSky};
 use crate::core::image::Image;
+use crate::core::mat4::Mat4;
 use crate::core::ray::Ray;
-use crate::core::vec3::{Color, Vec3};
-use crate::scene::Scene;
-use crate::scene::voxel::Voxel;
+use crate::core::rng::{mix_seed, Rng};
+use crate::core::vec3::{luminance, Color, Vec3};
+use crate::render::bluenoise::{pixel_jitter, BLUE_NOISE_SIZE};
+use crate::render::brdf::{PbrSurface, ShadingModel};
+use crate::render::cancel::CancelToken;
+use crate::render::debug::{
+    depth_color, heat_color, normal_color, DebugMode, SKY_SENTINEL, WIRE_EDGE, WIRE_FILL,
+};
+use crate::render::denoise::{atrous, Guides};
+use crate::render::exposure::{exposure_for, smooth_exposure};
+use crate::render::frustum::{Frustum, PrimaryVisible};
+use crate::render::pattern::{pattern_offset, SamplePattern};
+use crate::render::post::{chromatic_aberration, dither, film_grain};
+use crate::render::progress::Progress;
+use crate::render::sidecar::FrameMeta;
+use crate::render::stats::{timed, FrameStats, RenderStats, TileStats};
+use crate::scene::{Material, Scene, Skybox, TextureSource, DEFAULT_SPECULAR};
+use crate::scene::instance::{points_bounds, Instance, InstanceKind};
+use crate::scene::plane::Plane;
+use crate::scene::sphere::Sphere;
+use crate::scene::mesh::Tri;
+use crate::scene::voxel::{Voxel, NO_TINT};
 
 use image; // para cargar JPG/PNG/BMP
 
@@ -25,6 +50,21 @@ fn clamp01(v: Color) -> Color {
     )
 }
 
+/// Pone a 0 los componentes NaN/Inf (normales degeneradas, divisiones por
+/// cero) y devuelve cuántos había.
+fn sanitize(buf: &mut [Color]) -> u64 {
+    let mut bad = 0;
+    for c in buf.iter_mut() {
+        for v in [&mut c.x, &mut c.y, &mut c.z] {
+            if !v.is_finite() {
+                *v = 0.0;
+                bad += 1;
+            }
+        }
+    }
+    bad
+}
+
 #[inline]
 fn tonemap_aces(c: Color) -> Color {
     let a = 2.51;
@@ -33,21 +73,37 @@ fn tonemap_aces(c: Color) -> Color {
     let d = 0.59;
     let e = 0.14;
     Color::new(
-        ((c.x * (a * c.x + b)) / (c.x * (c1 * c.x + d) + e))
-            .max(0.0)
-            .min(1.0),
-        ((c.y * (a * c.y + b)) / (c.y * (c1 * c.y + d) + e))
-            .max(0.0)
-            .min(1.0),
-        ((c.z * (a * c.z + b)) / (c.z * (c1 * c.z + d) + e))
-            .max(0.0)
-            .min(1.0),
+        ((c.x * (a * c.x + b)) / (c.x * (c1 * c.x + d) + e)).clamp(0.0, 1.0),
+        ((c.y * (a * c.y + b)) / (c.y * (c1 * c.y + d) + e)).clamp(0.0, 1.0),
+        ((c.z * (a * c.z + b)) / (c.z * (c1 * c.z + d) + e)).clamp(0.0, 1.0),
     )
 }
 
+/// Toe y shoulder por defecto de `tonemap_filmic`: ajustados para quedar
+/// cerca de ACES entre 0 y 4.
+pub const DEFAULT_FILMIC_TOE: f64 = 0.5;
+pub const DEFAULT_FILMIC_SHOULDER: f64 = 0.5;
+
+/// Curva filmic de Hable (Uncharted 2) con dos perillas en 0..1: `toe`
+/// más alto levanta las sombras (baja la fuerza del pie, D de Hable) y
+/// `shoulder` más alto sube las altas luces contra el blanco (A de Hable).
+fn tonemap_filmic(c: Color, toe: f64, shoulder: f64) -> Color {
+    let a = 0.6 * shoulder.clamp(0.01, 1.0);
+    let d = 0.8 * (1.0 - toe.clamp(0.0, 1.0));
+    let (b, c1, e, f) = (0.3, 0.1, 0.02, 0.3);
+    let hable = |x: f64| ((x * (a * x + c1 * b) + d * e) / (x * (a * x + b) + d * f)) - e / f;
+    // exposición de 3 y blanco en 11.2, como en el original
+    let white = hable(11.2);
+    let map = |x: f64| (hable(3.0 * x.max(0.0)) / white).clamp(0.0, 1.0);
+    Color::new(map(c.x), map(c.y), map(c.z))
+}
+
+const DEFAULT_GAMMA: f64 = 2.2;
+
+/// Codificación gamma: c^(1/gamma).
 #[inline]
-fn gamma22(c: Color) -> Color {
-    let g = 1.0 / 2.2;
+fn encode_gamma(c: Color, gamma: f64) -> Color {
+    let g = 1.0 / gamma;
     Color::new(c.x.powf(g), c.y.powf(g), c.z.powf(g))
 }
 
@@ -58,15 +114,38 @@ fn hadamard(a: Color, b: Color) -> Color {
 
 /* ====================== Sol / muestreo ====================== */
 
-fn sun_sample_dir(sun_dir: Vec3, i: u32) -> Vec3 {
-    let n = sun_dir.normalized();
+/// Base ortonormal (t, b) perpendicular a `n` (unitario).
+fn tangent_frame(n: Vec3) -> (Vec3, Vec3) {
     let up = if n.y.abs() < 0.9 {
         Vec3::new(0.0, 1.0, 0.0)
     } else {
         Vec3::new(1.0, 0.0, 0.0)
     };
     let t = up.cross(n).normalized();
-    let b = n.cross(t);
+    (t, n.cross(t))
+}
+
+/// Muestras por punto para la sombra del sol.
+const SUN_SAMPLES: u32 = 4;
+/// Apertura angular de las muestras del sol.
+const SUN_SPREAD: f64 = 0.008;
+/// Modo referencia: muestras del sol (al azar en el disco) y rebotes de GI
+/// si no había GI.
+const REFERENCE_SUN_SAMPLES: u32 = 32;
+
+/// Piso de luz por defecto, como fracción del nivel de ambiente.
+const DEFAULT_MIN_LIGHT: f64 = 0.3;
+const REFERENCE_GI_DEPTH: u32 = 16;
+
+/// Contact hardening: distancia al oclusor a la que la apertura vale
+/// `SUN_SPREAD`, y límites del factor de escala.
+const CONTACT_REF_DIST: f64 = 2.0;
+const CONTACT_MIN_SCALE: f64 = 0.05;
+const CONTACT_MAX_SCALE: f64 = 4.0;
+
+fn sun_sample_dir(sun_dir: Vec3, i: u32, spread: f64) -> Vec3 {
+    let n = sun_dir.normalized();
+    let (t, b) = tangent_frame(n);
 
     let pts = [
         (0.0, 0.0),
@@ -78,41 +157,276 @@ fn sun_sample_dir(sun_dir: Vec3, i: u32) -> Vec3 {
         (-0.6, -0.3),
     ];
     let (ux, uy) = pts[(i as usize) % pts.len()];
-    let spread = 0.008;
     (n + t * (ux * spread) + b * (uy * spread)).normalized()
 }
 
-/* ====================== AO simplificado ====================== */
+/// Dirección al azar uniforme en el disco de radio `spread` alrededor del
+/// sol (mismo disco que cubre `sun_sample_dir`).
+fn sun_random_dir(sun_dir: Vec3, spread: f64, rng: &mut Rng) -> Vec3 {
+    let n = sun_dir.normalized();
+    let (t, b) = tangent_frame(n);
+    let r = rng.next_f64().sqrt() * 0.6;
+    let phi = std::f64::consts::TAU * rng.next_f64();
+    (n + t * (r * phi.cos() * spread) + b * (r * phi.sin() * spread)).normalized()
+}
 
-fn occlusion_ray_hit(ray: &Ray, voxels: &[Voxel], max_t: f64) -> bool {
-    for v in voxels {
-        if let Some((t0, _t1)) = ray_box_intersect(ray, v.min, v.max, max_t) {
-            if t0 > ray.tmin && t0 < max_t {
-                return true;
-            }
-        }
+/// Dirección en el hemisferio de `n` con densidad proporcional al coseno
+/// (Malley: disco uniforme proyectado) y su pdf = cos / pi. Para un BRDF
+/// difuso el peso f * cos / pdf queda igual al albedo.
+fn sample_cosine_hemisphere(n: Vec3, rng: &mut Rng) -> (Vec3, f64) {
+    let r = rng.next_f64().sqrt();
+    let phi = 2.0 * std::f64::consts::PI * rng.next_f64();
+    let z = (1.0 - r * r).max(0.0).sqrt();
+    let (t, b) = tangent_frame(n);
+    let d = t * (r * phi.cos()) + b * (r * phi.sin()) + n * z;
+    (d.normalized(), z / std::f64::consts::PI)
+}
+
+/// Qué tan concentrado queda el realce de la cáustica.
+const CAUSTIC_SHARPNESS: f64 = 64.0;
+
+/// Refracción de Snell para `d` entrando por una superficie de normal `n`
+/// (del lado de `d`), con eta = n1 / n2. None en reflexión total interna.
+fn refract(d: Vec3, n: Vec3, eta: f64) -> Option<Vec3> {
+    let cos_i = -d.dot(n);
+    let k = 1.0 - eta * eta * (1.0 - cos_i * cos_i);
+    if k < 0.0 {
+        return None;
+    }
+    Some((d * eta + n * (eta * cos_i - k.sqrt())).normalized())
+}
+
+/// Tope del escalado por `specular` en `blinn_phong`: con el
+/// `spec_strength` por defecto el pico queda en 0.6 y no quema a blanco
+/// (`Ks 1.0` de un MTL sería 25 veces el brillo de siempre).
+const SPEC_MAX_SCALE: f64 = 4.0;
+
+/// Brillo Blinn-Phong del material: nh^shininess * spec_strength, escalado
+/// por `specular` respecto del valor por defecto (hasta `SPEC_MAX_SCALE`).
+fn blinn_phong(nrm: Vec3, view: Vec3, light: Vec3, mat: &Material) -> f64 {
+    let half_vec = (view + light).normalized();
+    let nh = nrm.dot(half_vec).max(0.0);
+    let strength = mat.spec_strength * (mat.specular / DEFAULT_SPECULAR).min(SPEC_MAX_SCALE);
+    nh.powf(mat.shininess) * strength
+}
+
+/// Intensidad del sol desde la que el brillo especular va completo; por
+/// debajo se apaga en proporción (antes era un corte seco en 0.3).
+const SPEC_FULL_SUN: f64 = 0.3;
+
+/// Reflectancia de Fresnel (Schlick) para el coseno de incidencia dado.
+fn fresnel_schlick(cos_i: f64, ior: f64) -> f64 {
+    let f0 = ((1.0 - ior) / (1.0 + ior)).powi(2);
+    f0 + (1.0 - f0) * (1.0 - cos_i.clamp(0.0, 1.0)).powi(5)
+}
+
+/// Rayo reflejado por el plano de una cara superior (normal +Y); None si
+/// la cara no mira hacia arriba o el rayo no llega desde arriba.
+fn planar_reflection_ray(ray: &Ray, hit: &HitInfo) -> Option<Ray> {
+    let n = hit.n.normalized();
+    if n.y < 0.99 || ray.d.dot(n) >= 0.0 {
+        return None;
+    }
+    let d = ray.d - n * (2.0 * ray.d.dot(n));
+    Some(Ray::new(hit.p + n * 1e-4, d))
+}
+
+/// Diferencia de ior con el aire por debajo de la cual un material
+/// transparente se mezcla con lo de atrás en vez de refractar.
+const BLEND_IOR_EPS: f64 = 0.05;
+
+/// Semiángulo del cono de reflejo con rugosidad 1 (crece con roughness²).
+const GLOSS_MAX_ANGLE: f64 = std::f64::consts::FRAC_PI_4;
+
+/// Reflejo borroso: el rayo espejo `r` desviado al azar dentro de un cono
+/// que abre con la rugosidad; con 0 queda intacto. Promediando las muestras
+/// del píxel se ve el reflejo difuminado. Se mantiene hacia arriba, como el
+/// de `planar_reflection_ray`.
+fn glossy_reflection(r: Ray, roughness: f64, rng: &mut Rng) -> Ray {
+    let rough = roughness.clamp(0.0, 1.0);
+    if rough <= 0.0 {
+        return r;
     }
-    false
+    // uniforme en el ángulo sólido del cono
+    let cos_max = (rough * rough * GLOSS_MAX_ANGLE).cos();
+    let cos_t = 1.0 - rng.next_f64() * (1.0 - cos_max);
+    let sin_t = (1.0 - cos_t * cos_t).max(0.0).sqrt();
+    let phi = 2.0 * std::f64::consts::PI * rng.next_f64();
+    let (t, b) = tangent_frame(r.d);
+    let mut d = r.d * cos_t + t * (sin_t * phi.cos()) + b * (sin_t * phi.sin());
+    if d.y <= 0.0 {
+        d.y = -d.y;
+    }
+    Ray::new(r.o, d)
+}
+
+/// Normal perturbada por olas suaves que se mueven con el tiempo.
+fn wave_normal(n: Vec3, p: Vec3, time: f64) -> Vec3 {
+    let dx = (p.x * 3.0 + time * 1.5).sin() * 0.12;
+    let dz = (p.z * 2.5 - time * 1.1).cos() * 0.12;
+    let (t, b) = tangent_frame(n);
+    (n + t * dx + b * dz).normalized()
 }
 
-fn unoccluded_ray(ray: &Ray, voxels: &[Voxel], max_t: f64) -> bool {
-    !occlusion_ray_hit(ray, voxels, max_t)
+/* ====================== Any-hit ====================== */
+
+/// Recorrido de oclusión (rayos de sombra, AO y bent normals): corta en el
+/// primer bloqueador dentro de `(ray.tmin, tmax)` sin calcular normal, UV
+/// ni material. Entran voxels, triángulos, esferas, planos e instancias,
+/// así una malla apoyada en el piso oscurece el contacto. La inversa de la
+/// dirección se calcula una vez por rayo y no por voxel; solo los materiales
+/// con recorte por alfa pagan el muestreo de textura.
+fn blocked_along(ray: &Ray, ctx: &TraceCtx, tmax: f64) -> bool {
+    if voxels_block(ray, ctx.voxels, ctx.voxel_soa, ctx, tmax) {
+        return true;
+    }
+    if triangles_block(ray, &ctx.scene.triangles, ctx, tmax) {
+        return true;
+    }
+    if spheres_block(ray, &ctx.scene.spheres, ctx, tmax) {
+        return true;
+    }
+    if planes_block(ray, &ctx.scene.planes, ctx, tmax) {
+        return true;
+    }
+    ctx.scene.instances.iter().any(|inst| {
+        let (local, s) = to_local(ray, inst);
+        match &inst.base {
+            InstanceKind::Voxels(v) => voxels_block(&local, v, None, ctx, tmax * s),
+            InstanceKind::Mesh(t) => triangles_block(&local, t, ctx, tmax * s),
+        }
+    })
 }
 
-fn blocked_along(ray: &Ray, voxels: &[Voxel], tmax: f64) -> bool {
-    let mut shadow = *ray;
-    shadow.tmax = tmax;
-    for v in voxels {
-        if let Some((t0, _t1)) = ray_box_intersect(&shadow, v.min, v.max, tmax) {
-            if t0 > shadow.tmin && t0 < shadow.tmax {
-                return true;
+/// Con `soa` (las mismas cajas que `voxels`, ver `VoxelSoa`) la prueba de
+/// cajas lee de ahí; `voxels` solo se toca en los candidatos.
+fn voxels_block(
+    ray: &Ray,
+    voxels: &[Voxel],
+    soa: Option<&VoxelSoa>,
+    ctx: &TraceCtx,
+    tmax: f64,
+) -> bool {
+    let inv_d = inv_dir(ray.d);
+    let mats = &ctx.scene.materials;
+
+    let voxel_blocks = |i: usize| {
+        let (min, max, mat_id) = match soa {
+            Some(s) => s.get(i),
+            None => (voxels[i].min, voxels[i].max, voxels[i].mat_id),
+        };
+        match slab_entry(ray, inv_d, min, max) {
+            Some((t0, t1)) if t0 > ray.tmin && t0 < tmax => {
+                mats[mat_id].alpha_cutout <= 0.0
+                    || voxel_hit(ray, i, &voxels[i], t0, t1, ctx).is_some_and(|h| h.t < tmax)
             }
+            _ => false,
+        }
+    };
+    let blocker = (0..voxels.len()).position(voxel_blocks);
+    ctx.count_tests(blocker.map_or(voxels.len(), |i| i + 1));
+    blocker.is_some()
+}
+
+fn triangles_block(ray: &Ray, tris: &[Tri], ctx: &TraceCtx, tmax: f64) -> bool {
+    let blocker = tris.iter().position(|tri| {
+        ray_triangle_intersect(ray, tri, tmax)
+            .is_some_and(|(_t, b1, b2)| !cut_out(tri.mat_id, b1, b2, ctx))
+    });
+    ctx.count_tests(blocker.map_or(tris.len(), |i| i + 1));
+    blocker.is_some()
+}
+
+fn spheres_block(ray: &Ray, spheres: &[Sphere], ctx: &TraceCtx, tmax: f64) -> bool {
+    let blocker = spheres.iter().position(|s| {
+        ray_sphere_intersect(ray, s, tmax).is_some_and(|t| {
+            let (u, v) = Sphere::uv(s.normal_at(ray.at(t)));
+            !cut_out(s.mat_id, u, v, ctx)
+        })
+    });
+    ctx.count_tests(blocker.map_or(spheres.len(), |i| i + 1));
+    blocker.is_some()
+}
+
+fn planes_block(ray: &Ray, planes: &[Plane], ctx: &TraceCtx, tmax: f64) -> bool {
+    let blocker = planes.iter().position(|pl| {
+        ray_plane_intersect(ray, pl, tmax).is_some_and(|t| {
+            let (u, v) = Plane::uv(ray.at(t));
+            !cut_out(pl.mat_id, u, v, ctx)
+        })
+    });
+    ctx.count_tests(blocker.map_or(planes.len(), |i| i + 1));
+    blocker.is_some()
+}
+
+/// El rayo en el espacio local de la instancia, con la dirección
+/// normalizada, y el factor `s` de las distancias: t_local = t_mundo * s.
+fn to_local(ray: &Ray, inst: &Instance) -> (Ray, f64) {
+    let inv = inst.inverse();
+    let d = inv.transform_vector(ray.d);
+    let s = d.length();
+    let mut local = Ray::new(inv.transform_point(ray.o), d);
+    local.tmin = ray.tmin * s;
+    local.tmax = ray.tmax * s;
+    (local, s)
+}
+
+/// Entrada/salida del rayo en la caja con la inversa ya calculada.
+#[inline]
+fn slab_entry(ray: &Ray, inv_d: Vec3, min: Vec3, max: Vec3) -> Option<(f64, f64)> {
+    let (x0, x1) = ((min.x - ray.o.x) * inv_d.x, (max.x - ray.o.x) * inv_d.x);
+    let (y0, y1) = ((min.y - ray.o.y) * inv_d.y, (max.y - ray.o.y) * inv_d.y);
+    let (z0, z1) = ((min.z - ray.o.z) * inv_d.z, (max.z - ray.o.z) * inv_d.z);
+    let t0 = x0.min(x1).max(y0.min(y1)).max(z0.min(z1));
+    let t1 = x0.max(x1).min(y0.max(y1)).min(z0.max(z1));
+    (t0 <= t1).then_some((t0, t1))
+}
+
+/// Máximo de capas transparentes que cruza un rayo de sombra antes de
+/// darlo por bloqueado.
+const MAX_SHADOW_LAYERS: usize = 4;
+
+/// Transmitancia RGB de un rayo de sombra hasta `tmax`: blanco si llega libre,
+/// negro si lo corta algo opaco; cada capa transparente (vidrio, agua) que
+/// cruza la tiñe con albedo * transparency.
+fn shadow_transmittance(ray: &Ray, ctx: &TraceCtx, tmax: f64) -> Color {
+    let black = Color::new(0.0, 0.0, 0.0);
+    let mut trans = Color::new(1.0, 1.0, 1.0);
+    if !blocked_along(ray, ctx, tmax) {
+        return trans;
+    }
+
+    let mut r = *ray;
+    let mut remaining = tmax;
+    for _ in 0..MAX_SHADOW_LAYERS {
+        r.tmax = remaining;
+        // Las sombras son siempre de dos caras
+        let Some(hit) = trace_closest(&r, ctx, false) else {
+            return trans;
+        };
+        let mat = &ctx.scene.materials[hit.mat_id];
+        if mat.transparency <= 0.0 {
+            return black;
+        }
+        trans = hadamard(trans, clamp01(mat.albedo) * mat.transparency.min(1.0));
+
+        // Seguir desde el otro lado: con el origen dentro del voxel,
+        // ray_box_intersect ya no lo vuelve a reportar.
+        let step = hit.t + 1e-4;
+        r.o = r.at(step);
+        remaining -= step;
+        if remaining <= 0.0 {
+            return trans;
         }
     }
-    false
+    black
 }
 
-fn bent_normal(p: Vec3, n: Vec3, voxels: &[Voxel]) -> Vec3 {
+/* ====================== AO simplificado ====================== */
+
+// Sin uso por ahora; se conserva para el ambiente direccional.
+#[allow(dead_code)]
+fn bent_normal(p: Vec3, n: Vec3, ctx: &TraceCtx) -> Vec3 {
     let eps = 1e-3;
     let samples = [
         Vec3::new(1.0, 1.0, 0.0),
@@ -127,7 +441,7 @@ fn bent_normal(p: Vec3, n: Vec3, voxels: &[Voxel]) -> Vec3 {
     for s in samples.iter() {
         let dir = (*s).normalized();
         let r = Ray::new(p + n * eps, dir);
-        if unoccluded_ray(&r, voxels, 1.0e6) {
+        if !blocked_along(&r, ctx, 1.0e6) {
             b = b + dir;
             cnt += 1.0;
         }
@@ -140,22 +454,27 @@ fn bent_normal(p: Vec3, n: Vec3, voxels: &[Voxel]) -> Vec3 {
     }
 }
 
-fn ao_term(p: Vec3, n: Vec3, voxels: &[Voxel]) -> f64 {
+const AO_DIRS: [Vec3; 5] = [
+    Vec3::new(0.0, 1.0, 0.0),
+    Vec3::new(0.5, 1.0, 0.0),
+    Vec3::new(-0.5, 1.0, 0.0),
+    Vec3::new(0.0, 1.0, 0.5),
+    Vec3::new(0.0, 1.0, -0.5),
+];
+
+/// Radio de AO por defecto: un voxel.
+const DEFAULT_AO_RADIUS: f64 = 1.0;
+
+/// Oclusión ambiente con los oclusores a menos de `radius`.
+fn ao_term(p: Vec3, n: Vec3, ctx: &TraceCtx, radius: f64) -> f64 {
     let mut occ: f64 = 0.0;
     let eps: f64 = 1e-3;
-
-    let dirs = [
-        Vec3::new(0.0, 1.0, 0.0),
-        Vec3::new(0.5, 1.0, 0.0),
-        Vec3::new(-0.5, 1.0, 0.0),
-        Vec3::new(0.0, 1.0, 0.5),
-        Vec3::new(0.0, 1.0, -0.5),
-    ];
+    let dirs = AO_DIRS;
 
     for d in dirs.iter() {
         let dir = (*d).normalized();
         let r = Ray::new(p + n * eps, dir);
-        if occlusion_ray_hit(&r, voxels, 1.0) {
+        if blocked_along(&r, ctx, radius) {
             occ += 1.0;
         }
     }
@@ -164,6 +483,18 @@ fn ao_term(p: Vec3, n: Vec3, voxels: &[Voxel]) -> f64 {
     (1.0 - 0.35 * occ_norm).clamp(0.4, 1.0)
 }
 
+/// Fracción de `samples` rayos (coseno sobre `n`) que suben hasta el cielo
+/// sin chocar con nada.
+fn sky_visibility(p: Vec3, n: Vec3, ctx: &TraceCtx, samples: usize, rng: &mut Rng) -> f64 {
+    let open = (0..samples)
+        .filter(|_| {
+            let (d, _) = sample_cosine_hemisphere(n, rng);
+            d.y > 0.0 && !blocked_along(&Ray::new(p + n * 1e-3, d), ctx, 1e6)
+        })
+        .count();
+    open as f64 / samples as f64
+}
+
 /* ====================== Intersección AABB ====================== */
 
 fn safe_inv(x: f64) -> f64 {
@@ -174,10 +505,27 @@ fn safe_inv(x: f64) -> f64 {
     }
 }
 
+/// Inversa de la dirección para las pruebas de slab; se calcula una vez por
+/// rayo y se reusa en todas sus cajas.
+#[inline]
+fn inv_dir(d: Vec3) -> Vec3 {
+    Vec3::new(safe_inv(d.x), safe_inv(d.y), safe_inv(d.z))
+}
+
 fn ray_box_intersect(ray: &Ray, min: Vec3, max: Vec3, max_t: f64) -> Option<(f64, f64)> {
-    let inv_dx = safe_inv(ray.d.x);
-    let inv_dy = safe_inv(ray.d.y);
-    let inv_dz = safe_inv(ray.d.z);
+    ray_box_intersect_inv(ray, inv_dir(ray.d), min, max, max_t)
+}
+
+/// `ray_box_intersect` con `inv_d = inv_dir(ray.d)` ya calculada.
+#[inline]
+fn ray_box_intersect_inv(
+    ray: &Ray,
+    inv_d: Vec3,
+    min: Vec3,
+    max: Vec3,
+    max_t: f64,
+) -> Option<(f64, f64)> {
+    let (inv_dx, inv_dy, inv_dz) = (inv_d.x, inv_d.y, inv_d.z);
 
     let mut tmin = (min.x - ray.o.x) * inv_dx;
     let mut tmax = (max.x - ray.o.x) * inv_dx;
@@ -226,20 +574,547 @@ fn ray_box_intersect(ray: &Ray, min: Vec3, max: Vec3, max_t: f64) -> Option<(f64
     }
 }
 
+/* ==================== Intersección triángulo ==================== */
+
+/// Prueba estanca de Woop, Benthin y Wald (2013). Devuelve (t, b1, b2) con
+/// las baricéntricas de v1 y v2.
+///
+/// Se pasa a un espacio donde el rayo va por +z desde el origen y se evalúan
+/// las tres aristas en 2D. Dos triángulos que comparten una arista la
+/// calculan con los mismos productos y signo opuesto, así que un rayo que
+/// cae justo sobre ella da en al menos uno (Möller–Trumbore puede fallar en
+/// ambos y dejar agujeros en la silueta de una malla cerrada). Los bordes
+/// cuentan como dentro; si dan los dos, gana el primero en la lista.
+fn ray_triangle_intersect(ray: &Ray, tri: &Tri, max_t: f64) -> Option<(f64, f64, f64)> {
+    let axis = |v: Vec3, i: usize| match i {
+        0 => v.x,
+        1 => v.y,
+        _ => v.z,
+    };
+    let d = ray.d;
+    let kz = if d.x.abs() > d.y.abs() {
+        if d.x.abs() > d.z.abs() { 0 } else { 2 }
+    } else if d.y.abs() > d.z.abs() {
+        1
+    } else {
+        2
+    };
+    let (mut kx, mut ky) = ((kz + 1) % 3, (kz + 2) % 3);
+    // conserva el sentido de giro de los vértices
+    if axis(d, kz) < 0.0 {
+        std::mem::swap(&mut kx, &mut ky);
+    }
+    let dz = axis(d, kz);
+    let (sx, sy, sz) = (axis(d, kx) / dz, axis(d, ky) / dz, 1.0 / dz);
+
+    // vértices relativos al origen, con el corte que alinea el rayo a +z
+    let shear = |v: Vec3| {
+        let a = v - ray.o;
+        let z = axis(a, kz);
+        (axis(a, kx) - sx * z, axis(a, ky) - sy * z, sz * z)
+    };
+    let (ax, ay, az) = shear(tri.v0);
+    let (bx, by, bz) = shear(tri.v1);
+    let (cx, cy, cz) = shear(tri.v2);
+
+    let u = cx * by - cy * bx;
+    let v = ax * cy - ay * cx;
+    let w = bx * ay - by * ax;
+    if (u < 0.0 || v < 0.0 || w < 0.0) && (u > 0.0 || v > 0.0 || w > 0.0) {
+        return None;
+    }
+    let det = u + v + w;
+    if det == 0.0 {
+        return None;
+    }
+
+    let t = (u * az + v * bz + w * cz) / det;
+    if t <= ray.tmin || t >= max_t {
+        None
+    } else {
+        Some((t, v / det, w / det))
+    }
+}
+
+/// Primera intersección del rayo con la esfera dentro de `(tmin, max_t)`:
+/// la cara de entrada, o la de salida si el origen está dentro. Usa
+/// b' = d·(o - c) (la mitad de b) para perder menos precisión.
+fn ray_sphere_intersect(ray: &Ray, s: &Sphere, max_t: f64) -> Option<f64> {
+    let oc = ray.o - s.center;
+    let a = ray.d.dot(ray.d);
+    let half_b = oc.dot(ray.d);
+    let c = oc.dot(oc) - s.radius * s.radius;
+    let disc = half_b * half_b - a * c;
+    if disc < 0.0 {
+        return None;
+    }
+    let sq = disc.sqrt();
+    [(-half_b - sq) / a, (-half_b + sq) / a]
+        .into_iter()
+        .find(|&t| t > ray.tmin && t < max_t)
+}
+
+/// Intersección con el plano dentro de `(tmin, max_t)`, de ambos lados.
+/// None si el rayo va paralelo.
+fn ray_plane_intersect(ray: &Ray, pl: &Plane, max_t: f64) -> Option<f64> {
+    let dn = ray.d.dot(pl.normal);
+    if dn.abs() < 1e-12 {
+        return None;
+    }
+    let t = (pl.point - ray.o).dot(pl.normal) / dn;
+    (t > ray.tmin && t < max_t).then_some(t)
+}
+
 /* ====================== Renderer ====================== */
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 struct Light {
     pos: Vec3,
     color: Color,
     intensity: f64,
+    flicker_amp: f64,
+    flicker_freq: f64,
+    /// Fase propia (y un ±25% de frecuencia) sembrada con la posición, para
+    /// que dos antorchas no parpadeen al unísono.
+    flicker_phase: f64,
+}
+
+impl Light {
+    fn new(pos: Vec3, color: Color, mat: &Material) -> Self {
+        let seed = mix_seed(mix_seed(pos.x.to_bits(), pos.y.to_bits()), pos.z.to_bits());
+        let mut rng = Rng::new(seed);
+        let phase = rng.next_f64() * 2.0 * std::f64::consts::PI;
+        let detune = 0.75 + 0.5 * rng.next_f64();
+        Self {
+            pos,
+            color,
+            intensity: 1.0,
+            flicker_amp: mat.flicker_amp.clamp(0.0, 1.0),
+            flicker_freq: mat.flicker_freq * detune,
+            flicker_phase: phase,
+        }
+    }
+
+    /// Factor de intensidad en `time`: 1 - amp de base, más amp de ruido
+    /// (con amp 0.2 es el 0.8 + 0.2 * sin * cos de antes).
+    fn flicker(&self, time: f64) -> f64 {
+        if self.flicker_amp <= 0.0 {
+            return 1.0;
+        }
+        let phase = time * self.flicker_freq + self.flicker_phase;
+        let wave = phase.sin() * (phase * 1.3).cos();
+        ((1.0 - self.flicker_amp) + self.flicker_amp * wave).max(0.0)
+    }
+}
+
+/// Una luz puntual por cada voxel o triángulo emisivo (en su centro).
+fn gather_lights(scene: &Scene) -> Vec<Light> {
+    let emissive = |mat_id: usize| {
+        let mat = &scene.materials[mat_id];
+        let e = mat.emission();
+        (e.x > 0.0 || e.y > 0.0 || e.z > 0.0).then_some((e, mat))
+    };
+
+    let mut lights = Vec::new();
+    for v in &scene.voxels {
+        if let Some((e, mat)) = emissive(v.mat_id) {
+            lights.push(Light::new((v.min + v.max) * 0.5, e, mat));
+        }
+    }
+    for tri in &scene.triangles {
+        if let Some((e, mat)) = emissive(tri.mat_id) {
+            lights.push(Light::new((tri.v0 + tri.v1 + tri.v2) / 3.0, e, mat));
+        }
+    }
+    // Orden por posición, no por el de los voxels: reordenar la escena no
+    // cambia la lista (ni el orden en que se suman las luces)
+    let lex = |u: Vec3, v: Vec3| {
+        u.x.total_cmp(&v.x).then(u.y.total_cmp(&v.y)).then(u.z.total_cmp(&v.z))
+    };
+    lights.sort_by(|a, b| lex(a.pos, b.pos).then(lex(a.color, b.color)));
+    lights
+}
+
+/// Alcance de las luces emisivas: más lejos la atenuación ya es cero.
+const LIGHT_RANGE: f64 = 10.0;
+
+/// Rejilla de luces con celdas del tamaño del alcance: una luz que llega a
+/// `p` está en la celda de `p` o en una vecina.
+#[derive(Clone, Default)]
+struct LightGrid {
+    /// Por celda, las luces de ella y de sus 26 vecinas (en orden): las
+    /// candidatas para cualquier punto dentro
+    cells: HashMap<(i64, i64, i64), Vec<usize>>,
+    /// Posiciones, por índice en la lista de luces
+    pos: Vec<Vec3>,
+}
+
+impl LightGrid {
+    fn new(lights: &[Light]) -> Self {
+        let mut cells: HashMap<_, Vec<usize>> = HashMap::new();
+        for (i, l) in lights.iter().enumerate() {
+            let (cx, cy, cz) = Self::cell(l.pos);
+            for dz in -1..=1 {
+                for dy in -1..=1 {
+                    for dx in -1..=1 {
+                        // i crece, así que cada lista queda ordenada
+                        cells.entry((cx + dx, cy + dy, cz + dz)).or_default().push(i);
+                    }
+                }
+            }
+        }
+        Self { cells, pos: lights.iter().map(|l| l.pos).collect() }
+    }
+
+    fn cell(p: Vec3) -> (i64, i64, i64) {
+        let c = |v: f64| (v / LIGHT_RANGE).floor() as i64;
+        (c(p.x), c(p.y), c(p.z))
+    }
+
+    /// Índices (en orden, el mismo de suma que recorriendo la lista
+    /// entera) de las luces a menos de `LIGHT_RANGE` de `p`. No reserva
+    /// memoria: se llama en cada impacto.
+    fn near(&self, p: Vec3) -> impl Iterator<Item = usize> + '_ {
+        let ids = self.cells.get(&Self::cell(p)).map_or(&[][..], |v| &v[..]);
+        ids.iter().copied().filter(move |&i| (self.pos[i] - p).length() < LIGHT_RANGE)
+    }
 }
 
 #[derive(Clone)]
 struct Tex {
     w: usize,
     h: usize,
-    data: Vec<u8>, // RGB
+    data: Vec<u8>, // RGBA
+}
+
+/// Voxels de la escena como estructura de arreglos: el bucle que prueba
+/// todas las cajas recorre coordenadas contiguas en vez de saltar entre
+/// `Voxel`s con tint y material en medio. Solo guarda lo que mira ese
+/// bucle; el impacto en sí sale del `Voxel`.
+#[derive(Clone, Default)]
+struct VoxelSoa {
+    mins_x: Vec<f64>,
+    mins_y: Vec<f64>,
+    mins_z: Vec<f64>,
+    maxs_x: Vec<f64>,
+    maxs_y: Vec<f64>,
+    maxs_z: Vec<f64>,
+    mat_ids: Vec<usize>,
+}
+
+impl VoxelSoa {
+    fn new(voxels: &[Voxel]) -> Self {
+        Self {
+            mins_x: voxels.iter().map(|v| v.min.x).collect(),
+            mins_y: voxels.iter().map(|v| v.min.y).collect(),
+            mins_z: voxels.iter().map(|v| v.min.z).collect(),
+            maxs_x: voxels.iter().map(|v| v.max.x).collect(),
+            maxs_y: voxels.iter().map(|v| v.max.y).collect(),
+            maxs_z: voxels.iter().map(|v| v.max.z).collect(),
+            mat_ids: voxels.iter().map(|v| v.mat_id).collect(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.mat_ids.len()
+    }
+
+    /// Reemplaza la caja `i` (voxels animados).
+    fn set(&mut self, i: usize, v: &Voxel) {
+        (self.mins_x[i], self.mins_y[i], self.mins_z[i]) = (v.min.x, v.min.y, v.min.z);
+        (self.maxs_x[i], self.maxs_y[i], self.maxs_z[i]) = (v.max.x, v.max.y, v.max.z);
+        self.mat_ids[i] = v.mat_id;
+    }
+
+    #[inline]
+    fn bounds(&self, i: usize) -> (Vec3, Vec3) {
+        (
+            Vec3::new(self.mins_x[i], self.mins_y[i], self.mins_z[i]),
+            Vec3::new(self.maxs_x[i], self.maxs_y[i], self.maxs_z[i]),
+        )
+    }
+
+    #[inline]
+    fn get(&self, i: usize) -> (Vec3, Vec3, usize) {
+        let (min, max) = self.bounds(i);
+        (min, max, self.mat_ids[i])
+    }
+}
+
+/// Lo que necesitan las consultas de rayos: geometría, materiales y texturas
+/// (el recorte por alfa depende de la textura muestreada en el impacto).
+#[derive(Clone, Copy)]
+struct TraceCtx<'a> {
+    scene: &'a Scene,
+    /// `scene.voxels` en su posición de este instante (con animaciones, una
+    /// copia con los animados movidos)
+    voxels: &'a [Voxel],
+    tex_cache: &'a [Option<Tex>],
+    time: f64,
+    /// Contador de pruebas caja/triángulo (solo para `DebugMode::RayHeat`)
+    isect_tests: Option<&'a Cell<u64>>,
+    /// Cajas de `voxels` en SoA (None = leer los voxels directo)
+    voxel_soa: Option<&'a VoxelSoa>,
+}
+
+impl<'a> TraceCtx<'a> {
+    fn new(scene: &'a Scene, tex_cache: &'a [Option<Tex>], time: f64) -> Self {
+        Self { scene, voxels: &scene.voxels, tex_cache, time, isect_tests: None, voxel_soa: None }
+    }
+
+    #[inline]
+    fn count_tests(&self, n: usize) {
+        if let Some(c) = self.isect_tests {
+            c.set(c.get() + n as u64);
+        }
+    }
+}
+
+/// Iluminación global por path tracing difuso. Con `max_depth == 0` se usa
+/// el ambiente hemisférico de siempre.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct GiSettings {
+    /// Tope duro de rebotes (red de seguridad; lo normal es que corte la
+    /// ruleta rusa antes)
+    max_depth: u32,
+    /// Rebotes garantizados antes de que empiece la ruleta rusa
+    min_bounces: u32,
+}
+
+impl Default for GiSettings {
+    fn default() -> Self {
+        Self { max_depth: 0, min_bounces: 3 }
+    }
+}
+
+/// Qué muestras toma cada píxel en una pasada de tiles.
+#[derive(Clone, Copy)]
+enum SamplePlan<'a> {
+    /// Las `spp` muestras
+    All,
+    /// Solo la primera (pasada inicial del muestreo adaptativo)
+    First,
+    /// Las `spp - 1` restantes, solo en los píxeles marcados
+    Refine(&'a [bool]),
+    /// `n` muestras más en los píxeles marcados (antialias de siluetas)
+    Extra(&'a [bool], usize),
+}
+
+/// Píxeles que merecen más muestras: la varianza de luminancia en su
+/// vecindad 3x3 supera `threshold`.
+fn adaptive_mask(lum: &[f64], w: usize, h: usize, threshold: f64) -> Vec<bool> {
+    let mut mask = vec![false; w * h];
+    for y in 0..h {
+        for x in 0..w {
+            let (mut sum, mut sum2, mut n) = (0.0, 0.0, 0.0);
+            for ny in y.saturating_sub(1)..(y + 2).min(h) {
+                for nx in x.saturating_sub(1)..(x + 2).min(w) {
+                    let l = lum[ny * w + nx];
+                    sum += l;
+                    sum2 += l * l;
+                    n += 1.0;
+                }
+            }
+            let mean = sum / n;
+            mask[y * w + x] = sum2 / n - mean * mean > threshold;
+        }
+    }
+    mask
+}
+
+/// Recorte (near, far) por defecto de los rayos de cámara.
+const DEFAULT_CLIP: (f64, f64) = (0.001, 1e6);
+
+/// Tiempo de `render_turntable`: sol en lo alto del ciclo de `DayNight`.
+const TURNTABLE_TIME: f64 = 35.0;
+
+/// Saltos entre portales por rayo de cámara (un portal frente a otro
+/// muestra un túnel de esta profundidad). 0 por defecto: una escena con
+/// portales se ve como antes hasta que se activan con
+/// `set_max_portal_jumps`.
+const DEFAULT_PORTAL_JUMPS: usize = 0;
+
+/// Radios angulares (rad) por defecto del disco del sol y de su halo en el
+/// cielo procedural.
+const SUN_DISK_RADIUS: f64 = 0.008;
+const SUN_GLOW_RADIUS: f64 = 0.10;
+/// Tinte del disco con el sol en el horizonte; se desvanece al subir.
+const SUN_LOW_TINT: Color = Color::new(1.0, 0.55, 0.3);
+/// Elevación (y de la dirección) a partir de la cual el disco ya no se
+/// enrojece ni se agranda.
+const SUN_LOW_ELEV: f64 = 0.3;
+
+/// Lo que el cielo procedural necesita por frame, calculado una vez: los
+/// fallos de rayo solo interpolan.
+#[derive(Clone, Copy)]
+struct SkyCache {
+    horizon: Color,
+    zenith: Color,
+    /// Radios del disco y del halo ya ajustados a la elevación del sol
+    disk_radius: f64,
+    glow_radius: f64,
+    /// Color del sol en el cielo: `sun_color`, más rojo cerca del horizonte
+    disk_color: Color,
+    /// Coseno por debajo del cual la dirección queda fuera del halo
+    glow_cos: f64,
+    /// Con el cielo físico reemplaza al degradado
+    physical: Option<PhysicalSky>,
+}
+
+impl SkyCache {
+    /// `disk` = (radio del disco, radio del halo) con el sol alto.
+    fn new(sky_color: Color, sun_dir: Vec3, sun_color: Color, disk: (f64, f64)) -> Self {
+        let base = sky_color;
+        // 0 con el sol alto, 1 en el horizonte
+        let low = (1.0 - sun_dir.y / SUN_LOW_ELEV).clamp(0.0, 1.0);
+        let tint = Color::new(1.0, 1.0, 1.0) * (1.0 - low) + SUN_LOW_TINT * low;
+        let disk_radius = disk.0 * (1.0 + 0.8 * low);
+        let glow_radius = disk.1 * (1.0 + 0.5 * low);
+        Self {
+            horizon: Color::new(base.x * 1.05, base.y * 1.05, base.z * 1.05),
+            zenith: Color::new(base.x * 0.85, base.y * 0.90, base.z * 1.0),
+            disk_radius,
+            glow_radius,
+            disk_color: hadamard(sun_color, tint),
+            // margen para que el redondeo de acos no deje fuera un borde
+            glow_cos: (disk_radius.max(glow_radius) + 1e-6).min(std::f64::consts::PI).cos(),
+            physical: None,
+        }
+    }
+
+    /// Degradado horizonte-cénit según la altura de `d` (o el cielo físico).
+    fn gradient(&self, d: Vec3) -> Color {
+        if let Some(sky) = &self.physical {
+            return sky.radiance(d.normalized());
+        }
+        let t_h = ((d.y.clamp(-1.0, 1.0) + 1.0) * 0.5).clamp(0.0, 1.0);
+        self.zenith * t_h + self.horizon * (1.0 - t_h)
+    }
+}
+
+/// Diferencia relativa de profundidad entre vecinos que cuenta como borde.
+const EDGE_DEPTH_REL: f64 = 0.1;
+
+/// Píxeles de silueta dentro de `region`: algún vecino 3x3 (también dentro)
+/// cambia de cielo a geometría, de material, o salta en profundidad.
+/// `ids` es (material, distancia) del rayo central; None = cielo.
+fn edge_mask(
+    ids: &[Option<(usize, f64)>],
+    w: usize,
+    (x0, y0, x1, y1): (usize, usize, usize, usize),
+) -> Vec<bool> {
+    let mut mask = vec![false; ids.len()];
+    for y in y0..y1 {
+        for x in x0..x1 {
+            let here = ids[y * w + x];
+            mask[y * w + x] = (y.saturating_sub(1).max(y0)..(y + 2).min(y1)).any(|ny| {
+                (x.saturating_sub(1).max(x0)..(x + 2).min(x1)).any(|nx| {
+                    match (here, ids[ny * w + nx]) {
+                        (None, None) => false,
+                        (Some((ma, ta)), Some((mb, tb))) => {
+                            ma != mb || (ta - tb).abs() > EDGE_DEPTH_REL * ta.min(tb)
+                        }
+                        _ => true,
+                    }
+                })
+            });
+        }
+    }
+    mask
+}
+
+/// Suma de pasadas para `accumulate_frame`, válida mientras no cambien
+/// cámara ni tiempo.
+struct Accum {
+    sum: Vec<Color>,
+    passes: u64,
+    time: f64,
+    camera: Option<CameraPose>,
+}
+
+/// Lo que ve el rayo de cámara de un píxel (ver `Renderer::pick`).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PickResult {
+    pub point: Vec3,
+    /// Normal geométrica unitaria
+    pub normal: Vec3,
+    pub mat_id: usize,
+    /// Distancia desde el ojo
+    pub distance: f64,
+}
+
+/// Iluminación del ciclo día/noche en un instante (ver
+/// `Renderer::lighting_at`), para herramientas que sombrean por su cuenta.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LightingSample {
+    /// Dirección unitaria hacia el sol
+    pub sun_dir: Vec3,
+    pub sun_color: Color,
+    /// 0 con el sol bajo el horizonte
+    pub sun_intensity: f64,
+    /// Color del cielo cerca del horizonte
+    pub sky_color: Color,
+    /// Nivel de luz ambiente (0..1)
+    pub ambient: f64,
+}
+
+/// De dónde sale la luz del sol al sombrear.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SunSource {
+    /// Sol analítico de `DayNight` (dirección, color e intensidad por hora)
+    #[default]
+    Analytic,
+    /// El voxel emisivo más fuerte de la escena como luz de área, con
+    /// sombras suaves y caída 1/d²
+    Emissive,
+}
+
+/// Cómo se juntan las dos vistas de `Renderer::set_stereo`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StereoMode {
+    /// Ojo izquierdo en la mitad izquierda de una imagen del doble de ancho
+    /// y el derecho en la otra (visores VR, cross-eye)
+    SideBySide,
+    /// Anaglifo rojo/cian en una imagen del tamaño normal: rojo del ojo
+    /// izquierdo, verde y azul del derecho
+    Anaglyph,
+}
+
+/// Caja emisiva que hace de sol con `SunSource::Emissive`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct AreaSun {
+    min: Vec3,
+    max: Vec3,
+    radiance: Color,
+    /// Área proyectada media (un cuarto de la superficie de la caja)
+    area: f64,
+}
+
+impl AreaSun {
+    /// El voxel con más emisión por superficie; None si no hay emisivos.
+    fn find(scene: &Scene) -> Option<Self> {
+        let surface = |v: &Voxel| {
+            let e = v.max - v.min;
+            2.0 * (e.x * e.y + e.y * e.z + e.z * e.x)
+        };
+        scene
+            .voxels
+            .iter()
+            .map(|v| (v, scene.materials[v.mat_id].emission()))
+            .filter(|(_, e)| luminance(*e) > 0.0)
+            .max_by(|a, b| {
+                let power = |(v, e): &(&Voxel, Color)| luminance(*e) * surface(v);
+                power(a).total_cmp(&power(b))
+            })
+            .map(|(v, e)| Self { min: v.min, max: v.max, radiance: e, area: surface(v) / 4.0 })
+    }
+
+    /// Punto uniforme dentro de la caja.
+    fn sample(&self, rng: &mut Rng) -> Vec3 {
+        let e = self.max - self.min;
+        let (a, b, c) = (rng.next_f64(), rng.next_f64(), rng.next_f64());
+        self.min + Vec3::new(e.x * a, e.y * b, e.z * c)
+    }
 }
 
 pub struct Renderer {
@@ -251,9 +1126,72 @@ pub struct Renderer {
     camera: Option<CameraPose>,
     dn: DayNight,
     tex_cache: Vec<Option<Tex>>,
+    /// `scene.voxels` en SoA para el recorrido; se arma en `set_scene`
+    voxel_soa: VoxelSoa,
     skybox_cache: [Option<Tex>; 6],
     lights: Vec<Light>,
+    light_grid: LightGrid,
     use_procedural_sky: bool,
+    seed: u64,
+    verbose: bool,
+    timing: bool,
+    /// Hilos de render; None = `available_parallelism`
+    threads: Option<usize>,
+    gamma: f64,
+    /// (toe, shoulder) de la curva filmic; None = ACES
+    filmic: Option<(f64, f64)>,
+    linear_output: bool,
+    auto_exposure: Option<f64>,
+    exposure_smoothing: f64,
+    last_exposure: Option<f64>,
+    accum: Option<Accum>,
+    /// Buffer lineal de `render_frame`, reusado entre cuadros
+    scratch: Vec<Color>,
+    /// Vista previa: render a 1/factor de resolución con 1 spp (1 = normal)
+    preview_scale: usize,
+    /// Estéreo: (distancia entre ojos, modo); None = una sola vista
+    stereo: Option<(f64, StereoMode)>,
+    gi: GiSettings,
+    contact_hardening: bool,
+    reference: bool,
+    min_light: f64,
+    caustics: f64,
+    sun_source: SunSource,
+    area_sun: Option<AreaSun>,
+    shading: ShadingModel,
+    ground_ambient: Color,
+    debug: DebugMode,
+    /// Umbral de varianza del muestreo adaptativo (None = apagado)
+    adaptive: Option<f64>,
+    denoise_iterations: u32,
+    chromatic_aberration: f64,
+    grain: f64,
+    /// Zona de interés (x0, y0, x1, y1); None = el frame entero
+    region: Option<(usize, usize, usize, usize)>,
+    /// Muestras extra en las siluetas (0 = apagado)
+    edge_aa: usize,
+    pixel_aspect: f64,
+    blue_noise: bool,
+    /// Radios del disco del sol y de su halo en el cielo procedural
+    sun_disk: (f64, f64),
+    /// Recorte (near, far) de los rayos de cámara
+    clip: (f64, f64),
+    max_portal_jumps: usize,
+    frustum_cull: bool,
+    /// Fondo liso sin cielo procedural ni skybox (None = degradado)
+    background: Option<Color>,
+    /// Lado máximo de las texturas al cargarlas (None = sin límite)
+    max_texture_size: Option<usize>,
+    dither: bool,
+    /// JSON con cámara, hora y ajustes junto a cada cuadro guardado
+    sidecars: bool,
+    sample_pattern: SamplePattern,
+    ao_radius: f64,
+    sky_occlusion: usize,
+    anisotropic: f64,
+    /// Mips de cada textura de `tex_cache` (nivel 1 en adelante); solo con
+    /// filtrado anisotrópico
+    tex_mips: Vec<Vec<Tex>>,
 }
 
 impl Renderer {
@@ -267,23 +1205,162 @@ impl Renderer {
             camera: None,
             dn: DayNight::new(),
             tex_cache: Vec::new(),
+            voxel_soa: VoxelSoa::default(),
             skybox_cache: [None, None, None, None, None, None],
             lights: Vec::new(),
+            light_grid: LightGrid::default(),
             use_procedural_sky: true,
+            seed: 0,
+            verbose: false,
+            timing: false,
+            threads: None,
+            gamma: DEFAULT_GAMMA,
+            filmic: None,
+            linear_output: false,
+            auto_exposure: None,
+            exposure_smoothing: 0.25,
+            last_exposure: None,
+            accum: None,
+            scratch: Vec::new(),
+            preview_scale: 1,
+            stereo: None,
+            gi: GiSettings::default(),
+            contact_hardening: false,
+            reference: false,
+            min_light: DEFAULT_MIN_LIGHT,
+            caustics: 0.0,
+            sun_source: SunSource::Analytic,
+            area_sun: None,
+            shading: ShadingModel::Legacy,
+            ground_ambient: Color::new(0.08, 0.07, 0.06),
+            debug: DebugMode::Off,
+            adaptive: None,
+            denoise_iterations: 0,
+            chromatic_aberration: 0.0,
+            grain: 0.0,
+            region: None,
+            edge_aa: 0,
+            pixel_aspect: 1.0,
+            blue_noise: true,
+            sun_disk: (SUN_DISK_RADIUS, SUN_GLOW_RADIUS),
+            clip: DEFAULT_CLIP,
+            max_portal_jumps: DEFAULT_PORTAL_JUMPS,
+            frustum_cull: true,
+            background: None,
+            max_texture_size: None,
+            dither: false,
+            sidecars: false,
+            sample_pattern: SamplePattern::Center,
+            ao_radius: DEFAULT_AO_RADIUS,
+            sky_occlusion: 0,
+            anisotropic: 0.0,
+            tex_mips: Vec::new(),
         }
     }
 
+    /// Ciclo día/noche del cielo procedural (p. ej. con otra paleta).
+    pub fn set_day_night(&mut self, dn: DayNight) {
+        self.dn = dn;
+    }
+
     pub fn set_use_procedural_sky(&mut self, v: bool) {
         self.use_procedural_sky = v;
     }
 
+    /// Color liso de fondo (p. ej. gris neutro para fotos de producto). Se
+    /// usa solo sin cielo procedural y sin skybox; en lugar del degradado
+    /// que sale de `DayNight`.
+    pub fn set_background(&mut self, c: Color) {
+        self.background = Some(c);
+    }
+
+    /// Reduce al cargar (en `set_scene`) las texturas cuyo lado mayor pasa
+    /// de `px`, promediando en lineal. Ahorra memoria con texturas grandes
+    /// que se ven chicas.
+    pub fn set_max_texture_size(&mut self, px: usize) {
+        self.max_texture_size = Some(px.max(1));
+    }
+
     pub fn set_scene(&mut self, scene: &Scene) {
         let cloned = scene.clone();
+        for w in cloned.validate() {
+            println!("  aviso de escena: {:?}", w);
+        }
+        self.load_material_textures(&cloned);
+        self.load_skybox(&cloned.skybox);
+        self.voxel_soa = VoxelSoa::new(&cloned.voxels);
+
+        self.lights = gather_lights(&cloned);
+        self.light_grid = LightGrid::new(&self.lights);
+        self.area_sun = AreaSun::find(&cloned);
+        self.reset_accumulation();
+
+        self.scene = Some(cloned);
+        println!("================================\n");
+    }
+
+    /// Como `render_frame`, pero antes `edit` puede cambiar la escena para
+    /// el instante `time` (abrir una puerta, mover un bloque, cambiar un
+    /// material); los cambios quedan para los cuadros siguientes. Las
+    /// texturas se recargan solo si cambió alguna fuente, la rejilla de luces
+    /// solo si cambiaron los emisivos y la SoA de voxels solo si se movió
+    /// algún voxel. La acumulación se descarta con cualquier cambio de la
+    /// escena y se conserva si `edit` no tocó nada.
+    pub fn render_frame_with<F>(&mut self, img: &mut Image, time: f64, edit: F) -> RenderStats
+    where
+        F: FnOnce(&mut Scene, f64),
+    {
+        if let Some(mut scene) = self.scene.take() {
+            let before = scene.clone();
+            edit(&mut scene, time);
+
+            let texture = |m: &Material| m.texture.clone();
+            if scene.materials.iter().map(texture).ne(before.materials.iter().map(texture)) {
+                self.load_material_textures(&scene);
+            }
+            if scene.skybox != before.skybox {
+                self.load_skybox(&scene.skybox);
+            }
+            let voxels_changed = scene.voxels != before.voxels;
+            if voxels_changed {
+                self.voxel_soa = VoxelSoa::new(&scene.voxels);
+            }
+            let lights = gather_lights(&scene);
+            let lights_changed = lights != self.lights;
+            if lights_changed {
+                self.lights = lights;
+                self.light_grid = LightGrid::new(&self.lights);
+            }
+            if voxels_changed || lights_changed {
+                self.area_sun = AreaSun::find(&scene);
+            }
+            if scene != before {
+                self.reset_accumulation();
+            }
+            self.scene = Some(scene);
+        }
+        self.render_frame(img, time)
+    }
 
-        let mut cache = Vec::with_capacity(cloned.materials.len());
+    fn load_material_textures(&mut self, scene: &Scene) {
+        let mut cache = Vec::with_capacity(scene.materials.len());
         println!("\n== Texturas de materiales ==");
-        for (i, m) in cloned.materials.iter().enumerate() {
-            if let Some(path) = m.texture_path {
+        for (i, m) in scene.materials.iter().enumerate() {
+            if let Some(TextureSource::Rgb { w, h, data }) = &m.texture {
+                let tex = rgb_tex(*w, *h, data, self.max_texture_size);
+                match tex {
+                    Some(_) => println!("  [{}] {} -> en memoria ({}x{} RGB)", i, m.name, w, h),
+                    None => println!(
+                        "  [{}] {} -> ERROR: {} bytes para {}x{} RGB",
+                        i,
+                        m.name,
+                        data.len(),
+                        w,
+                        h
+                    ),
+                }
+                cache.push(tex);
+            } else if let Some(path) = m.texture_path() {
                 let exists = Path::new(path).exists();
                 println!(
                     "  [{}] {} -> {}  ({})",
@@ -292,9 +1369,9 @@ impl Renderer {
                     path,
                     if exists { "existe" } else { "NO existe" }
                 );
-                let tex = load_tex(path);
+                let tex = load_tex(path, self.max_texture_size);
                 if let Some(ref t) = tex {
-                    println!("       cargada OK ({}x{} RGB)", t.w, t.h);
+                    println!("       cargada OK ({}x{} RGBA)", t.w, t.h);
                 } else {
                     println!("       ERROR: no se pudo cargar imagen");
                 }
@@ -305,8 +1382,12 @@ impl Renderer {
             }
         }
         self.tex_cache = cache;
+        self.build_mips();
+    }
 
-        fn load_opt(path_opt: &Option<&'static str>) -> Option<Tex> {
+    fn load_skybox(&mut self, sb: &Skybox) {
+        let max_size = self.max_texture_size;
+        let load_opt = |path_opt: &Option<&'static str>| -> Option<Tex> {
             if let Some(p) = path_opt {
                 let exists = Path::new(p).exists();
                 println!(
@@ -314,12 +1395,11 @@ impl Renderer {
                     p,
                     if exists { "existe" } else { "NO existe" }
                 );
-                load_tex(p)
+                load_tex(p, max_size)
             } else {
                 None
             }
-        }
-        let sb = &cloned.skybox;
+        };
         println!("\n== Skybox ==");
         self.skybox_cache = [
             load_opt(&sb.right),
@@ -329,23 +1409,6 @@ impl Renderer {
             load_opt(&sb.front),
             load_opt(&sb.back),
         ];
-
-        let mut lights = Vec::new();
-        for v in &cloned.voxels {
-            let m = &cloned.materials[v.mat_id];
-            if m.emissive.x > 0.0 || m.emissive.y > 0.0 || m.emissive.z > 0.0 {
-                let center = (v.min + v.max) * 0.5;
-                lights.push(Light {
-                    pos: center,
-                    color: Color::new(m.emissive.x, m.emissive.y, m.emissive.z),
-                    intensity: 1.0,
-                });
-            }
-        }
-        self.lights = lights;
-
-        self.scene = Some(cloned);
-        println!("================================\n");
     }
 
     pub fn set_camera(&mut self, pose: &CameraPose) {
@@ -357,534 +1420,4817 @@ impl Renderer {
         });
     }
 
-    pub fn render_frame(&mut self, img: &mut Image, time: f64) {
-        let ntiles_x = (self.w + self.tilesz - 1) / self.tilesz;
-        let ntiles_y = (self.h + self.tilesz - 1) / self.tilesz;
-
-        let sun_dir = self.dn.sun_direction(time);
-        let sun_intensity = self.dn.sun_intensity(time);
-        let sun_color = self.dn.sun_color(time);
-        let sky_color = self.dn.sky_color(time);
-        let ambient_level = self.dn.ambient_level(time);
-
-        let scene_cloned = self.scene.clone();
-        let camera_cloned = self.camera.clone();
-        let tex_cache_cloned = self.tex_cache.clone();
-        let skybox_cache_cloned = self.skybox_cache.clone();
-        let lights_cloned = self.lights.clone();
-        let time_local = time;
-
-        let fb = Arc::new(Mutex::new(vec![
-            Color::new(0.0, 0.0, 0.0);
-            self.w * self.h
-        ]));
-
-        let mut handles = Vec::new();
-
-        for ty in 0..ntiles_y {
-            for tx in 0..ntiles_x {
-                let fb_cl = Arc::clone(&fb);
-                let w = self.w;
-                let h = self.h;
-                let tilesz = self.tilesz;
-                let spp = self.spp;
-
-                let sun_dir_local = sun_dir;
-                let sun_intensity_local = sun_intensity;
-                let sun_color_local = sun_color;
-                let sky_color_local = sky_color;
-                let ambient_level_local = ambient_level;
-                let use_procedural_sky_local = self.use_procedural_sky;
-
-                let scene_local = scene_cloned.clone();
-                let cam_local = camera_cloned.clone();
-                let tex_cache_local = tex_cache_cloned.clone();
-                let skybox_cache_local = skybox_cache_cloned.clone();
-                let lights_local = lights_cloned.clone();
-
-                let handle = thread::spawn(move || {
-                    let x0 = tx * tilesz;
-                    let y0 = ty * tilesz;
-                    let x1 = (x0 + tilesz).min(w);
-                    let y1 = (y0 + tilesz).min(h);
-
-                    let mut tile_colors: Vec<(usize, usize, Color)> =
-                        Vec::with_capacity((x1 - x0) * (y1 - y0));
-
-                    if scene_local.is_none() || cam_local.is_none() {
-                        for y in y0..y1 {
-                            for x in x0..x1 {
-                                let v = y as f64 / (h - 1).max(1) as f64;
-                                let base = Color::new(
-                                    sky_color_local.x * (1.0 - v * 0.3),
-                                    sky_color_local.y * (1.0 - v * 0.3),
-                                    sky_color_local.z,
-                                );
-                                tile_colors.push((x, y, base));
-                            }
-                        }
-                    } else {
-                        let scene = scene_local.unwrap();
-                        let cam = cam_local.unwrap();
-                        let pose = cam;
-
-                        for y in y0..y1 {
-                            for x in x0..x1 {
-                                let mut color_acc = Color::new(0.0, 0.0, 0.0);
-
-                                for _s in 0..spp {
-                                    let ray = make_primary_ray(x, y, w, h, &pose);
-
-                                    if let Some(hit) = trace_voxels(&ray, &scene.voxels) {
-                                        let mat = &scene.materials[hit.mat_id];
-
-                                        let (mut u, mut v) =
-                                            voxel_uv(hit.vmin, hit.vmax, hit.p, hit.n);
-                                        let uvscale = if mat.uv_scale.is_finite() {
-                                            mat.uv_scale
-                                        } else {
-                                            1.0
-                                        };
-                                        u *= uvscale;
-                                        v *= uvscale;
-                                        if mat.animated_uv {
-                                            u = (u + time_local * 0.2).fract();
-                                            v = v.fract();
-                                        }
-
-                                        let mut albedo = clamp01(mat.albedo);
-                                        if let Some(tex) =
-                                            tex_for_mat(hit.mat_id, &tex_cache_local)
-                                        {
-                                            let tex_c = sample_tex_nearest(tex, u, v);
-                                            albedo = clamp01(hadamard(albedo, tex_c));
-                                        }
-
-                                        let nrm = hit.n.normalized();
-
-                                        // luz solar
-                                        let mut sun_contribution =
-                                            Color::new(0.0, 0.0, 0.0);
-                                        if sun_intensity_local > 0.0 {
-                                            let samples = 4;
-                                            let mut sun_lit = 0.0;
-                                            for i in 0..samples {
-                                                let l =
-                                                    sun_sample_dir(sun_dir_local, i as u32);
-                                                let nl = nrm.dot(l).max(0.0);
-                                                if nl > 0.0 {
-                                                    let eps = 1e-4;
-                                                    let vis =
-                                                        if unoccluded_ray(
-                                                            &Ray::new(
-                                                                hit.p + nrm * eps,
-                                                                l,
-                                                            ),
-                                                            &scene.voxels,
-                                                            1e6,
-                                                        ) {
-                                                            1.0
-                                                        } else {
-                                                            0.0
-                                                        };
-                                                    sun_lit += nl * vis;
-                                                }
-                                            }
-                                            sun_lit /= samples as f64;
-
-                                            let sun_rgb = Color::new(
-                                                sun_color_local.x,
-                                                sun_color_local.y,
-                                                sun_color_local.z,
-                                            );
-                                            sun_contribution = hadamard(
-                                                albedo,
-                                                sun_rgb,
-                                            ) * (sun_lit * sun_intensity_local * 1.0);
-                                        }
-
-                                        // ambiente hemisférico
-                                        let sky_up = Color::new(
-                                            sky_color_local.x,
-                                            sky_color_local.y,
-                                            sky_color_local.z,
-                                        );
-                                        let ground_col =
-                                            Color::new(0.08, 0.07, 0.06);
-                                        let k_hemi =
-                                            (nrm.y * 0.5 + 0.5).clamp(0.0, 1.0);
-                                        let hemi = sky_up * k_hemi
-                                            + ground_col * (1.0 - k_hemi);
-                                        let ambient =
-                                            hadamard(albedo, hemi) * ambient_level_local;
-
-                                        // AO
-                                        let ao = ao_term(hit.p, nrm, &scene.voxels);
-
-                                        // especular solar
-                                        let mut specular =
-                                            Color::new(0.0, 0.0, 0.0);
-                                        if sun_intensity_local > 0.3 {
-                                            let view = (-ray.d).normalized();
-                                            let mut sun_vec = sun_dir_local;
-                                            if sun_vec.y < 0.1 {
-                                                sun_vec.y = 0.1;
-                                            }
-                                            let half_vec =
-                                                (view + sun_vec).normalized();
-                                            let nh = nrm.dot(half_vec).max(0.0);
-                                            let shininess = 32.0;
-                                            let spec_strength = 0.15;
-                                            let spec_factor =
-                                                nh.powf(shininess) * spec_strength;
-                                            let sun_rgb = Color::new(
-                                                sun_color_local.x,
-                                                sun_color_local.y,
-                                                sun_color_local.z,
-                                            );
-                                            specular =
-                                                hadamard(sun_rgb, albedo) * spec_factor;
-                                        }
-
-                                        // luces emisivas
-                                        let mut lights_sum =
-                                            Color::new(0.0, 0.0, 0.0);
-                                        for light in &lights_local {
-                                            let to_l = light.pos - hit.p;
-                                            let dist = to_l.length();
-                                            let ldir = to_l / dist;
-
-                                            let nl = nrm.dot(ldir).max(0.0);
-                                            if nl <= 0.0 {
-                                                continue;
-                                            }
-
-                                            let eps = 1e-4;
-                                            let unoccluded = !blocked_along(
-                                                &Ray::new(hit.p + nrm * eps, ldir),
-                                                &scene.voxels,
-                                                dist - eps,
-                                            );
-                                            if !unoccluded {
-                                                continue;
-                                            }
-
-                                            let max_range = 10.0;
-                                            let falloff =
-                                                (1.0 - (dist / max_range).min(1.0))
-                                                    .max(0.0);
-                                            let atten = falloff * falloff;
-
-                                            // flicker usando time_local
-                                            let phase = time_local * 6.0
-                                                + light.pos.x * 2.0
-                                                + light.pos.z * 3.0;
-                                            let flicker = (0.8
-                                                + 0.2
-                                                    * (phase.sin()
-                                                        * (phase * 1.3).cos()))
-                                                .clamp(0.6, 1.2);
-
-                                            let contrib = hadamard(
-                                                albedo,
-                                                light.color
-                                                    * (light.intensity * flicker),
-                                            ) * (nl * atten * 0.8);
-                                            lights_sum = lights_sum + contrib;
-                                        }
-
-                                        let mut c = (ambient + sun_contribution
-                                            + lights_sum
-                                            + specular)
-                                            * ao;
-
-                                        let min_light = ambient_level_local * 0.3;
-                                        c = c + (albedo * min_light);
-
-                                        color_acc = color_acc + c;
-                                    } else {
-                                        // miss: cielo
-                                        if use_procedural_sky_local {
-                                            let up = ray.d.y.clamp(-1.0, 1.0);
-                                            let base = Color::new(
-                                                sky_color_local.x,
-                                                sky_color_local.y,
-                                                sky_color_local.z,
-                                            );
-
-                                            let t_h = ((up + 1.0) * 0.5)
-                                                .clamp(0.0, 1.0);
-                                            let horizon = Color::new(
-                                                base.x * 1.05,
-                                                base.y * 1.05,
-                                                base.z * 1.05,
-                                            );
-                                            let zenith = Color::new(
-                                                base.x * 0.85,
-                                                base.y * 0.90,
-                                                base.z * 1.0,
-                                            );
-                                            let mut sky = zenith * t_h
-                                                + horizon * (1.0 - t_h);
-
-                                            let dp =
-                                                ray.d.dot(sun_dir_local).clamp(-1.0, 1.0);
-                                            let ang = dp.acos();
-                                            let sun_disk =
-                                                (0.008 - ang).max(0.0) * 80.0;
-                                            let sun_glow =
-                                                (0.10 - ang).max(0.0) * 1.5;
-                                            let sun_rgb = Color::new(
-                                                sun_color_local.x,
-                                                sun_color_local.y,
-                                                sun_color_local.z,
-                                            );
-                                            sky = sky + sun_rgb
-                                                * (sun_disk + sun_glow)
-                                                * sun_intensity_local;
-
-                                            color_acc = color_acc + sky;
-                                        } else {
-                                            let (face, su, sv) =
-                                                dir_to_cube_uv(ray.d);
-                                            if let Some(tex) =
-                                                &skybox_cache_local[face]
-                                            {
-                                                let c =
-                                                    sample_tex_nearest(tex, su, sv);
-                                                color_acc = color_acc + c;
-                                            } else {
-                                                let v = y as f64
-                                                    / (h - 1).max(1) as f64;
-                                                let base = Color::new(
-                                                    sky_color_local.x
-                                                        * (1.0 - v * 0.3),
-                                                    sky_color_local.y
-                                                        * (1.0 - v * 0.3),
-                                                    sky_color_local.z,
-                                                );
-                                                color_acc = color_acc + base;
-                                            }
-                                        }
-                                    }
-                                }
-
-                                let c = color_acc / (spp as f64);
-                                tile_colors.push((x, y, c));
-                            }
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    /// `render_sequence` y `render_turntable` (y `save_sidecar`) escriben
+    /// junto a cada `frame_XXXX.bmp` un `frame_XXXX.json` con lo necesario
+    /// para repetirlo (ver `FrameMeta`). Apagado por defecto.
+    pub fn set_sidecars(&mut self, on: bool) {
+        self.sidecars = on;
+    }
+
+    /// Metadatos de un cuadro renderizado con `camera` (None = la puesta) a
+    /// la hora `time`.
+    pub fn frame_meta(
+        &self,
+        time: f64,
+        camera: Option<CameraPose>,
+        stats: &RenderStats,
+    ) -> FrameMeta {
+        FrameMeta {
+            camera: camera.or(self.camera),
+            time,
+            spp: self.spp,
+            seed: self.seed,
+            exposure: stats.exposure,
+        }
+    }
+
+    /// Escribe `meta` junto al cuadro `path` si los sidecars están activos
+    /// (`set_sidecars`). Un error se avisa por stderr y no corta el render.
+    pub fn save_sidecar(&self, path: &str, meta: FrameMeta) {
+        if !self.sidecars {
+            return;
+        }
+        if let Err(e) = meta.save(path) {
+            eprintln!("No se pudo escribir {:?}: {}", FrameMeta::sidecar_path(path), e);
+        }
+    }
+
+    /// Reporta por stderr el avance (tiles por frame, frames por secuencia)
+    /// con tiempo restante estimado.
+    pub fn set_verbose(&mut self, v: bool) {
+        self.verbose = v;
+    }
+
+    /// Mide el tiempo por etapa (cámara, sombras) en `RenderStats`.
+    pub fn set_timing(&mut self, v: bool) {
+        self.timing = v;
+    }
+
+    /// Tope de hilos de render. `Some(1)` hace todo en el hilo que llama
+    /// (sin lanzar ninguno, para depurar paso a paso); None usa todos los
+    /// núcleos. La imagen no depende del número de hilos.
+    pub fn set_threads(&mut self, threads: Option<usize>) {
+        self.threads = threads.map(|n| n.max(1));
+    }
+
+    /// Hilos a usar para `jobs` trabajos independientes.
+    fn workers(&self, jobs: usize) -> usize {
+        let n = self.threads.unwrap_or_else(|| {
+            thread::available_parallelism().map_or(1, |n| n.get())
+        });
+        n.min(jobs).max(1)
+    }
+
+    /// Cambia el tonemap ACES fijo por una curva filmic con pie y hombro
+    /// ajustables (ver `DEFAULT_FILMIC_TOE` / `DEFAULT_FILMIC_SHOULDER`,
+    /// que se parecen a ACES): más toe aclara las sombras, más shoulder
+    /// lleva las altas luces antes al blanco.
+    pub fn set_filmic_params(&mut self, toe: f64, shoulder: f64) {
+        self.filmic = Some((toe, shoulder));
+    }
+
+    /// Curva de tonos activa: ACES o la filmic de `set_filmic_params`.
+    fn tonemap(&self, c: Color) -> Color {
+        match self.filmic {
+            Some((toe, shoulder)) => tonemap_filmic(c, toe, shoulder),
+            None => tonemap_aces(c),
+        }
+    }
+
+    /// Gamma de salida (2.2 por defecto). Valores no positivos o no finitos
+    /// vuelven al default.
+    pub fn set_gamma(&mut self, gamma: f64) {
+        self.gamma = if gamma.is_finite() && gamma > 0.0 {
+            gamma
+        } else {
+            DEFAULT_GAMMA
+        };
+    }
+
+    /// Salida lineal: omite la codificación gamma (para un pipeline con
+    /// gestión de color propio).
+    pub fn set_linear_output(&mut self, v: bool) {
+        self.linear_output = v;
+    }
+
+    /// Auto-exposición (Reinhard): escala el buffer previo al tonemap para
+    /// que su luminancia log-promedio quede en `target_key` (0.18 es el gris
+    /// medio clásico). Desactivada por defecto.
+    pub fn set_auto_exposure(&mut self, target_key: f64) {
+        self.auto_exposure = (target_key.is_finite() && target_key > 0.0).then_some(target_key);
+        self.last_exposure = None;
+    }
+
+    pub fn disable_auto_exposure(&mut self) {
+        self.auto_exposure = None;
+        self.last_exposure = None;
+    }
+
+    /// Peso del frame nuevo al suavizar la exposición entre llamadas a
+    /// `render_frame` (1 = sin suavizado, 0.25 por defecto). Evita parpadeo
+    /// en el timelapse.
+    pub fn set_exposure_smoothing(&mut self, k: f64) {
+        self.exposure_smoothing = k.clamp(0.0, 1.0);
+    }
+
+    /// Sol y cielo del ciclo día/noche en `time`, sin renderizar (p. ej.
+    /// para dibujar la posición del sol en un editor).
+    pub fn lighting_at(&self, time: f64) -> LightingSample {
+        LightingSample {
+            sun_dir: self.dn.sun_direction(time),
+            sun_color: self.dn.sun_color(time),
+            sun_intensity: self.dn.sun_intensity(time),
+            sky_color: self.dn.sky_color(time),
+            ambient: self.dn.ambient_level(time),
+        }
+    }
+
+    /// Primer impacto del rayo por el centro del píxel (x, y), para elegir
+    /// voxels con el mouse o medir distancias. None si da al cielo o falta
+    /// escena o cámara. Sin animación de UV (tiempo 0) en los recortes.
+    pub fn pick(&self, x: usize, y: usize) -> Option<PickResult> {
+        let (scene, pose) = (self.scene.as_ref()?, self.camera?);
+        let (sx, sy) = (x as f64 + 0.5, y as f64 + 0.5);
+        let ray = make_primary_ray(sx, sy, self.w, self.h, self.pixel_aspect, self.clip, &pose);
+        let ctx = TraceCtx::new(scene, &self.tex_cache, 0.0);
+        let hit = trace_scene(&ray, &TraceCtx { voxel_soa: self.voxel_soa(), ..ctx })?;
+        Some(PickResult {
+            point: hit.p,
+            normal: hit.n.normalized(),
+            mat_id: hit.mat_id,
+            distance: hit.t,
+        })
+    }
+
+    pub fn render_frame(&mut self, img: &mut Image, time: f64) -> RenderStats {
+        let stats = self.render_scratch(img, time, None);
+        if self.auto_exposure.is_some() {
+            self.last_exposure = Some(stats.exposure);
+        }
+        stats
+    }
+
+    /// Como `render_frame`, pero mira `token` entre tiles: si se activa, los
+    /// tiles que faltan quedan en negro y se vuelve enseguida con la imagen
+    /// parcial (sin denoiser y sin tocar la exposición automática).
+    pub fn render_frame_cancellable(
+        &mut self,
+        img: &mut Image,
+        time: f64,
+        token: &CancelToken,
+    ) -> RenderStats {
+        let stats = self.render_scratch(img, time, Some(token));
+        if self.auto_exposure.is_some() && !token.is_cancelled() {
+            self.last_exposure = Some(stats.exposure);
+        }
+        stats
+    }
+
+    /// Render de referencia, lento, para comparar los atajos: GI por path
+    /// tracing (si estaba apagada), sombras del sol con muchas muestras al
+    /// azar en el disco, sin AO ni ambiente hemisférico y sin el piso de
+    /// luz mínima.
+    pub fn set_reference_mode(&mut self, on: bool) {
+        self.reference = on;
+    }
+
+    /// Piso de luz que se suma a todo punto (albedo * ambiente * `k`), para
+    /// que las sombras nunca queden negras. 0.3 por defecto; 0 deja sombras
+    /// y oclusión con todo su contraste.
+    pub fn set_min_light(&mut self, k: f64) {
+        self.min_light = k.max(0.0);
+    }
+
+    /// Activa la GI con hasta `max_depth` rebotes (0 la apaga).
+    pub fn set_gi(&mut self, max_depth: u32) {
+        self.gi.max_depth = max_depth;
+    }
+
+    /// Rebotes antes de que la ruleta rusa pueda cortar el camino.
+    pub fn set_min_bounces(&mut self, n: u32) {
+        self.gi.min_bounces = n;
+    }
+
+    /// Penumbras que se endurecen en el contacto con el oclusor y se
+    /// ablandan con la distancia.
+    pub fn set_contact_hardening(&mut self, v: bool) {
+        self.contact_hardening = v;
+    }
+
+    /// Cáusticas aproximadas bajo agua y vidrio con la intensidad dada
+    /// (0 = apagadas, por defecto).
+    pub fn set_caustics(&mut self, strength: f64) {
+        self.caustics = strength.max(0.0);
+    }
+
+    /// Sol analítico (por defecto) o el voxel emisivo más fuerte como luz
+    /// de área. Con `Emissive` y sin emisivos en la escena no hay sol
+    /// directo; el cielo sigue viniendo de `DayNight`.
+    pub fn set_sun_source(&mut self, source: SunSource) {
+        self.sun_source = source;
+    }
+
+    /// Modelo de sombreado del sol y las luces (`Legacy` por defecto).
+    pub fn set_shading(&mut self, model: ShadingModel) {
+        self.shading = model;
+    }
+
+    /// Color del suelo en el ambiente hemisférico (sin GI): tiñe la luz que
+    /// reciben las caras que miran hacia abajo (pasto, nieve...).
+    pub fn set_ground_ambient(&mut self, c: Color) {
+        self.ground_ambient = c;
+    }
+
+    /// Muestreo adaptativo: una muestra por píxel y el resto de `spp` solo
+    /// donde la varianza de luminancia (tras el tonemap, 3x3) supera
+    /// `variance_threshold`. `spp` sigue siendo el máximo por píxel.
+    pub fn set_adaptive(&mut self, on: bool, variance_threshold: f64) {
+        self.adaptive = on.then_some(variance_threshold.max(0.0));
+    }
+
+    /// Denoiser à-trous con `iterations` pasadas (0 = apagado) sobre el
+    /// buffer lineal, antes del tonemap. Respeta los bordes de geometría.
+    pub fn set_denoise(&mut self, iterations: u32) {
+        self.denoise_iterations = iterations;
+    }
+
+    /// Aberración cromática radial tras el tonemap (0 = apagada).
+    pub fn set_chromatic_aberration(&mut self, strength: f64) {
+        self.chromatic_aberration = strength.max(0.0);
+    }
+
+    /// Grano de película tras el tonemap (0 = apagado; 0.02..0.05 es sutil).
+    pub fn set_grain(&mut self, strength: f64) {
+        self.grain = strength.max(0.0);
+    }
+
+    /// Distancia hasta la que la geometría ocluye en el AO (1 = un voxel).
+    pub fn set_ao_radius(&mut self, radius: f64) {
+        self.ao_radius = radius.max(0.0);
+    }
+
+    /// Filtrado anisotrópico de texturas en los rayos de cámara: la huella
+    /// del píxel en UV (de las UV de los rayos vecinos) elige el mip por su
+    /// eje corto y promedia hasta `max_ratio` muestras a lo largo del largo.
+    /// Quita el parpadeo del pasto visto de refilón. 0 = nearest sin mips,
+    /// como siempre; 1 = solo mips.
+    pub fn set_anisotropic(&mut self, max_ratio: f64) {
+        self.anisotropic = if max_ratio > 0.0 { max_ratio.max(1.0) } else { 0.0 };
+        self.build_mips();
+    }
+
+    /// (Re)genera los mips de las texturas cargadas si hacen falta.
+    fn build_mips(&mut self) {
+        if self.anisotropic == 0.0 {
+            self.tex_mips.clear();
+            return;
+        }
+        self.tex_mips = self
+            .tex_cache
+            .iter()
+            .map(|t| t.as_ref().map_or(Vec::new(), mip_chain))
+            .collect();
+    }
+
+    /// Estima con `samples` rayos cuánto cielo ve cada punto y escala con
+    /// eso la parte de cielo del ambiente hemisférico (bajo un alero llega
+    /// menos). 0 = ambiente sin oclusión, como siempre. Sin efecto con GI.
+    pub fn set_sky_occlusion(&mut self, samples: usize) {
+        self.sky_occlusion = samples;
+    }
+
+    /// Radio de AO proporcional al tamaño de la escena actual: `fraction`
+    /// de la diagonal de su caja. Sin escena (o vacía) no cambia nada.
+    pub fn set_ao_radius_from_scene(&mut self, fraction: f64) {
+        let Some(scene) = &self.scene else {
+            return;
+        };
+        let points: Vec<Vec3> = scene
+            .voxels
+            .iter()
+            .flat_map(|v| [v.min, v.max])
+            .chain(scene.triangles.iter().flat_map(|t| [t.v0, t.v1, t.v2]))
+            .chain(scene.spheres.iter().flat_map(|s| {
+                let r = Vec3::new(s.radius, s.radius, s.radius);
+                [s.center - r, s.center + r]
+            }))
+            .chain(scene.instances.iter().flat_map(|i| {
+                let (lo, hi) = i.bounds();
+                [lo, hi]
+            }))
+            .collect();
+        if !points.is_empty() {
+            let (lo, hi) = points_bounds(&points);
+            self.set_ao_radius((hi - lo).length() * fraction);
+        }
+    }
+
+    /// Reparto de las `spp` muestras dentro del píxel. Por defecto todas van
+    /// al centro, como antes del jitter; `Random` las reparte al azar.
+    pub fn set_sample_pattern(&mut self, pattern: SamplePattern) {
+        self.sample_pattern = pattern;
+    }
+
+    /// Dither con ruido azul antes de cuantizar a 8 bits: quita las bandas
+    /// del degradado del cielo. Apagado por defecto.
+    pub fn set_dither(&mut self, on: bool) {
+        self.dither = on;
+    }
+
+    /// Recorte de los rayos de cámara: se ignora lo que está a menos de
+    /// `near` (la pared detrás de la que está la cámara) y más allá de `far`
+    /// se ve cielo. Por defecto (0.001, 1e6).
+    pub fn set_clip(&mut self, near: f64, far: f64) {
+        let near = near.max(0.0);
+        self.clip = (near, far.max(near));
+    }
+
+    /// Máximo de portales que cruza un rayo de cámara (0 por defecto); en
+    /// el último los portales dejan de teletransportar. 0 = sin portales.
+    pub fn set_max_portal_jumps(&mut self, n: usize) {
+        self.max_portal_jumps = n;
+    }
+
+    /// Descarta para los rayos de cámara los objetos fuera del campo de
+    /// visión (activado por defecto). Sombras y rebotes ven todo igual.
+    pub fn set_frustum_cull(&mut self, on: bool) {
+        self.frustum_cull = on;
+    }
+
+    /// Radio angular (rad) del disco del sol y de su halo en el cielo
+    /// procedural, con el sol alto (0.008 y 0.10 por defecto). Cerca del
+    /// horizonte el disco se agranda y se enrojece.
+    pub fn set_sun_disk(&mut self, angular_radius: f64, glow: f64) {
+        self.sun_disk = (angular_radius.max(0.0), glow.max(0.0));
+    }
+
+    /// Jitter de `SamplePattern::Random` con ruido azul (por defecto) o, con
+    /// `false`, ruido blanco del `Rng` como antes, para comparar.
+    pub fn set_blue_noise(&mut self, on: bool) {
+        self.blue_noise = on;
+    }
+
+    /// Relación ancho/alto de los píxeles de la pantalla destino (1.0 por
+    /// defecto). Para salidas anamórficas: con 2.0 cada píxel se verá el
+    /// doble de ancho, así que el encuadre horizontal abarca el doble.
+    pub fn set_pixel_aspect(&mut self, aspect: f64) {
+        self.pixel_aspect = if aspect > 0.0 { aspect } else { 1.0 };
+    }
+
+    /// Antialias de siluetas: tras la pasada normal, `extra_samples`
+    /// muestras más en los píxeles donde cambia la geometría (cielo,
+    /// material o salto de profundidad). 0 = apagado. Con esto activo el
+    /// jitter sub-píxel se usa aunque `spp` sea 1.
+    pub fn set_edge_aa(&mut self, extra_samples: usize) {
+        self.edge_aa = extra_samples;
+    }
+
+    /// Vista previa rápida para encuadre e iluminación: `render_frame`
+    /// traza a 1/`factor` de la resolución con 1 spp y agranda por vecino
+    /// más cercano al copiar a la imagen. 1 (por defecto) = render normal.
+    pub fn set_preview_scale(&mut self, factor: usize) {
+        self.preview_scale = factor.max(1);
+    }
+
+    /// Estéreo: `render_frame` traza dos vistas desde ojos separados `ipd`
+    /// (unidades de mundo) sobre el eje derecho de la cámara, con ejes
+    /// paralelos. Con `SideBySide` la imagen debe tener el doble de ancho.
+    /// `ipd` <= 0 vuelve a una sola vista; sin cámara no hace nada.
+    pub fn set_stereo(&mut self, ipd: f64, mode: StereoMode) {
+        self.stereo = (ipd > 0.0).then_some((ipd, mode));
+    }
+
+    /// Renderiza solo los píxeles en [x0, x1) x [y0, y1) y deja el resto de
+    /// la imagen como estaba (None = todo el frame). La exposición
+    /// automática se mide solo dentro de la región.
+    pub fn set_region(&mut self, region: Option<(usize, usize, usize, usize)>) {
+        self.region = region;
+    }
+
+    /// Región a renderizar, recortada al frame.
+    fn region_bounds(&self) -> (usize, usize, usize, usize) {
+        match self.region {
+            Some((x0, y0, x1, y1)) => (
+                x0.min(self.w),
+                y0.min(self.h),
+                x1.clamp(x0.min(self.w), self.w),
+                y1.clamp(y0.min(self.h), self.h),
+            ),
+            None => (0, 0, self.w, self.h),
+        }
+    }
+
+    /// Vista de depuración en lugar del sombreado normal.
+    pub fn set_debug(&mut self, mode: DebugMode) {
+        self.debug = mode;
+    }
+
+    /// Suma una pasada más al promedio acumulado y deja el resultado en
+    /// `img`. Con la cámara quieta la imagen converge; si cambia la cámara o
+    /// el tiempo, la acumulación arranca de nuevo. Cada pasada usa otro
+    /// jitter, así que conviene spp > 1 con `SamplePattern::Random`.
+    pub fn accumulate_frame(&mut self, img: &mut Image, time: f64) -> RenderStats {
+        let mut acc = match self.accum.take() {
+            Some(a) if a.time.to_bits() == time.to_bits() && a.camera == self.camera => a,
+            _ => Accum {
+                sum: vec![Color::new(0.0, 0.0, 0.0); self.w * self.h],
+                passes: 0,
+                time,
+                camera: self.camera,
+            },
+        };
+
+        let frame_start = Instant::now();
+        let stats = FrameStats::default();
+        let mut fb = std::mem::take(&mut self.scratch);
+        let fc = self.frame_ctx(time, self.camera, self.frame_seed(time, acc.passes), &stats);
+        self.render_linear_into(&mut fb, &fc, true, None);
+        drop(fc);
+        for (s, c) in acc.sum.iter_mut().zip(&fb) {
+            *s = *s + *c;
+        }
+        self.scratch = fb;
+        acc.passes += 1;
+        let n = acc.passes as f64;
+        let mut avg: Vec<Color> = acc.sum.iter().map(|&s| s / n).collect();
+        self.accum = Some(acc);
+        self.denoise(&mut avg, time, self.camera, true, &stats);
+
+        let out = self.resolve(img, &avg, time, self.last_exposure, &stats, frame_start);
+        if self.auto_exposure.is_some() {
+            self.last_exposure = Some(out.exposure);
+        }
+        out
+    }
+
+    /// Descarta las pasadas acumuladas.
+    pub fn reset_accumulation(&mut self) {
+        self.accum = None;
+    }
+
+    /// Pasadas acumuladas hasta ahora (0 sin acumulación en curso).
+    pub fn accumulated_passes(&self) -> u64 {
+        self.accum.as_ref().map_or(0, |a| a.passes)
+    }
+
+    /// Renderiza `frames` cuadros en paralelo (un cuadro por hilo, con sus
+    /// tiles en serie) y guarda cada uno en `outdir/frame_XXXX.bmp`.
+    /// Útil cuando los tiles de un solo cuadro no alcanzan a ocupar todos
+    /// los núcleos. La semilla de cada cuadro sale de su tiempo, así que el
+    /// resultado es idéntico a llamar `render_frame` cuadro por cuadro.
+    /// La auto-exposición se calcula por cuadro, sin suavizado: los cuadros
+    /// no terminan en orden.
+    pub fn render_sequence<T, C>(&self, frames: u32, time_fn: T, camera_fn: C, outdir: &str)
+    where
+        T: Fn(u32) -> f64 + Sync,
+        C: Fn(u32) -> CameraPose + Sync,
+    {
+        fs::create_dir_all(outdir).expect("no se pudo crear carpeta de salida");
+
+        let next = AtomicU32::new(0);
+        let progress = self.verbose.then(|| Progress::new("frames", frames as usize));
+        let workers = self.workers(frames as usize);
+
+        let work = || {
+            let mut img = Image::new(self.w, self.h);
+            let mut fb = Vec::new();
+            loop {
+                let f = next.fetch_add(1, Ordering::Relaxed);
+                if f >= frames {
+                    break;
+                }
+                let (pose, time) = (Some(camera_fn(f)), time_fn(f));
+                let frame = FrameStats::default();
+                let fc = self.frame_ctx(time, pose, self.frame_seed(time, 0), &frame);
+                let stats = self.render_into(&mut img, &mut fb, &fc, false, None, None);
+                // Cada cuadro tiene su propio nombre: no hay choques
+                let path = format!("{}/frame_{:04}.bmp", outdir, f);
+                img.save_bmp(&path);
+                self.save_sidecar(&path, self.frame_meta(time, pose, &stats));
+                println!("Saved {}", path);
+                if let Some(p) = &progress {
+                    p.tick();
+                }
+            }
+        };
+        if workers == 1 {
+            work();
+            return;
+        }
+        thread::scope(|s| {
+            for _ in 0..workers {
+                s.spawn(work);
+            }
+        });
+    }
+
+    /// Vuelta de 360° alrededor de `center` en `frames` cuadros numerados
+    /// (`frame_0000.bmp`, ...), con `render_frame` en cada ángulo y el sol
+    /// fijo a mediodía. Deja puesta la cámara del último cuadro.
+    pub fn render_turntable(
+        &mut self,
+        outdir: &str,
+        frames: u32,
+        center: Vec3,
+        radius: f64,
+        height: f64,
+        fov_deg: f64,
+    ) {
+        fs::create_dir_all(outdir).expect("no se pudo crear carpeta de salida");
+        let mut img = Image::new(self.w, self.h);
+        let poses = turntable_poses(center, radius, height, fov_deg, frames);
+        for (f, pose) in poses.iter().enumerate() {
+            self.set_camera(pose);
+            let stats = self.render_frame(&mut img, TURNTABLE_TIME);
+            let path = format!("{}/frame_{:04}.bmp", outdir, f);
+            img.save_bmp(&path);
+            self.save_sidecar(&path, self.frame_meta(TURNTABLE_TIME, None, &stats));
+            println!("Saved {}", path);
+        }
+    }
+
+    /// `render_frame` con el buffer lineal del renderer: se presta durante
+    /// el cuadro y vuelve al final, así no se reasigna w*h colores cada vez.
+    fn render_scratch(
+        &mut self,
+        img: &mut Image,
+        time: f64,
+        cancel: Option<&CancelToken>,
+    ) -> RenderStats {
+        if let (Some((ipd, mode)), Some(pose)) = (self.stereo, self.camera) {
+            return self.render_stereo(img, time, pose, (ipd, mode), cancel);
+        }
+        if self.preview_scale > 1 {
+            return self.render_preview(img, time, cancel);
+        }
+        let mut fb = std::mem::take(&mut self.scratch);
+        let stats = FrameStats::default();
+        let fc = self.frame_ctx(time, self.camera, self.frame_seed(time, 0), &stats);
+        let out = self.render_into(img, &mut fb, &fc, true, self.last_exposure, cancel);
+        drop(fc);
+        self.scratch = fb;
+        out
+    }
+
+    /// `render_scratch` a resolución reducida: el renderer se achica (w, h,
+    /// spp y región) durante el cuadro y el resultado se agranda por vecino
+    /// más cercano dentro de la región original.
+    fn render_preview(
+        &mut self,
+        img: &mut Image,
+        time: f64,
+        cancel: Option<&CancelToken>,
+    ) -> RenderStats {
+        let f = self.preview_scale;
+        let (rx0, ry0, rx1, ry1) = self.region_bounds();
+        let full = (self.w, self.h, self.spp, self.region);
+        self.w = self.w.div_ceil(f);
+        self.h = self.h.div_ceil(f);
+        self.spp = 1;
+        self.region = self.region.map(|_| (rx0 / f, ry0 / f, rx1.div_ceil(f), ry1.div_ceil(f)));
+        self.preview_scale = 1;
+
+        let mut small = Image::new(self.w, self.h);
+        let stats = self.render_scratch(&mut small, time, cancel);
+
+        self.preview_scale = f;
+        (self.w, self.h, self.spp, self.region) = full;
+        for y in ry0..ry1.min(img.h) {
+            for x in rx0..rx1.min(img.w) {
+                img.set(x, y, small.get((x / f).min(small.w - 1), (y / f).min(small.h - 1)));
+            }
+        }
+        stats
+    }
+
+    /// `render_scratch` una vez por ojo (ver `stereo_poses`) y las dos vistas
+    /// juntas en `img` según `mode`, dentro de la región. Los dos ojos usan
+    /// la misma exposición previa, así no difieren en brillo.
+    fn render_stereo(
+        &mut self,
+        img: &mut Image,
+        time: f64,
+        pose: CameraPose,
+        (ipd, mode): (f64, StereoMode),
+        cancel: Option<&CancelToken>,
+    ) -> RenderStats {
+        let (left, right) = stereo_poses(&pose, ipd);
+        let mut views = [Image::new(self.w, self.h), Image::new(self.w, self.h)];
+        self.stereo = None;
+        self.camera = Some(left);
+        let stats = self.render_scratch(&mut views[0], time, cancel);
+        self.camera = Some(right);
+        let stats = stats.combined(self.render_scratch(&mut views[1], time, cancel));
+        self.camera = Some(pose);
+        self.stereo = Some((ipd, mode));
+
+        let (x0, y0, x1, y1) = self.region_bounds();
+        let [l, r] = &views;
+        for y in y0..y1.min(img.h) {
+            for x in x0..x1 {
+                match mode {
+                    StereoMode::SideBySide => {
+                        if x + self.w < img.w {
+                            img.set(x + self.w, y, r.get(x, y));
+                        }
+                        if x < img.w {
+                            img.set(x, y, l.get(x, y));
+                        }
+                    }
+                    StereoMode::Anaglyph if x < img.w => {
+                        let (cl, cr) = (l.get(x, y), r.get(x, y));
+                        img.set(x, y, Color::new(cl.x, cr.y, cr.z));
+                    }
+                    StereoMode::Anaglyph => {}
+                }
+            }
+        }
+        stats
+    }
+
+    /// Cuadro completo de `fc` hasta `img`, usando `fb` como buffer lineal.
+    fn render_into(
+        &self,
+        img: &mut Image,
+        fb: &mut Vec<Color>,
+        fc: &FrameCtx,
+        parallel_tiles: bool,
+        prev_exposure: Option<f64>,
+        cancel: Option<&CancelToken>,
+    ) -> RenderStats {
+        let frame_start = Instant::now();
+        self.render_linear_into(fb, fc, parallel_tiles, cancel);
+        if !cancel.is_some_and(|c| c.is_cancelled()) {
+            self.denoise(fb, fc.time, fc.camera, parallel_tiles, fc.stats);
+        }
+        self.resolve(img, fb, fc.time, prev_exposure, fc.stats, frame_start)
+    }
+
+    /// Semilla del cuadro en `time`. `pass` decorrelaciona el jitter entre
+    /// pasadas acumuladas (0 = la de siempre).
+    fn frame_seed(&self, time: f64, pass: u64) -> u64 {
+        let frame_seed = mix_seed(self.seed, time.to_bits());
+        if pass == 0 {
+            frame_seed
+        } else {
+            mix_seed(frame_seed, pass)
+        }
+    }
+
+    /// Radiancia lineal del frame en `fb_data`, antes de exposición y
+    /// tonemap. El buffer se vacía y se redimensiona primero.
+    fn render_linear_into(
+        &self,
+        fb_data: &mut Vec<Color>,
+        fc: &FrameCtx,
+        parallel_tiles: bool,
+        cancel: Option<&CancelToken>,
+    ) {
+        let adaptive = self.adaptive.filter(|_| self.spp > 1 && self.debug == DebugMode::Off);
+        let first = if adaptive.is_some() {
+            SamplePlan::First
+        } else {
+            SamplePlan::All
+        };
+
+        fb_data.clear();
+        fb_data.resize(self.w * self.h, Color::new(0.0, 0.0, 0.0));
+        let tilesz = self.tilesz;
+        let tiles = |x0, y0| fc.render_tile(x0, y0, tilesz, first);
+        self.run_tiles(fb_data, parallel_tiles, "  tiles", cancel, tiles, |_, px, c| *px = c);
+
+        // Adaptativo: el resto del presupuesto solo donde la primera muestra
+        // varía respecto de sus vecinos
+        let mut refined = None;
+        if let Some(threshold) = adaptive {
+            let lum: Vec<f64> = fb_data.iter().map(|&c| luminance(self.tonemap(c))).collect();
+            let mask = adaptive_mask(&lum, self.w, self.h, threshold);
+            let refine = SamplePlan::Refine(&mask);
+            let spp = self.spp as f64;
+            let tiles = |x0, y0| fc.render_tile(x0, y0, tilesz, refine);
+            self.run_tiles(fb_data, parallel_tiles, "  refine", cancel, tiles, |_, px, sum| {
+                *px = (*px + sum) / spp
+            });
+            refined = Some(mask);
+        }
+
+        // Siluetas: muestras extra donde cambia la geometría
+        if self.edge_aa > 0 && self.debug == DebugMode::Off {
+            let ids: Vec<Option<(usize, f64)>> = self
+                .center_hits(fc, parallel_tiles)
+                .into_iter()
+                .map(|h| h.map(|(_, t, mat_id)| (mat_id, t)))
+                .collect();
+            let mask = edge_mask(&ids, self.w, fc.region);
+            let extra = SamplePlan::Extra(&mask, self.edge_aa);
+            let (spp, edge_aa) = (self.spp as f64, self.edge_aa as f64);
+            let tiles = |x0, y0| fc.render_tile(x0, y0, tilesz, extra);
+            self.run_tiles(fb_data, parallel_tiles, "  edges", cancel, tiles, |i, px, sum| {
+                let taken = match &refined {
+                    Some(m) if !m[i] => 1.0,
+                    _ => spp,
+                };
+                *px = (*px * taken + sum) / (taken + edge_aa);
+            });
+        }
+    }
+
+    /// Renderiza todos los tiles y vuelca cada píxel en `buf` con
+    /// `merge(índice, píxel, valor)`. El buffer se parte en los tramos de
+    /// fila de cada tile (`chunks_mut`), así cada tile escribe solo en los
+    /// suyos sin locks por píxel; los hilos toman tiles de una cola común
+    /// a medida que terminan. Cada tile se renderiza de forma independiente,
+    /// así que el resultado no depende de qué hilo lo tomó ni de cuándo.
+    fn run_tiles<T, V, F, M>(
+        &self,
+        buf: &mut [T],
+        parallel_tiles: bool,
+        label: &'static str,
+        cancel: Option<&CancelToken>,
+        tile_fn: F,
+        merge: M,
+    ) where
+        T: Send,
+        F: Fn(usize, usize) -> Vec<(usize, usize, V)> + Sync,
+        M: Fn(usize, &mut T, V) + Sync,
+    {
+        let (w, tilesz) = (self.w, self.tilesz);
+        let ntiles_x = w.div_ceil(tilesz);
+        let ntiles = ntiles_x * self.h.div_ceil(tilesz);
+
+        // Con tiles en serie (render_sequence) el avance se reporta por frame
+        let progress = (self.verbose && parallel_tiles).then(|| Progress::new(label, ntiles));
+
+        // Tramos de fila de cada tile, en orden de y: rows[y - y0][x - x0]
+        let mut tiles: Vec<Vec<&mut [T]>> = (0..ntiles).map(|_| Vec::new()).collect();
+        for (y, row) in buf.chunks_mut(w).enumerate() {
+            for (tx, seg) in row.chunks_mut(tilesz).enumerate() {
+                tiles[(y / tilesz) * ntiles_x + tx].push(seg);
+            }
+        }
+
+        let render_tile = |i: usize, mut rows: Vec<&mut [T]>| {
+            // cancelado: los tiles que faltan se saltan enteros
+            if cancel.is_some_and(|c| c.is_cancelled()) {
+                return;
+            }
+            let (x0, y0) = ((i % ntiles_x) * tilesz, (i / ntiles_x) * tilesz);
+            for (x, y, v) in tile_fn(x0, y0) {
+                merge(y * w + x, &mut rows[y - y0][x - x0], v);
+            }
+            if let Some(p) = &progress {
+                p.tick();
+            }
+        };
+
+        let workers = if parallel_tiles { self.workers(ntiles) } else { 1 };
+        if workers > 1 {
+            // Cola de tiles: cada hilo saca el siguiente al terminar el suyo,
+            // así los tiles caros (suelo) no se juntan en unos pocos hilos
+            let queue = Mutex::new(tiles.into_iter().enumerate());
+            thread::scope(|s| {
+                for _ in 0..workers {
+                    s.spawn(|| loop {
+                        let next = queue.lock().unwrap().next();
+                        let Some((i, rows)) = next else { break };
+                        render_tile(i, rows);
+                    });
+                }
+            });
+        } else {
+            for (i, rows) in tiles.into_iter().enumerate() {
+                render_tile(i, rows);
+            }
+        }
+    }
+
+    /// Post-pasada à-trous sobre el buffer lineal, guiada por normales y
+    /// profundidad de un rayo por el centro de cada píxel.
+    fn denoise(
+        &self,
+        fb: &mut Vec<Color>,
+        time: f64,
+        camera: Option<CameraPose>,
+        parallel_tiles: bool,
+        stats: &FrameStats,
+    ) {
+        if self.denoise_iterations == 0 || self.debug != DebugMode::Off {
+            return;
+        }
+        let fc = self.frame_ctx(time, camera, 0, stats);
+        let mut guides = Guides {
+            normal: vec![Vec3::new(0.0, 0.0, 0.0); self.w * self.h],
+            depth: vec![f64::INFINITY; self.w * self.h],
+        };
+        for (i, hit) in self.center_hits(&fc, parallel_tiles).into_iter().enumerate() {
+            if let Some((n, t, _)) = hit {
+                guides.normal[i] = n;
+                guides.depth[i] = t;
+            }
+        }
+        *fb = atrous(fb, &guides, self.w, self.h, self.denoise_iterations);
+    }
+
+    /// Normal, distancia y material del rayo por el centro de cada píxel
+    /// (None = cielo o fuera de la región).
+    fn center_hits(&self, fc: &FrameCtx, parallel_tiles: bool) -> Vec<Option<(Vec3, f64, usize)>> {
+        let tilesz = self.tilesz;
+        let mut hits = vec![None; self.w * self.h];
+        let tiles = |x0, y0| fc.guide_tile(x0, y0, tilesz);
+        self.run_tiles(&mut hits, parallel_tiles, "  guides", None, tiles, |_, px, hit| {
+            *px = Some(hit)
+        });
+        hits
+    }
+
+    /// La SoA de voxels, si corresponde a `self.scene` (sin `set_scene`,
+    /// p. ej. en los tests, queda vacía y se usan los voxels directo).
+    fn voxel_soa(&self) -> Option<&VoxelSoa> {
+        let n = self.scene.as_ref()?.voxels.len();
+        (self.voxel_soa.len() == n).then_some(&self.voxel_soa)
+    }
+
+    fn frame_ctx<'a>(
+        &'a self,
+        time: f64,
+        camera: Option<CameraPose>,
+        seed: u64,
+        stats: &'a FrameStats,
+    ) -> FrameCtx<'a> {
+        let scene = self.scene.as_ref();
+        // voxels animados en su posición de este frame: solo se tocan sus
+        // índices (en una copia de los voxels y de la SoA)
+        let moved: Vec<(usize, Voxel)> =
+            scene.map(|s| s.animated_voxels(time).collect()).unwrap_or_default();
+        let mut voxels = Cow::Borrowed(scene.map_or(&[][..], |s| &s.voxels[..]));
+        let mut voxel_soa = self.voxel_soa().map(Cow::Borrowed);
+        for (i, v) in &moved {
+            voxels.to_mut()[*i] = v.clone();
+            if let Some(soa) = voxel_soa.as_mut() {
+                soa.to_mut().set(*i, v);
+            }
+        }
+        FrameCtx {
+            w: self.w,
+            h: self.h,
+            spp: self.spp,
+            camera,
+            tex_cache: &self.tex_cache,
+            skybox_cache: &self.skybox_cache,
+            lights: &self.lights,
+            light_grid: &self.light_grid,
+            use_procedural_sky: self.use_procedural_sky,
+            background: self.background,
+            time,
+            seed,
+            sun_dir: self.dn.sun_direction(time),
+            sun_intensity: self.dn.sun_intensity(time),
+            sun_color: self.dn.sun_color(time),
+            sky_color: self.dn.sky_color(time),
+            sky_cache: SkyCache {
+                physical: self.dn.physical_sky(time),
+                ..SkyCache::new(
+                    self.dn.sky_color(time),
+                    self.dn.sun_direction(time),
+                    self.dn.sun_color(time),
+                    self.sun_disk,
+                )
+            },
+            sun_spread: SUN_SPREAD * self.dn.sun_softness(),
+            ambient_level: self.dn.ambient_level(time),
+            gi: match self.gi {
+                gi if self.reference && gi.max_depth == 0 => {
+                    GiSettings { max_depth: REFERENCE_GI_DEPTH, ..gi }
+                }
+                gi => gi,
+            },
+            contact_hardening: self.contact_hardening,
+            reference: self.reference,
+            min_light: if self.reference {
+                0.0
+            } else {
+                self.dn.ambient_level(time) * self.min_light
+            },
+            caustics: self.caustics,
+            area_sun: self.area_sun.filter(|_| self.sun_source == SunSource::Emissive),
+            shading: self.shading,
+            ground_ambient: self.ground_ambient,
+            debug: self.debug,
+            region: self.region_bounds(),
+            jitter: self.spp > 1 || self.edge_aa > 0,
+            sample_pattern: self.sample_pattern,
+            ao_radius: self.ao_radius,
+            sky_occlusion: self.sky_occlusion,
+            anisotropic: self.anisotropic,
+            tex_mips: &self.tex_mips,
+            pixel_aspect: self.pixel_aspect,
+            clip: self.clip,
+            max_portal_jumps: self.max_portal_jumps,
+            primary_visible: match (scene, camera) {
+                (Some(scene), Some(pose)) if self.frustum_cull => {
+                    let aspect = self.w as f64 / self.h as f64 * self.pixel_aspect;
+                    let frustum = Frustum::new(&pose, aspect, self.clip.1);
+                    let mut vis = PrimaryVisible::new(scene, &frustum);
+                    for (i, v) in &moved {
+                        vis.voxels[*i] = frustum.intersects_aabb(v.min, v.max);
+                    }
+                    Some(vis)
+                }
+                _ => None,
+            },
+            scene,
+            voxels,
+            voxel_soa,
+            // la semilla del frame corre la textura: otro patrón por frame
+            blue_noise: self.blue_noise.then(|| {
+                let n = BLUE_NOISE_SIZE as u64;
+                ((seed % n) as usize, ((seed >> 32) % n) as usize)
+            }),
+            stats,
+            timing: self.timing,
+        }
+    }
+
+    /// Exposición, tonemap y gamma de un buffer lineal hacia `img`.
+    fn resolve(
+        &self,
+        img: &mut Image,
+        fb_data: &[Color],
+        time: f64,
+        prev_exposure: Option<f64>,
+        stats: &FrameStats,
+        frame_start: Instant,
+    ) -> RenderStats {
+        let tonemap_start = Instant::now();
+        // Un NaN llegaría al tonemap y a la exposición: se limpia en una
+        // copia, solo si hace falta
+        let finite = |c: &Color| c.x.is_finite() && c.y.is_finite() && c.z.is_finite();
+        let mut clean = Vec::new();
+        let mut non_finite = 0;
+        let fb_data = if fb_data.iter().all(finite) {
+            fb_data
+        } else {
+            clean.extend_from_slice(fb_data);
+            non_finite = sanitize(&mut clean);
+            eprintln!("Aviso: {} componentes NaN/Inf en el frame, puestos a 0", non_finite);
+            &clean
+        };
+        let (rx0, ry0, rx1, ry1) = self.region_bounds();
+        let exposure = match self.auto_exposure {
+            Some(key) => {
+                let e = if self.region.is_some() {
+                    let inside: Vec<Color> = (ry0..ry1)
+                        .flat_map(|y| (rx0..rx1).map(move |x| fb_data[y * self.w + x]))
+                        .collect();
+                    exposure_for(&inside, key)
+                } else {
+                    exposure_for(fb_data, key)
+                };
+                smooth_exposure(prev_exposure, e, self.exposure_smoothing)
+            }
+            None => 1.0,
+        };
+        for y in ry0..ry1 {
+            for x in rx0..rx1 {
+                let idx = y * self.w + x;
+                let mut out = fb_data[idx];
+                if self.debug != DebugMode::Off {
+                    img.set(x, y, out);
+                    continue;
+                }
+                if self.auto_exposure.is_some() {
+                    out = out * exposure;
+                }
+                out = self.tonemap(out);
+                img.set(x, y, self.encode_output(out));
+            }
+        }
+        if self.debug == DebugMode::Off && self.region.is_some() {
+            // los efectos leen vecinos: se aplican a una copia y solo se
+            // devuelve la región
+            let mut full = Image { w: img.w, h: img.h, data: img.data.clone() };
+            self.post_process(&mut full, time);
+            for y in ry0..ry1 {
+                for x in rx0..rx1 {
+                    img.set(x, y, full.get(x, y));
+                }
+            }
+        } else if self.debug == DebugMode::Off {
+            self.post_process(img, time);
+        }
+
+        let ms = |t: Instant| t.elapsed().as_secs_f64() * 1e3;
+        RenderStats {
+            exposure,
+            non_finite,
+            ..stats.finish(ms(tonemap_start), ms(frame_start))
+        }
+    }
+
+    /// Efectos de lente sobre la imagen final (ya con tonemap y gamma).
+    fn post_process(&self, img: &mut Image, time: f64) {
+        if self.chromatic_aberration > 0.0 {
+            *img = chromatic_aberration(img, self.chromatic_aberration);
+        }
+        if self.grain > 0.0 {
+            // Con la semilla del frame: grano animado pero reproducible
+            film_grain(img, self.grain, mix_seed(self.seed, time.to_bits()));
+        }
+        if self.dither {
+            dither(img);
+        }
+    }
+
+    /// Último paso antes de escribir el píxel: gamma, salvo en salida lineal.
+    fn encode_output(&self, c: Color) -> Color {
+        if self.linear_output {
+            c
+        } else {
+            encode_gamma(c, self.gamma)
+        }
+    }
+}
+
+/* ====================== Sombreado por frame ====================== */
+
+/// Todo lo que un tile necesita para sombrear un frame (solo lectura).
+struct FrameCtx<'a> {
+    w: usize,
+    h: usize,
+    spp: usize,
+    scene: Option<&'a Scene>,
+    camera: Option<CameraPose>,
+    tex_cache: &'a [Option<Tex>],
+    /// Voxels de la escena en este cuadro: prestados, o copiados con los
+    /// animados movidos
+    voxels: Cow<'a, [Voxel]>,
+    /// SoA de `voxels` (None sin `set_scene`)
+    voxel_soa: Option<Cow<'a, VoxelSoa>>,
+    skybox_cache: &'a [Option<Tex>; 6],
+    lights: &'a [Light],
+    light_grid: &'a LightGrid,
+    use_procedural_sky: bool,
+    background: Option<Color>,
+    time: f64,
+    /// Semilla del frame; cada píxel deriva la suya de aquí
+    seed: u64,
+    sun_dir: Vec3,
+    sun_intensity: f64,
+    sun_color: Color,
+    sky_color: Color,
+    sky_cache: SkyCache,
+    /// Apertura base de las muestras del sol (más ancha con cielo cubierto)
+    sun_spread: f64,
+    ambient_level: f64,
+    gi: GiSettings,
+    contact_hardening: bool,
+    /// Modo referencia (ver `Renderer::set_reference_mode`)
+    reference: bool,
+    /// Piso de luz que se suma a todo (albedo * min_light)
+    min_light: f64,
+    caustics: f64,
+    /// Luz de área con `SunSource::Emissive` (None = sol analítico)
+    area_sun: Option<AreaSun>,
+    shading: ShadingModel,
+    ground_ambient: Color,
+    debug: DebugMode,
+    /// Píxeles a renderizar (x0, y0, x1, y1), ya recortados al frame
+    region: (usize, usize, usize, usize),
+    /// Jitter sub-píxel en las muestras de cámara
+    jitter: bool,
+    sample_pattern: SamplePattern,
+    ao_radius: f64,
+    /// Rayos hacia el cielo por impacto para el ambiente (0 = sin oclusión)
+    sky_occlusion: usize,
+    /// Razón máxima del filtrado anisotrópico (0 = muestreo nearest)
+    anisotropic: f64,
+    tex_mips: &'a [Vec<Tex>],
+    pixel_aspect: f64,
+    clip: (f64, f64),
+    max_portal_jumps: usize,
+    /// Objetos que pueden ver los rayos de cámara (None = todos)
+    primary_visible: Option<PrimaryVisible>,
+    /// Desplazamiento de la textura de ruido azul (None = ruido blanco)
+    blue_noise: Option<(usize, usize)>,
+    stats: &'a FrameStats,
+    timing: bool,
+}
+
+impl FrameCtx<'_> {
+    /// Contexto de trazado del cuadro sobre `scene` (la de este instante).
+    fn trace_ctx<'s>(&'s self, scene: &'s Scene) -> TraceCtx<'s> {
+        TraceCtx {
+            voxels: &self.voxels,
+            voxel_soa: self.voxel_soa.as_deref(),
+            ..TraceCtx::new(scene, self.tex_cache, self.time)
+        }
+    }
+
+    /// Píxeles del tile según `plan`. Con `Refine` solo devuelve los píxeles
+    /// marcados, con la suma (no el promedio) de sus muestras extra.
+    fn render_tile(
+        &self,
+        x0: usize,
+        y0: usize,
+        tilesz: usize,
+        plan: SamplePlan,
+    ) -> Vec<(usize, usize, Color)> {
+        let (x0, y0, x1, y1) = self.clip_tile(x0, y0, tilesz);
+
+        let mut tile_colors: Vec<(usize, usize, Color)> =
+            Vec::with_capacity((x1 - x0) * (y1 - y0));
+
+        let (Some(scene), Some(pose)) = (self.scene, self.camera) else {
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    tile_colors.push((x, y, self.fallback_sky(y)));
+                }
+            }
+            return tile_colors;
+        };
+
+        let heat = Cell::new(0u64);
+        let mut ctx = self.trace_ctx(scene);
+        if self.debug == DebugMode::RayHeat {
+            ctx.isect_tests = Some(&heat);
+        }
+
+        let mut ts = TileStats::default();
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let pixel_seed = mix_seed(self.seed, (y * self.w + x) as u64);
+                let c = match plan {
+                    SamplePlan::All => {
+                        heat.set(0);
+                        let mut rng = Rng::new(pixel_seed);
+                        let n = self.spp;
+                        let c = self.sample_pixel(&ctx, &pose, (x, y), &mut rng, 0..n, &mut ts)
+                            / n as f64;
+                        if self.debug == DebugMode::RayHeat {
+                            heat_color(heat.get())
+                        } else {
+                            c
+                        }
+                    }
+                    SamplePlan::First => {
+                        let mut rng = Rng::new(pixel_seed);
+                        self.sample_pixel(&ctx, &pose, (x, y), &mut rng, 0..1, &mut ts)
+                    }
+                    SamplePlan::Refine(mask) => {
+                        if !mask[y * self.w + x] {
+                            continue;
+                        }
+                        let mut rng = Rng::new(mix_seed(pixel_seed, 1));
+                        let n = self.spp;
+                        self.sample_pixel(&ctx, &pose, (x, y), &mut rng, 1..n, &mut ts)
+                    }
+                    SamplePlan::Extra(mask, n) => {
+                        if !mask[y * self.w + x] {
+                            continue;
                         }
+                        let mut rng = Rng::new(mix_seed(pixel_seed, 2));
+                        let s0 = self.spp;
+                        self.sample_pixel(&ctx, &pose, (x, y), &mut rng, s0..s0 + n, &mut ts)
                     }
+                };
+                tile_colors.push((x, y, c));
+            }
+        }
+        self.stats.add(&ts);
+        tile_colors
+    }
+
+    /// Parte del tile que cae dentro de la región (puede quedar vacía).
+    fn clip_tile(&self, x0: usize, y0: usize, tilesz: usize) -> (usize, usize, usize, usize) {
+        let (rx0, ry0, rx1, ry1) = self.region;
+        let x1 = (x0 + tilesz).min(rx1);
+        let y1 = (y0 + tilesz).min(ry1);
+        let x0 = x0.max(rx0).min(x1);
+        let y0 = y0.max(ry0).min(y1);
+        (x0, y0, x1.max(x0), y1.max(y0))
+    }
+
+    /// Normal, distancia y material del impacto por el centro de cada píxel
+    /// del tile (guías del denoiser y del antialias de siluetas). El cielo
+    /// no aparece.
+    fn guide_tile(
+        &self,
+        x0: usize,
+        y0: usize,
+        tilesz: usize,
+    ) -> Vec<(usize, usize, (Vec3, f64, usize))> {
+        let (x0, y0, x1, y1) = self.clip_tile(x0, y0, tilesz);
+        let mut out = Vec::with_capacity((x1 - x0) * (y1 - y0));
+        let (Some(scene), Some(pose)) = (self.scene, self.camera) else {
+            return out;
+        };
+        let ctx = self.trace_ctx(scene);
+        let mut ts = TileStats::default();
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let (sx, sy) = (x as f64 + 0.5, y as f64 + 0.5);
+                let ray =
+                    make_primary_ray(sx, sy, self.w, self.h, self.pixel_aspect, self.clip, &pose);
+                ts.primary_rays += 1;
+                if let Some(hit) = self.trace_primary(&ray, &ctx).hit {
+                    out.push((x, y, (hit.n.normalized(), hit.t, hit.mat_id)));
+                }
+            }
+        }
+        self.stats.add(&ts);
+        out
+    }
+
+    /// Impacto de un rayo de cámara, saltando lo que quedó fuera del frustum
+    /// y cruzando portales.
+    fn trace_primary(&self, ray: &Ray, ctx: &TraceCtx) -> PortalWalk {
+        let vis = self.primary_visible.as_ref();
+        portal_walk(ray, ctx, vis, self.max_portal_jumps)
+    }
+
+    /// Suma de las muestras `samples` (índices dentro del píxel) de (x, y).
+    fn sample_pixel(
+        &self,
+        ctx: &TraceCtx,
+        pose: &CameraPose,
+        (x, y): (usize, usize),
+        rng: &mut Rng,
+        samples: Range<usize>,
+        ts: &mut TileStats,
+    ) -> Color {
+        let mut color_acc = Color::new(0.0, 0.0, 0.0);
+
+        for s in samples {
+            // Jitter sub-píxel (con 1 spp y sin antialias de siluetas, centro
+            // del píxel)
+            let pattern = pattern_offset(self.sample_pattern, s, self.spp);
+            let (jx, jy) = match (self.jitter, pattern, self.blue_noise) {
+                (false, _, _) => (0.5, 0.5),
+                (true, Some(o), _) => o,
+                (true, None, Some(offset)) => pixel_jitter(x, y, s, offset),
+                (true, None, None) => (rng.next_f64(), rng.next_f64()),
+            };
+            let (sx, sy) = (x as f64 + jx, y as f64 + jy);
+            let ray = make_primary_ray(sx, sy, self.w, self.h, self.pixel_aspect, self.clip, pose);
+
+            ts.primary_rays += 1;
+            let walk = timed(self.timing, &mut ts.primary_ns, || self.trace_primary(&ray, ctx));
+            let (ray, mut hit) = (walk.ray, walk.hit);
+            if let Some(h) = hit.as_mut().filter(|_| self.anisotropic > 0.0) {
+                // rayos al píxel vecino en x y en y: solo se cortan con el
+                // plano del impacto, no se trazan (pasan por los mismos
+                // portales)
+                let (w, h_px, aspect, clip) = (self.w, self.h, self.pixel_aspect, self.clip);
+                let rx = walk.carry(&make_primary_ray(sx + 1.0, sy, w, h_px, aspect, clip, pose));
+                let ry = walk.carry(&make_primary_ray(sx, sy + 1.0, w, h_px, aspect, clip, pose));
+                h.uv_grad = uv_gradients(ctx, h, &rx, &ry);
+            }
+            color_acc = color_acc
+                + match (hit, self.debug) {
+                    // RayHeat sombrea normal: el costo incluye las sombras
+                    (Some(hit), DebugMode::Off | DebugMode::RayHeat) => {
+                        self.shade_hit(ctx, &ray, &hit, rng, ts)
+                    }
+                    (None, DebugMode::Off | DebugMode::RayHeat) => self.sky(&ray, y),
+                    (hit, mode) => debug_color(mode, ctx, hit.as_ref()),
+                };
+        }
+
+        color_acc
+    }
+
+    fn shade_hit(
+        &self,
+        ctx: &TraceCtx,
+        ray: &Ray,
+        hit: &HitInfo,
+        rng: &mut Rng,
+        ts: &mut TileStats,
+    ) -> Color {
+        let albedo = self.albedo_at(ctx, hit);
+        let mat = &ctx.scene.materials[hit.mat_id];
+        let view = (-ray.d).normalized();
+        let mut nrm = hit.n.normalized();
+        if mat.double_sided && nrm.dot(view) < 0.0 {
+            nrm = -nrm;
+        }
+        let pbr = (self.shading == ShadingModel::Pbr).then(|| PbrSurface::new(mat, albedo));
+        let base = pbr.map_or(albedo, |s| s.ambient_albedo());
+
+        // luz solar
+        let mut sun_contribution = Color::new(0.0, 0.0, 0.0);
+        if let Some(area) = &self.area_sun {
+            // puntos al azar de la caja emisiva, caída 1/d²
+            let mut lit = Color::new(0.0, 0.0, 0.0);
+            for _ in 0..SUN_SAMPLES {
+                let to = area.sample(rng) - hit.p;
+                let d2 = to.dot(to).max(1e-6);
+                let l = to / d2.sqrt();
+                let nl = nrm.dot(l);
+                if nl <= 0.0 {
+                    continue;
+                }
+                let shadow = Ray::new(hit.p + nrm * 1e-4, l);
+                // solo hasta la cara de la caja: no se tapa a sí misma
+                let reach = ray_box_intersect(&shadow, area.min, area.max, 1e9)
+                    .map_or(d2.sqrt(), |(t, _)| t)
+                    - 1e-4;
+                ts.shadow_rays += 1;
+                let vis = timed(self.timing, &mut ts.shadow_ns, || {
+                    shadow_transmittance(&shadow, ctx, reach)
+                });
+                let brdf = match &pbr {
+                    Some(s) => hadamard(vis, s.eval(nrm, view, l)),
+                    None => hadamard(vis, albedo) * nl,
+                };
+                lit = lit + brdf * (area.area / d2);
+            }
+            sun_contribution = hadamard(area.radiance, lit) / SUN_SAMPLES as f64;
+        } else if self.sun_intensity > 0.0 {
+            let samples = if self.reference { REFERENCE_SUN_SAMPLES } else { SUN_SAMPLES };
+            let spread = self.sun_spread(ctx, hit.p, nrm, ts);
+            let mut sun_lit = Color::new(0.0, 0.0, 0.0);
+            for i in 0..samples {
+                let l = if self.reference {
+                    sun_random_dir(self.sun_dir, spread, rng)
+                } else {
+                    sun_sample_dir(self.sun_dir, i, spread)
+                };
+                let nl = nrm.dot(l).max(0.0);
+                if nl > 0.0 {
+                    let eps = 1e-4;
+                    let shadow = Ray::new(hit.p + nrm * eps, l);
+                    ts.shadow_rays += 1;
+                    let vis = timed(self.timing, &mut ts.shadow_ns, || {
+                        shadow_transmittance(&shadow, ctx, 1e6)
+                    });
+                    sun_lit = sun_lit
+                        + match &pbr {
+                            Some(s) => hadamard(vis, s.eval(nrm, view, l)),
+                            None => vis * nl,
+                        };
+                }
+            }
+            sun_lit = sun_lit / samples as f64;
+            if self.caustics > 0.0 {
+                let caustic = self.caustic(ctx, hit.p, nrm, ts);
+                sun_lit = sun_lit + pbr.map_or(caustic, |s| hadamard(s.diffuse, caustic));
+            }
+
+            // con PBR el albedo ya va dentro del BRDF
+            sun_contribution = match pbr {
+                Some(_) => hadamard(self.sun_color, sun_lit) * self.sun_intensity,
+                None => hadamard(hadamard(albedo, self.sun_color), sun_lit) * self.sun_intensity,
+            };
+        }
+
+        // ambiente: GI por path tracing o hemisférico
+        let ambient = if self.gi.max_depth > 0 {
+            let (d, _pdf) = sample_cosine_hemisphere(nrm, rng);
+            let bounce = Ray::new(hit.p + nrm * 1e-4, d);
+            hadamard(base, self.path_radiance(ctx, &bounce, rng, ts))
+        } else {
+            let mut sky_up = self.sky_color;
+            if self.sky_occlusion > 0 {
+                ts.shadow_rays += self.sky_occlusion as u64;
+                sky_up = sky_up
+                    * timed(self.timing, &mut ts.shadow_ns, || {
+                        sky_visibility(hit.p, nrm, ctx, self.sky_occlusion, rng)
+                    });
+            }
+            let k_hemi = (nrm.y * 0.5 + 0.5).clamp(0.0, 1.0);
+            let hemi = sky_up * k_hemi + self.ground_ambient * (1.0 - k_hemi);
+            hadamard(base, hemi) * self.ambient_level
+        };
+
+        // AO (en referencia ya la da la GI)
+        let ao = if self.reference {
+            1.0
+        } else {
+            ts.shadow_rays += AO_DIRS.len() as u64;
+            timed(self.timing, &mut ts.shadow_ns, || ao_term(hit.p, nrm, ctx, self.ao_radius))
+        };
+
+        // especular solar (con PBR ya está en el BRDF)
+        let mut specular = Color::new(0.0, 0.0, 0.0);
+        if pbr.is_none() && mat.specular > 0.0 && self.sun_intensity > 0.0 {
+            let mut sun_vec = self.sun_dir;
+            if sun_vec.y < 0.1 {
+                sun_vec.y = 0.1;
+            }
+            let fade = (self.sun_intensity / SPEC_FULL_SUN).min(1.0);
+            let spec_factor = blinn_phong(nrm, view, sun_vec, mat) * fade;
+            specular = hadamard(self.sun_color, albedo) * spec_factor;
+        }
+
+        // luces emisivas
+        let mut lights_sum = Color::new(0.0, 0.0, 0.0);
+        // solo las luces cuyo alcance llega al punto
+        for light in self.light_grid.near(hit.p).map(|i| &self.lights[i]) {
+            let to_l = light.pos - hit.p;
+            let dist = to_l.length();
+            let ldir = to_l / dist;
+
+            let nl = nrm.dot(ldir).max(0.0);
+            if nl <= 0.0 {
+                continue;
+            }
+
+            let eps = 1e-4;
+            let shadow = Ray::new(hit.p + nrm * eps, ldir);
+            ts.shadow_rays += 1;
+            let vis = timed(self.timing, &mut ts.shadow_ns, || {
+                shadow_transmittance(&shadow, ctx, dist - eps)
+            });
+            if vis.x <= 0.0 && vis.y <= 0.0 && vis.z <= 0.0 {
+                continue;
+            }
+
+            let falloff = (1.0 - (dist / LIGHT_RANGE).min(1.0)).max(0.0);
+            let atten = falloff * falloff;
+
+            let flicker = light.flicker(self.time);
+
+            let radiance = light.color * (light.intensity * flicker);
+            let contrib = match &pbr {
+                Some(s) => {
+                    hadamard(hadamard(s.eval(nrm, view, ldir), vis), radiance) * (atten * 0.8)
+                }
+                None => hadamard(hadamard(albedo, vis), radiance) * (nl * atten * 0.8),
+            };
+            lights_sum = lights_sum + contrib;
+        }
+
+        // paneles de luz: un punto al azar por muestra, así la penumbra se
+        // resuelve con las spp
+        for panel in &ctx.scene.area_lights {
+            let q = panel.point(rng.next_f64(), rng.next_f64());
+            let to = q - hit.p;
+            let d2 = to.dot(to).max(1e-6);
+            let dist = d2.sqrt();
+            let l = to / dist;
+            let (nl, cos_light) = (nrm.dot(l), -panel.normal().dot(l));
+            if nl <= 0.0 || cos_light <= 0.0 {
+                continue;
+            }
+            let shadow = Ray::new(hit.p + nrm * 1e-4, l);
+            ts.shadow_rays += 1;
+            let vis = timed(self.timing, &mut ts.shadow_ns, || {
+                shadow_transmittance(&shadow, ctx, dist - 1e-4)
+            });
+            let brdf = match &pbr {
+                Some(s) => hadamard(vis, s.eval(nrm, view, l)),
+                None => hadamard(vis, albedo) * nl,
+            };
+            let geom = cos_light * panel.area() / d2;
+            lights_sum = lights_sum + hadamard(panel.radiance(), brdf) * geom;
+        }
+
+        let mut c = (ambient + sun_contribution + lights_sum + specular) * ao;
+
+        c = c + (base * self.min_light);
+
+        // Espejo plano (agua). El rayo reflejado sube, así que no puede
+        // volver a dar de frente en una cara superior: no hay recursión.
+        if mat.planar_reflection > 0.0 {
+            if let Some(r) = planar_reflection_ray(ray, hit) {
+                let r = glossy_reflection(r, mat.reflection_roughness, rng);
+                ts.bounce_rays += 1;
+                let seen = match trace_scene(&r, ctx) {
+                    Some(h) => self.shade_hit(ctx, &r, &h, rng, ts),
+                    None => self.sky_radiance(r.d),
+                };
+                let k = (mat.planar_reflection * fresnel_schlick(-ray.d.dot(nrm), mat.ior))
+                    .clamp(0.0, 1.0);
+                c = c * (1.0 - k) + seen * k;
+            }
+        }
+
+        // Vidrio de color sin refracción (ior ~1): el rayo sigue derecho y
+        // lo de atrás se mezcla teñido por el albedo
+        let tau = mat.transparency.min(1.0);
+        if tau > 0.0 && (mat.ior - 1.0).abs() < BLEND_IOR_EPS {
+            let through = Ray::new(hit.p + ray.d * 1e-4, ray.d);
+            ts.bounce_rays += 1;
+            let behind = match trace_scene(&through, ctx) {
+                Some(h) => self.shade_hit(ctx, &through, &h, rng, ts),
+                None => self.sky_radiance(through.d),
+            };
+            c = c * (1.0 - tau) + hadamard(behind, albedo) * tau;
+        }
+        c
+    }
+
+    /// Apertura de las muestras del sol en `p`. Con contact hardening se
+    /// buscan oclusores con la apertura máxima (como PCSS) y la apertura
+    /// crece con su distancia media: penumbra nítida en el contacto y
+    /// difusa lejos de él.
+    fn sun_spread(&self, ctx: &TraceCtx, p: Vec3, nrm: Vec3, ts: &mut TileStats) -> f64 {
+        if !self.contact_hardening {
+            return self.sun_spread;
+        }
+        let mut sum = 0.0;
+        let mut count = 0;
+        for i in 0..SUN_SAMPLES {
+            let l = sun_sample_dir(self.sun_dir, i, self.sun_spread * CONTACT_MAX_SCALE);
+            if nrm.dot(l) <= 0.0 {
+                continue;
+            }
+            let probe = Ray::new(p + nrm * 1e-4, l);
+            ts.shadow_rays += 1;
+            let hit = timed(self.timing, &mut ts.shadow_ns, || trace_closest(&probe, ctx, false));
+            if let Some(h) = hit {
+                sum += h.t;
+                count += 1;
+            }
+        }
+        if count == 0 {
+            return self.sun_spread;
+        }
+        let d = sum / count as f64;
+        self.sun_spread * (d / CONTACT_REF_DIST).clamp(CONTACT_MIN_SCALE, CONTACT_MAX_SCALE)
+    }
+
+    /// Cáustica aproximada: si el rayo al sol cruza una superficie
+    /// transparente, se refracta ahí la luz del sol (con la normal ondulada
+    /// si el material está animado, como el agua) y se aclara el punto en
+    /// la medida en que el rayo refractado apunta hacia él. No conserva
+    /// energía; es solo un realce controlado por `caustics`.
+    fn caustic(&self, ctx: &TraceCtx, p: Vec3, nrm: Vec3, ts: &mut TileStats) -> Color {
+        let black = Color::new(0.0, 0.0, 0.0);
+        let l = self.sun_dir.normalized();
+        let nl = nrm.dot(l);
+        if nl <= 0.0 {
+            return black;
+        }
+        let probe = Ray::new(p + nrm * 1e-4, l);
+        ts.shadow_rays += 1;
+        let hit = timed(self.timing, &mut ts.shadow_ns, || trace_closest(&probe, ctx, false));
+        let Some(hit) = hit else {
+            return black;
+        };
+        let mat = &ctx.scene.materials[hit.mat_id];
+        if mat.transparency <= 0.0 {
+            return black;
+        }
+
+        // Normal de la superficie del lado del sol
+        let mut n = hit.n.normalized();
+        if n.dot(l) < 0.0 {
+            n = -n;
+        }
+        if mat.animated_uv {
+            n = wave_normal(n, hit.p, self.time);
+        }
+        let Some(t) = refract(-l, n, 1.0 / mat.ior.max(1.0)) else {
+            return black;
+        };
+        // Cuánto apunta la luz refractada hacia p
+        let focus = t.dot(-l).max(0.0).powf(CAUSTIC_SHARPNESS);
+
+        // Lo que queda de camino hasta el sol (otras capas, oclusores)
+        let rest = Ray::new(hit.p + l * 1e-3, l);
+        ts.shadow_rays += 1;
+        let vis = timed(self.timing, &mut ts.shadow_ns, || shadow_transmittance(&rest, ctx, 1e6));
+        let tint = clamp01(mat.albedo) * mat.transparency.min(1.0);
+        hadamard(vis, tint) * (self.caustics * focus * nl)
+    }
+
+    /// Albedo en el impacto: material por textura.
+    fn albedo_at(&self, ctx: &TraceCtx, hit: &HitInfo) -> Color {
+        let mat = &ctx.scene.materials[hit.mat_id];
+        let mut albedo = clamp01(hadamard(mat.albedo, hit.tint));
+        if let Some(tex) = tex_for_mat(hit.mat_id, ctx.tex_cache) {
+            if mat.triplanar > 0.0 {
+                let texel = sample_tex_triplanar(tex, hit.p, hit.n, mat.triplanar);
+                return clamp01(hadamard(albedo, texel));
+            }
+            let (u, v) = material_uv(mat, hit.u, hit.v, ctx.time);
+            let mips = self.tex_mips.get(hit.mat_id).map_or(&[][..], |m| &m[..]);
+            let texel = match hit.uv_grad {
+                Some(g) if self.anisotropic > 0.0 => {
+                    let k = if mat.uv_scale.is_finite() { mat.uv_scale } else { 1.0 };
+                    let g = [g[0] * k, g[1] * k, g[2] * k, g[3] * k];
+                    sample_tex_aniso(tex, mips, u, v, g, self.anisotropic)
+                }
+                _ => sample_tex_nearest(tex, u, v),
+            };
+            albedo = clamp01(hadamard(albedo, texel));
+        }
+        albedo
+    }
+
+    /// Radiancia que llega por `ray` en el modo GI: emisión + sol directo en
+    /// cada vértice y cielo al escapar. Tras `min_bounces` rebotes el camino
+    /// sigue con probabilidad q = max(throughput) y se divide entre q, así
+    /// que cortar no sesga el resultado.
+    fn path_radiance(
+        &self,
+        ctx: &TraceCtx,
+        ray: &Ray,
+        rng: &mut Rng,
+        ts: &mut TileStats,
+    ) -> Color {
+        let mut radiance = Color::new(0.0, 0.0, 0.0);
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+        let mut ray = *ray;
+
+        for bounce in 1..=self.gi.max_depth {
+            ts.bounce_rays += 1;
+            let Some(hit) = trace_scene(&ray, ctx) else {
+                radiance = radiance + hadamard(throughput, self.sky_radiance(ray.d));
+                break;
+            };
+            let mat = &ctx.scene.materials[hit.mat_id];
+            let albedo = self.albedo_at(ctx, &hit);
+            let mut n = hit.n.normalized();
+            if n.dot(ray.d) > 0.0 {
+                n = -n;
+            }
+
+            let direct = hadamard(albedo, self.sun_direct(ctx, hit.p, n, ts));
+            radiance = radiance + hadamard(throughput, mat.emission() + direct);
+            if bounce == self.gi.max_depth {
+                break;
+            }
+
+            throughput = hadamard(throughput, albedo);
+            if bounce >= self.gi.min_bounces {
+                let q = throughput.x.max(throughput.y).max(throughput.z).min(1.0);
+                if q <= 0.0 || rng.next_f64() >= q {
+                    break;
+                }
+                throughput = throughput / q;
+            }
+
+            // Muestreo por coseno: el peso del rebote es solo el albedo
+            let (d, _pdf) = sample_cosine_hemisphere(n, rng);
+            ray = Ray::new(hit.p + n * 1e-4, d);
+        }
+        radiance
+    }
+
+    /// Sol directo sobre un vértice de rebote: una muestra, sin especular.
+    fn sun_direct(&self, ctx: &TraceCtx, p: Vec3, n: Vec3, ts: &mut TileStats) -> Color {
+        let nl = n.dot(self.sun_dir);
+        if self.sun_intensity <= 0.0 || nl <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        ts.shadow_rays += 1;
+        let shadow = Ray::new(p + n * 1e-4, self.sun_dir);
+        let vis = timed(self.timing, &mut ts.shadow_ns, || {
+            shadow_transmittance(&shadow, ctx, 1e6)
+        });
+        hadamard(vis, self.sun_color) * (nl * self.sun_intensity)
+    }
+
+    /// Cielo visto por un rebote: sin disco ni halo solar, que ya entran
+    /// como luz directa.
+    fn sky_radiance(&self, d: Vec3) -> Color {
+        if self.use_procedural_sky {
+            self.sky_cache.gradient(d)
+        } else {
+            let (face, su, sv) = dir_to_cube_uv(d);
+            match &self.skybox_cache[face] {
+                Some(tex) => sample_tex_nearest(tex, su, sv),
+                None => self.background.unwrap_or(self.sky_color),
+            }
+        }
+    }
+
+    /// miss: cielo procedural, skybox, fondo liso o degradado de respaldo
+    fn sky(&self, ray: &Ray, y: usize) -> Color {
+        if self.use_procedural_sky {
+            let sky = self.sky_cache.gradient(ray.d);
+
+            // Fuera del halo el disco y el brillo son cero: sin acos
+            let dp = ray.d.dot(self.sun_dir).clamp(-1.0, 1.0);
+            if dp < self.sky_cache.glow_cos {
+                return sky;
+            }
+            let ang = dp.acos();
+            let sc = &self.sky_cache;
+            let sun_disk = (sc.disk_radius - ang).max(0.0) * 80.0;
+            let sun_glow = (sc.glow_radius - ang).max(0.0) * 1.5;
+            sky + sc.disk_color * (sun_disk + sun_glow) * self.sun_intensity
+        } else {
+            let (face, su, sv) = dir_to_cube_uv(ray.d);
+            match &self.skybox_cache[face] {
+                Some(tex) => sample_tex_nearest(tex, su, sv),
+                None => self.background.unwrap_or_else(|| self.fallback_sky(y)),
+            }
+        }
+    }
+
+    /// Degradado vertical a partir del color del cielo
+    fn fallback_sky(&self, y: usize) -> Color {
+        let v = y as f64 / (self.h - 1).max(1) as f64;
+        Color::new(
+            self.sky_color.x * (1.0 - v * 0.3),
+            self.sky_color.y * (1.0 - v * 0.3),
+            self.sky_color.z,
+        )
+    }
+}
+
+/* ====================== Depuración ====================== */
+
+/// Ancho de arista del wireframe: fracción de la altura del triángulo, o
+/// del lado menor de la cara del voxel (con tope en unidades de mundo).
+const WIRE_WIDTH: f64 = 0.04;
+
+fn debug_color(mode: DebugMode, ctx: &TraceCtx, hit: Option<&HitInfo>) -> Color {
+    match (mode, hit) {
+        (DebugMode::Off | DebugMode::RayHeat, _) => WIRE_FILL,
+        (DebugMode::Wireframe, Some(h)) if near_edge(ctx, h) => WIRE_EDGE,
+        (DebugMode::Wireframe, _) => WIRE_FILL,
+        (DebugMode::Normals, Some(h)) => normal_color(h.n),
+        (DebugMode::Depth { near, far }, Some(h)) => depth_color(h.t, near, far),
+        (DebugMode::Normals | DebugMode::Depth { .. }, None) => SKY_SENTINEL,
+    }
+}
+
+/// true si el impacto cae cerca de una arista de su primitiva.
+fn near_edge(ctx: &TraceCtx, hit: &HitInfo) -> bool {
+    match hit.prim {
+        // Las baricéntricas son la distancia a cada arista sobre la altura
+        Prim::Tri(_) => {
+            let b0 = 1.0 - hit.u - hit.v;
+            b0.min(hit.u).min(hit.v) < WIRE_WIDTH
+        }
+        Prim::Voxel(i) => {
+            let v = &ctx.voxels[i];
+            let n = hit.n;
+            let size = v.max - v.min;
+            let lo = hit.p - v.min;
+            let hi = v.max - hit.p;
+            // Las dos coordenadas sobre el plano de la cara
+            let axes: [(f64, f64, f64); 2] = if n.x.abs() > 0.5 {
+                [(lo.y, hi.y, size.y), (lo.z, hi.z, size.z)]
+            } else if n.y.abs() > 0.5 {
+                [(lo.x, hi.x, size.x), (lo.z, hi.z, size.z)]
+            } else {
+                [(lo.x, hi.x, size.x), (lo.y, hi.y, size.y)]
+            };
+            let w = (WIRE_WIDTH * axes[0].2.min(axes[1].2)).min(WIRE_WIDTH);
+            axes.iter().any(|&(a, b, _)| a.min(b) < w)
+        }
+        // las UV de una malla instanciada siguen siendo baricéntricas; de un
+        // voxel instanciado no se guarda cuál fue
+        Prim::Sphere(_) | Prim::Plane(_) => false,
+        Prim::Instance(i) => match ctx.scene.instances[i].base {
+            InstanceKind::Mesh(_) => {
+                let b0 = 1.0 - hit.u - hit.v;
+                b0.min(hit.u).min(hit.v) < WIRE_WIDTH
+            }
+            InstanceKind::Voxels(_) => false,
+        },
+    }
+}
+
+/* ====================== Helpers de Ray Tracing ====================== */
+
+#[derive(Clone, Copy)]
+struct HitInfo {
+    t: f64,
+    p: Vec3,
+    n: Vec3,
+    mat_id: usize,
+    /// Tinte del voxel o color de vértice del triángulo (`NO_TINT` si no
+    /// tiene)
+    tint: Color,
+    /// UV geométricas (sin uv_scale ni animación; ver `material_uv`)
+    u: f64,
+    v: f64,
+    prim: Prim,
+    /// (du/dx, dv/dx, du/dy, dv/dy) de esas UV por píxel de pantalla; solo
+    /// en rayos de cámara con filtrado anisotrópico
+    uv_grad: Option<[f64; 4]>,
+}
+
+/// Primitiva impactada (índice en `Scene::voxels`, `Scene::triangles`,
+/// `Scene::spheres`, `Scene::planes` o `Scene::instances`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Prim {
+    Voxel(usize),
+    Tri(usize),
+    Sphere(usize),
+    Plane(usize),
+    Instance(usize),
+}
+
+/// `sx`, `sy` en coordenadas de píxel continuas (x + 0.5 es el centro).
+/// `pixel_aspect` es ancho/alto de cada píxel en la pantalla final (1 =
+/// píxeles cuadrados); `(near, far)` recorta el rayo.
+fn make_primary_ray(
+    sx: f64,
+    sy: f64,
+    w: usize,
+    h: usize,
+    pixel_aspect: f64,
+    (near, far): (f64, f64),
+    cam: &CameraPose,
+) -> Ray {
+    let aspect = w as f64 / h as f64 * pixel_aspect;
+    let fov = cam.fov_deg.to_radians();
+    let scale = (fov * 0.5).tan();
+
+    let px = (2.0 * (sx / w as f64) - 1.0) * aspect * scale;
+    let py = (1.0 - 2.0 * (sy / h as f64)) * scale;
+
+    let forward = (cam.target - cam.eye).normalized();
+    let right = forward.cross(cam.up).normalized();
+    let up = right.cross(forward).normalized();
+
+    let dir = (forward + right * px + up * py).normalized();
+
+    let mut ray = Ray::new(cam.eye, dir);
+    ray.tmin = near;
+    ray.tmax = far;
+    ray
+}
+
+/// Poses de los ojos izquierdo y derecho: `cam` corrida `ipd / 2` a cada
+/// lado sobre su eje derecho (el mismo de `make_primary_ray`), con el
+/// objetivo corrido igual para que los ejes queden paralelos.
+fn stereo_poses(cam: &CameraPose, ipd: f64) -> (CameraPose, CameraPose) {
+    let right = (cam.target - cam.eye).normalized().cross(cam.up).normalized();
+    let half = right * (ipd * 0.5);
+    let shifted = |d: Vec3| CameraPose { eye: cam.eye + d, target: cam.target + d, ..*cam };
+    (shifted(-half), shifted(half))
+}
+
+/// Con `visible`, solo prueba los voxels marcados. Con `soa` las cajas se
+/// leen de ahí, como en `voxels_block`.
+fn trace_voxels(
+    ray: &Ray,
+    voxels: &[Voxel],
+    soa: Option<&VoxelSoa>,
+    ctx: &TraceCtx,
+    visible: Option<&[bool]>,
+) -> Option<HitInfo> {
+    let mut closest_t = ray.tmax;
+    let mut best: Option<HitInfo> = None;
+    let inv_d = inv_dir(ray.d);
+
+    for i in 0..voxels.len() {
+        if visible.is_some_and(|m| !m[i]) {
+            continue;
+        }
+        ctx.count_tests(1);
+        let (min, max) = match soa {
+            Some(s) => s.bounds(i),
+            None => (voxels[i].min, voxels[i].max),
+        };
+        if let Some((t0, t1)) = ray_box_intersect_inv(ray, inv_d, min, max, closest_t) {
+            if t0 < closest_t && t0 > ray.tmin {
+                if let Some(hit) = voxel_hit(ray, i, &voxels[i], t0, t1, ctx) {
+                    if hit.t < closest_t {
+                        closest_t = hit.t;
+                        best = Some(hit);
+                    }
+                }
+            }
+        }
+    }
+    best
+}
+
+/// Impacto contra un voxel: la cara de entrada, o la de salida (vista desde
+/// dentro) si la entrada quedó recortada por alfa. Así la copa del árbol
+/// muestra sus hojas interiores en vez de un agujero.
+fn voxel_hit(
+    ray: &Ray,
+    idx: usize,
+    v: &Voxel,
+    t0: f64,
+    t1: f64,
+    ctx: &TraceCtx,
+) -> Option<HitInfo> {
+    for (t, inside) in [(t0, false), (t1, true)] {
+        let p = ray.at(t);
+        // al salir (t1) la cara es la que el rayo cruza hacia afuera
+        let n = voxel_normal_at(p, v.min, v.max, if inside { -ray.d } else { ray.d });
+        let (u, uv_v) = voxel_uv(v.min, v.max, p, n);
+        if !cut_out(v.mat_id, u, uv_v, ctx) {
+            return Some(HitInfo {
+                t,
+                p,
+                n: if inside { -n } else { n },
+                mat_id: v.mat_id,
+                tint: v.tint,
+                u,
+                v: uv_v,
+                prim: Prim::Voxel(idx),
+                uv_grad: None,
+            });
+        }
+    }
+    None
+}
+
+/// Con `cull_backfaces`, descarta triángulos cuya normal mira en la misma
+/// dirección que el rayo (caras traseras de mallas cerradas).
+fn trace_triangles(
+    ray: &Ray,
+    tris: &[Tri],
+    ctx: &TraceCtx,
+    cull_backfaces: bool,
+) -> Option<HitInfo> {
+    let mut closest_t = ray.tmax;
+    let mut best: Option<HitInfo> = None;
+
+    for (i, tri) in tris.iter().enumerate() {
+        if cull_backfaces && ray.d.dot(tri.n) >= 0.0 {
+            continue;
+        }
+        ctx.count_tests(1);
+        if let Some((t, b1, b2)) = ray_triangle_intersect(ray, tri, closest_t) {
+            if cut_out(tri.mat_id, b1, b2, ctx) {
+                continue;
+            }
+            closest_t = t;
+            best = Some(HitInfo {
+                t,
+                p: ray.at(t),
+                n: tri.n,
+                mat_id: tri.mat_id,
+                tint: tri.color_at(b1, b2),
+                u: b1,
+                v: b2,
+                prim: Prim::Tri(i),
+                uv_grad: None,
+            });
+        }
+    }
+    best
+}
+
+/// Con el origen dentro de una esfera el impacto es la salida, con la
+/// normal exterior. Las esferas no tienen tinte.
+fn trace_spheres(ray: &Ray, spheres: &[Sphere], ctx: &TraceCtx) -> Option<HitInfo> {
+    let mut closest_t = ray.tmax;
+    let mut best: Option<HitInfo> = None;
+
+    for (i, s) in spheres.iter().enumerate() {
+        ctx.count_tests(1);
+        if let Some(t) = ray_sphere_intersect(ray, s, closest_t) {
+            let p = ray.at(t);
+            let n = s.normal_at(p);
+            let (u, v) = Sphere::uv(n);
+            if cut_out(s.mat_id, u, v, ctx) {
+                continue;
+            }
+            closest_t = t;
+            best = Some(HitInfo {
+                t,
+                p,
+                n,
+                mat_id: s.mat_id,
+                tint: NO_TINT,
+                u,
+                v,
+                prim: Prim::Sphere(i),
+                uv_grad: None,
+            });
+        }
+    }
+    best
+}
+
+/// Visto desde abajo, la normal se da vuelta hacia el rayo: el plano es
+/// de dos caras, como una hoja.
+fn trace_planes(ray: &Ray, planes: &[Plane], ctx: &TraceCtx) -> Option<HitInfo> {
+    let mut closest_t = ray.tmax;
+    let mut best: Option<HitInfo> = None;
+
+    for (i, pl) in planes.iter().enumerate() {
+        ctx.count_tests(1);
+        if let Some(t) = ray_plane_intersect(ray, pl, closest_t) {
+            let p = ray.at(t);
+            let (u, v) = Plane::uv(p);
+            if cut_out(pl.mat_id, u, v, ctx) {
+                continue;
+            }
+            closest_t = t;
+            best = Some(HitInfo {
+                t,
+                p,
+                n: if ray.d.dot(pl.normal) > 0.0 { -pl.normal } else { pl.normal },
+                mat_id: pl.mat_id,
+                tint: NO_TINT,
+                u,
+                v,
+                prim: Prim::Plane(i),
+                uv_grad: None,
+            });
+        }
+    }
+    best
+}
+
+/// Impacto más cercano entre voxels y triángulos (rayos de cámara: respeta
+/// `Scene::cull_backfaces`).
+fn trace_scene(ray: &Ray, ctx: &TraceCtx) -> Option<HitInfo> {
+    trace_closest(ray, ctx, ctx.scene.cull_backfaces)
+}
+
+fn trace_closest(ray: &Ray, ctx: &TraceCtx, cull_backfaces: bool) -> Option<HitInfo> {
+    trace_visible(ray, ctx, cull_backfaces, None)
+}
+
+/// `trace_closest` limitado a los objetos de `vis` (None = todos).
+fn trace_visible(
+    ray: &Ray,
+    ctx: &TraceCtx,
+    cull_backfaces: bool,
+    vis: Option<&PrimaryVisible>,
+) -> Option<HitInfo> {
+    let vis_voxels = vis.map(|v| &v.voxels[..]);
+    let best = trace_voxels(ray, ctx.voxels, ctx.voxel_soa, ctx, vis_voxels);
+    let mut r = *ray;
+    if let Some(h) = &best {
+        r.tmax = h.t;
+    }
+    let best = if vis.is_none_or(|v| v.mesh) {
+        trace_triangles(&r, &ctx.scene.triangles, ctx, cull_backfaces).or(best)
+    } else {
+        best
+    };
+    if let Some(h) = &best {
+        r.tmax = h.t;
+    }
+    let best = trace_spheres(&r, &ctx.scene.spheres, ctx).or(best);
+    if let Some(h) = &best {
+        r.tmax = h.t;
+    }
+    let best = trace_planes(&r, &ctx.scene.planes, ctx).or(best);
+    if let Some(h) = &best {
+        r.tmax = h.t;
+    }
+    trace_instances(&r, ctx, cull_backfaces, vis.map(|v| &v.instances[..])).or(best)
+}
+
+/// Un rayo de cámara después de cruzar portales.
+struct PortalWalk {
+    /// Último tramo, ya en el marco del último portal
+    ray: Ray,
+    hit: Option<HitInfo>,
+    jumps: usize,
+    /// Composición de los `Portal::transform` cruzados
+    xform: Mat4,
+}
+
+impl PortalWalk {
+    /// `r` llevado al marco final (para rayos vecinos del mismo píxel).
+    fn carry(&self, r: &Ray) -> Ray {
+        let d = self.xform.transform_vector(r.d);
+        Ray { o: self.xform.transform_point(r.o), d: d.normalized(), ..*r }
+    }
+}
+
+/// Traza `ray` y, si antes del impacto entra en un portal, sigue desde la
+/// salida con el rayo transformado, hasta `max_jumps` veces. Un portal que
+/// contiene el origen del tramo no cuenta: el rayo está saliendo de él.
+/// `vis` (frustum) solo vale para el primer tramo.
+fn portal_walk(
+    ray: &Ray,
+    ctx: &TraceCtx,
+    mut vis: Option<&PrimaryVisible>,
+    max_jumps: usize,
+) -> PortalWalk {
+    let mut walk = PortalWalk { ray: *ray, hit: None, jumps: 0, xform: Mat4::identity() };
+    loop {
+        let r = walk.ray;
+        walk.hit = trace_visible(&r, ctx, ctx.scene.cull_backfaces, vis);
+        if walk.jumps >= max_jumps {
+            return walk;
+        }
+        let limit = walk.hit.as_ref().map_or(r.tmax, |h| h.t);
+        let entry = ctx
+            .scene
+            .portals
+            .iter()
+            .filter(|p| !p.contains(r.o))
+            .filter_map(|p| ray_box_intersect(&r, p.min, p.max, limit).map(|(t, _)| (t, p)))
+            .min_by(|a, b| a.0.total_cmp(&b.0));
+        let Some((t, portal)) = entry else {
+            return walk;
+        };
+        let m = portal.transform();
+        let mut next = Ray::new(m.transform_point(r.at(t)), m.transform_vector(r.d));
+        next.tmax = r.tmax - t;
+        walk.ray = next;
+        walk.xform = m * walk.xform;
+        walk.jumps += 1;
+        vis = None;
+    }
+}
+
+/// Impacto más cercano entre las instancias: cada una se traza en su espacio
+/// local y el impacto vuelve a mundo (distancia, punto y normal).
+fn trace_instances(
+    ray: &Ray,
+    ctx: &TraceCtx,
+    cull_backfaces: bool,
+    visible: Option<&[bool]>,
+) -> Option<HitInfo> {
+    let mut best: Option<HitInfo> = None;
+    let mut r = *ray;
+    for (i, inst) in ctx.scene.instances.iter().enumerate() {
+        if visible.is_some_and(|m| !m[i]) {
+            continue;
+        }
+        let (local, s) = to_local(&r, inst);
+        let hit = match &inst.base {
+            InstanceKind::Voxels(v) => trace_voxels(&local, v, None, ctx, None),
+            InstanceKind::Mesh(t) => trace_triangles(&local, t, ctx, cull_backfaces),
+        };
+        if let Some(h) = hit {
+            let t = h.t / s;
+            r.tmax = t;
+            best = Some(HitInfo {
+                t,
+                p: ray.at(t),
+                n: inst.inverse().transform_normal_transposed(h.n),
+                prim: Prim::Instance(i),
+                uv_grad: None,
+                ..h
+            });
+        }
+    }
+    best
+}
+
+/// true si el material recorta este punto por alfa y el rayo debe seguir.
+fn cut_out(mat_id: usize, u: f64, v: f64, ctx: &TraceCtx) -> bool {
+    let mat = &ctx.scene.materials[mat_id];
+    if mat.alpha_cutout <= 0.0 {
+        return false;
+    }
+    match tex_for_mat(mat_id, ctx.tex_cache) {
+        Some(tex) => {
+            let (u, v) = material_uv(mat, u, v, ctx.time);
+            sample_alpha_nearest(tex, u, v) < mat.alpha_cutout
+        }
+        None => false,
+    }
+}
+
+/// Aplica uv_scale y la animación del material a las UV geométricas.
+fn material_uv(mat: &Material, u: f64, v: f64, time: f64) -> (f64, f64) {
+    let uvscale = if mat.uv_scale.is_finite() {
+        mat.uv_scale
+    } else {
+        1.0
+    };
+    let (mut u, mut v) = (u * uvscale, v * uvscale);
+    if mat.animated_uv {
+        u = (u + time * 0.2).fract();
+        v = v.fract();
+    }
+    (u, v)
+}
+
+/// Normal de la cara de la caja más cercana a `p` entre las que miran
+/// contra la dirección `d` del rayo.
+fn voxel_normal_at(p: Vec3, min: Vec3, max: Vec3, d: Vec3) -> Vec3 {
+    let faces = [
+        ((p.x - min.x).abs(), Vec3::new(-1.0, 0.0, 0.0)),
+        ((p.x - max.x).abs(), Vec3::new(1.0, 0.0, 0.0)),
+        ((p.y - min.y).abs(), Vec3::new(0.0, -1.0, 0.0)),
+        ((p.y - max.y).abs(), Vec3::new(0.0, 1.0, 0.0)),
+        ((p.z - min.z).abs(), Vec3::new(0.0, 0.0, -1.0)),
+        ((p.z - max.z).abs(), Vec3::new(0.0, 0.0, 1.0)),
+    ];
+    // Solo caras por las que el rayo pudo entrar: en una esquina rozada la
+    // más cercana puede ser una vecina que el rayo ni mira
+    let nearest = |enterable: bool| {
+        faces
+            .iter()
+            .filter(|(_, n)| !enterable || d.dot(*n) < 0.0)
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|&(_, n)| n)
+    };
+    nearest(true).or_else(|| nearest(false)).unwrap_or(faces[0].1)
+}
+
+/* ====================== Skybox mapping ====================== */
+
+fn dir_to_cube_uv(d: Vec3) -> (usize, f64, f64) {
+    let ax = d.x.abs();
+    let ay = d.y.abs();
+    let az = d.z.abs();
+
+    let (face, sc, tc, ma) = if ax >= ay && ax >= az {
+        if d.x > 0.0 {
+            (0usize, -d.z, -d.y, ax)
+        } else {
+            (1usize, d.z, -d.y, ax)
+        }
+    } else if ay >= ax && ay >= az {
+        if d.y > 0.0 {
+            (2usize, d.x, d.z, ay)
+        } else {
+            (3usize, d.x, -d.z, ay)
+        }
+    } else if d.z > 0.0 {
+        (4usize, d.x, -d.y, az)
+    } else {
+        (5usize, -d.x, -d.y, az)
+    };
+
+    let u = 0.5 * (sc / ma + 1.0);
+    let v = 0.5 * (tc / ma + 1.0);
+    (face, u, v)
+}
+
+/* ====================== Texturas ====================== */
+
+fn load_tex(path: &str, max_size: Option<usize>) -> Option<Tex> {
+    let tex = match image::open(path) {
+        Ok(img) => {
+            let img = img.to_rgba8();
+            let (w, h) = img.dimensions();
+            Tex {
+                w: w as usize,
+                h: h as usize,
+                data: img.into_raw(),
+            }
+        }
+        // BMP 24-bit con nuestro lector (respeta filas top-down y bottom-up)
+        Err(_) => bmp_tex(&Image::load_bmp(path).ok()?),
+    };
+    Some(match max_size {
+        Some(m) if tex.w.max(tex.h) > m => downscale_tex(&tex, m),
+        _ => tex,
+    })
+}
+
+/// Textura a partir de píxeles RGB en memoria; None si `data` no mide
+/// `w * h * 3` o la imagen está vacía.
+fn rgb_tex(w: usize, h: usize, data: &[u8], max_size: Option<usize>) -> Option<Tex> {
+    if w == 0 || h == 0 || data.len() != w * h * 3 {
+        return None;
+    }
+    let data = data.chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], 255]).collect();
+    let tex = Tex { w, h, data };
+    Some(match max_size {
+        Some(m) if tex.w.max(tex.h) > m => downscale_tex(&tex, m),
+        _ => tex,
+    })
+}
+
+fn bmp_tex(img: &Image) -> Tex {
+    let to_u8 = |c: f64| (c * 255.0).round() as u8;
+    let data = (0..img.h)
+        .flat_map(|y| (0..img.w).map(move |x| img.get(x, y)))
+        .flat_map(|c| [to_u8(c.x), to_u8(c.y), to_u8(c.z), 255])
+        .collect();
+    Tex { w: img.w, h: img.h, data }
+}
+
+/// Reduce `tex` para que su lado mayor sea `max_size`, promediando cajas
+/// de texels. El color se promedia en lineal (si no, los bordes claros y
+/// oscuros se oscurecen); el alfa tal cual.
+fn downscale_tex(tex: &Tex, max_size: usize) -> Tex {
+    let k = max_size as f64 / tex.w.max(tex.h) as f64;
+    let nw = ((tex.w as f64 * k).round() as usize).max(1);
+    let nh = ((tex.h as f64 * k).round() as usize).max(1);
+    let to_lin = |c: u8| (c as f64 / 255.0).powf(2.2);
+    let to_srgb = |c: f64| (c.powf(1.0 / 2.2) * 255.0).round() as u8;
+
+    let mut data = Vec::with_capacity(nw * nh * 4);
+    for y in 0..nh {
+        let (y0, y1) = (y * tex.h / nh, ((y + 1) * tex.h).div_ceil(nh));
+        for x in 0..nw {
+            let (x0, x1) = (x * tex.w / nw, ((x + 1) * tex.w).div_ceil(nw));
+            let mut sum = [0.0; 4];
+            for sy in y0..y1 {
+                for sx in x0..x1 {
+                    let px = &tex.data[(sy * tex.w + sx) * 4..][..4];
+                    for c in 0..3 {
+                        sum[c] += to_lin(px[c]);
+                    }
+                    sum[3] += px[3] as f64 / 255.0;
+                }
+            }
+            let n = ((y1 - y0) * (x1 - x0)) as f64;
+            data.extend(sum[..3].iter().map(|&c| to_srgb(c / n)));
+            data.push((sum[3] / n * 255.0).round() as u8);
+        }
+    }
+    Tex { w: nw, h: nh, data }
+}
+
+fn texel_index(tex: &Tex, mut u: f64, mut v: f64) -> usize {
+    u = u.fract();
+    if u < 0.0 {
+        u += 1.0;
+    }
+    v = v.fract();
+    if v < 0.0 {
+        v += 1.0;
+    }
+
+    let x = (u * tex.w as f64)
+        .floor()
+        .clamp(0.0, (tex.w - 1) as f64) as usize;
+    let y = (v * tex.h as f64)
+        .floor()
+        .clamp(0.0, (tex.h - 1) as f64) as usize;
+    (y * tex.w + x) * 4
+}
+
+fn sample_tex_nearest(tex: &Tex, u: f64, v: f64) -> Color {
+    let idx = texel_index(tex, u, v);
+    let r = tex.data[idx] as f64 / 255.0;
+    let g = tex.data[idx + 1] as f64 / 255.0;
+    let b = tex.data[idx + 2] as f64 / 255.0;
+    Color::new(r, g, b)
+}
+
+/// Peso de cada proyección (x, y, z) en el texturado triplanar: |n|^4
+/// normalizado, así las caras casi alineadas usan una sola proyección y
+/// las inclinadas mezclan sin estirarse.
+fn triplanar_weights(n: Vec3) -> [f64; 3] {
+    let w = [n.x.abs().powi(4), n.y.abs().powi(4), n.z.abs().powi(4)];
+    let sum = (w[0] + w[1] + w[2]).max(1e-12);
+    [w[0] / sum, w[1] / sum, w[2] / sum]
+}
+
+/// Textura proyectada en mundo por los tres ejes y mezclada con
+/// `triplanar_weights`; `scale` = repeticiones por unidad.
+fn sample_tex_triplanar(tex: &Tex, p: Vec3, n: Vec3, scale: f64) -> Color {
+    let q = p * scale;
+    let [wx, wy, wz] = triplanar_weights(n);
+    let mut c = Color::new(0.0, 0.0, 0.0);
+    for (w, (u, v)) in [(wx, (q.z, q.y)), (wy, (q.x, q.z)), (wz, (q.x, q.y))] {
+        if w > 1e-6 {
+            c = c + sample_tex_nearest(tex, u, v) * w;
+        }
+    }
+    c
+}
+
+fn sample_alpha_nearest(tex: &Tex, u: f64, v: f64) -> f64 {
+    tex.data[texel_index(tex, u, v) + 3] as f64 / 255.0
+}
+
+fn tex_for_mat(mat_id: usize, cache: &[Option<Tex>]) -> Option<&Tex> {
+    if mat_id < cache.len() {
+        cache[mat_id].as_ref()
+    } else {
+        None
+    }
+}
+
+/* ========== UV helper (ajusta si ya lo tienes en otro lado) ========== */
+
+fn voxel_uv(_min: Vec3, _max: Vec3, p: Vec3, n: Vec3) -> (f64, f64) {
+    let (u, v) = if n.x.abs() > n.y.abs() && n.x.abs() > n.z.abs() {
+        (p.z, p.y)
+    } else if n.y.abs() > n.z.abs() {
+        (p.x, p.z)
+    } else {
+        (p.x, p.y)
+    };
+    (u, v)
+}
+
+/* ==================== Filtrado anisotrópico ==================== */
+
+/// Derivadas de las UV geométricas de `hit` entre su rayo y los de los
+/// píxeles vecinos `rx` (x + 1) y `ry` (y + 1), cortándolos con el plano
+/// tangente del impacto. None en instancias o si un vecino va casi paralelo
+/// al plano.
+fn uv_gradients(ctx: &TraceCtx, hit: &HitInfo, rx: &Ray, ry: &Ray) -> Option<[f64; 4]> {
+    let on_plane = |r: &Ray| {
+        let dn = r.d.dot(hit.n);
+        (dn.abs() > 1e-9).then(|| r.at((hit.p - r.o).dot(hit.n) / dn))
+    };
+    let uv_at = |q: Vec3| match hit.prim {
+        Prim::Voxel(_) => Some(voxel_uv(Vec3::default(), Vec3::default(), q, hit.n)),
+        Prim::Plane(_) => Some(Plane::uv(q)),
+        Prim::Tri(i) => {
+            let tri = &ctx.scene.triangles[i];
+            let (e1, e2, w) = (tri.v1 - tri.v0, tri.v2 - tri.v0, q - tri.v0);
+            let (d11, d12, d22) = (e1.dot(e1), e1.dot(e2), e2.dot(e2));
+            let (w1, w2) = (w.dot(e1), w.dot(e2));
+            let det = d11 * d22 - d12 * d12;
+            (det.abs() > 1e-18).then(|| ((d22 * w1 - d12 * w2) / det, (d11 * w2 - d12 * w1) / det))
+        }
+        // la costura de las UV esféricas daría un salto de 1 en u
+        Prim::Sphere(_) | Prim::Instance(_) => None,
+    };
+    let (ux, vx) = uv_at(on_plane(rx)?)?;
+    let (uy, vy) = uv_at(on_plane(ry)?)?;
+    Some([ux - hit.u, vx - hit.v, uy - hit.u, vy - hit.v])
+}
+
+/// Huella de un píxel con derivadas `g` (en UV ya escaladas) sobre una
+/// textura de `w` x `h`: (nivel de mip, número de muestras, paso en UV a lo
+/// largo del eje largo). El mip sale del eje largo dividido por las
+/// muestras, así que más muestras dejan un mip más fino.
+fn aniso_footprint(g: [f64; 4], w: usize, h: usize, max_ratio: f64) -> (f64, usize, (f64, f64)) {
+    let (w, h) = (w as f64, h as f64);
+    let lx = (g[0] * w).hypot(g[1] * h);
+    let ly = (g[2] * w).hypot(g[3] * h);
+    let (major, minor, axis) = if lx >= ly {
+        (lx, ly, (g[0], g[1]))
+    } else {
+        (ly, lx, (g[2], g[3]))
+    };
+    let ratio = (major / minor.max(1e-12)).clamp(1.0, max_ratio.max(1.0));
+    let n = ratio.ceil() as usize;
+    let lod = (major / ratio).max(1.0).log2();
+    (lod, n, (axis.0 / n as f64, axis.1 / n as f64))
+}
+
+/// Mips por mitades, del nivel 1 hasta 1x1 (el nivel 0 es la textura).
+fn mip_chain(tex: &Tex) -> Vec<Tex> {
+    let mut levels: Vec<Tex> = Vec::new();
+    loop {
+        let last = levels.last().unwrap_or(tex);
+        let side = last.w.max(last.h);
+        if side <= 1 {
+            return levels;
+        }
+        let next = downscale_tex(last, side / 2);
+        levels.push(next);
+    }
+}
+
+/// Promedio de `n` muestras nearest a lo largo del eje largo de la huella,
+/// en el mip de su eje corto.
+fn sample_tex_aniso(tex: &Tex, mips: &[Tex], u: f64, v: f64, g: [f64; 4], max_ratio: f64) -> Color {
+    let (lod, n, (su, sv)) = aniso_footprint(g, tex.w, tex.h, max_ratio);
+    let level = (lod.round() as usize).min(mips.len());
+    let t = if level == 0 { tex } else { &mips[level - 1] };
+    let mut sum = Color::new(0.0, 0.0, 0.0);
+    for i in 0..n {
+        let k = i as f64 + 0.5 - n as f64 * 0.5;
+        sum = sum + sample_tex_nearest(t, u + su * k, v + sv * k);
+    }
+    sum / n as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::voxel::VoxelAnim;
+    use crate::scene::{AreaLight, Portal, SceneWarning};
+
+    fn tex_rgba(r: u8, g: u8, b: u8, a: u8) -> Tex {
+        Tex { w: 1, h: 1, data: vec![r, g, b, a] }
+    }
+
+    fn cutout_scene() -> Scene {
+        let mut scene = Scene::new();
+        scene.materials.push(
+            Material::new("leaves", Vec3::new(0.5, 0.8, 0.5), None).with_alpha_cutout(0.5),
+        );
+        scene.voxels.push(Voxel {
+            min: Vec3::new(-1.0, -1.0, 2.0),
+            max: Vec3::new(1.0, 1.0, 3.0),
+            mat_id: 0,
+            tint: NO_TINT,
+        });
+        scene
+    }
+
+    #[test]
+    fn test_alpha_cutout_pass_through_and_block() {
+        let scene = cutout_scene();
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0));
+
+        let clear = [Some(tex_rgba(255, 255, 255, 0))];
+        let ctx = TraceCtx::new(&scene, &clear, 0.0);
+        assert!(trace_scene(&ray, &ctx).is_none());
+        assert!(!blocked_along(&ray, &ctx, 10.0));
+
+        let opaque = [Some(tex_rgba(255, 255, 255, 255))];
+        let ctx = TraceCtx::new(&scene, &opaque, 0.0);
+        let hit = trace_scene(&ray, &ctx).expect("el texel opaco debe bloquear");
+        assert!((hit.t - 2.0).abs() < 1e-9);
+        assert!(blocked_along(&ray, &ctx, 10.0));
+    }
+
+    #[test]
+    fn test_repeated_frames_are_identical() {
+        let mut r = Renderer::new(48, 40, 2);
+        r.set_scene(&small_scene());
+        r.set_camera(&small_pose());
+        // Tiles chicos para que haya más tiles que hilos
+        r.tilesz = 8;
+        let mut first = Image::new(48, 40);
+        r.render_frame(&mut first, 30.0);
+        for _ in 0..10 {
+            let mut img = Image::new(48, 40);
+            r.render_frame(&mut img, 30.0);
+            assert!(img.data == first.data, "el frame cambió entre corridas");
+        }
+    }
+
+    #[test]
+    fn test_legacy_shading_is_default_and_pbr_differs() {
+        // Luminancias fijadas con el sombreado Legacy (Lambert + Blinn-Phong):
+        // piso visto desde arriba y cara lateral del bloque, a las 30.0
+        let shade = |shading: Option<ShadingModel>| {
+            let mut r = Renderer::new(24, 16, 1);
+            r.set_scene(&small_scene());
+            if let Some(m) = shading {
+                r.set_shading(m);
+            }
+            let up = Vec3::new(0.0, 1.0, 0.0);
+            let floor = Vec3::new(2.5, 0.0, 2.5);
+            let top = shade_point(&r, 30.0, &Ray::new(floor + up * 3.0, -up), floor, up, 0);
+            let x = Vec3::new(1.0, 0.0, 0.0);
+            let side = Vec3::new(1.0, 1.0, 0.0);
+            let eye = Vec3::new(5.0, 1.5, 0.5);
+            let lat = shade_point(&r, 30.0, &Ray::new(eye, (side - eye).normalized()), side, x, 1);
+            (top, lat)
+        };
+        let legacy = shade(None);
+        assert!((legacy.0 - 0.459_512_098_816_923_6).abs() < 1e-9, "{}", legacy.0);
+        assert!((legacy.1 - 0.100_141_734_884_337_95).abs() < 1e-9, "{}", legacy.1);
+        assert_eq!(shade(Some(ShadingModel::Legacy)), legacy);
+        let pbr = shade(Some(ShadingModel::Pbr));
+        assert!((pbr.0 - legacy.0).abs() > 1e-3 && (pbr.1 - legacy.1).abs() > 1e-3);
+    }
+
+    #[test]
+    fn test_cancel_token_stops_render() {
+        let mut r = Renderer::new(24, 16, 1);
+        r.set_scene(&small_scene());
+        r.set_camera(&small_pose());
+        let mut full = Image::new(24, 16);
+        r.render_frame(&mut full, 30.0);
+
+        let token = CancelToken::new();
+        let mut img = Image::new(24, 16);
+        let stats = r.render_frame_cancellable(&mut img, 30.0, &token);
+        assert!(img.data == full.data);
+        assert_eq!(stats.primary_rays, 24 * 16);
+
+        token.cancel();
+        let stats = r.render_frame_cancellable(&mut img, 30.0, &token);
+        assert_eq!(stats.primary_rays, 0);
+        assert!(img.data.iter().all(|&c| c == Color::new(0.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn test_region_leaves_outside_pixels_untouched() {
+        let mut r = Renderer::new(24, 16, 1);
+        r.set_scene(&small_scene());
+        r.set_camera(&small_pose());
+        r.tilesz = 8;
+        let mut full = Image::new(24, 16);
+        r.render_frame(&mut full, 30.0);
+
+        // la región corta tiles por la mitad
+        let (x0, y0, x1, y1) = (5, 3, 17, 11);
+        let marker = Color::new(0.25, 0.5, 0.75);
+        let mut img = Image::new(24, 16);
+        img.data.fill(marker);
+        r.set_region(Some((x0, y0, x1, y1)));
+        let stats = r.render_frame(&mut img, 30.0);
+        assert_eq!(stats.primary_rays, ((x1 - x0) * (y1 - y0)) as u64);
+        for y in 0..16 {
+            for x in 0..24 {
+                let inside = (x0..x1).contains(&x) && (y0..y1).contains(&y);
+                let want = if inside { full.get(x, y) } else { marker };
+                assert_eq!(img.get(x, y), want, "({x}, {y})");
+            }
+        }
+    }
+
+    #[test]
+    fn test_default_samples_stay_at_pixel_center() {
+        let render = |spp: usize, pattern: Option<SamplePattern>| {
+            let mut r = Renderer::new(24, 16, spp);
+            r.set_scene(&small_scene());
+            r.set_camera(&small_pose());
+            if let Some(p) = pattern {
+                r.set_sample_pattern(p);
+            }
+            let mut img = Image::new(24, 16);
+            r.render_frame(&mut img, 30.0);
+            img.data
+        };
+        // Sin elegir patrón, 4 spp dan lo mismo que 1 (todas al centro)
+        let one = render(1, None);
+        let four = render(4, None);
+        assert!(one.iter().zip(&four).all(|(a, b)| (*a - *b).length() < 1e-9));
+        assert!(render(4, Some(SamplePattern::Random)) != one);
+    }
+
+    #[test]
+    fn test_accumulation_of_identical_passes() {
+        // spp = 1: sin jitter, cada pasada es idéntica
+        let mut r = Renderer::new(24, 16, 1);
+        r.set_scene(&small_scene());
+        r.set_camera(&small_pose());
+        let mut single = Image::new(24, 16);
+        r.render_frame(&mut single, 30.0);
+
+        let mut img = Image::new(24, 16);
+        r.accumulate_frame(&mut img, 30.0);
+        r.accumulate_frame(&mut img, 30.0);
+        assert_eq!(r.accumulated_passes(), 2);
+        for (a, b) in img.data.iter().zip(&single.data) {
+            assert!((*a - *b).length() < 1e-12, "{:?} != {:?}", a, b);
+        }
+
+        // Cambiar el tiempo reinicia
+        r.accumulate_frame(&mut img, 31.0);
+        assert_eq!(r.accumulated_passes(), 1);
+        r.reset_accumulation();
+        assert_eq!(r.accumulated_passes(), 0);
+    }
+
+    #[test]
+    fn test_gi_roulette_converges_in_emissive_box() {
+        // Caja cerrada de paredes emisivas (Le = 1) con albedo 0.5: la
+        // radiancia en cualquier dirección es Le / (1 - a) = 2.
+        let mut scene = Scene::new();
+        scene.materials.push(
+            Material::new("wall", Vec3::new(0.5, 0.5, 0.5), None)
+                .with_emissive(Vec3::new(1.0, 1.0, 1.0)),
+        );
+        let (a, b) = (2.0, 2.5);
+        for axis in 0..3 {
+            for sign in [-1.0, 1.0] {
+                let mut min = Vec3::new(-b, -b, -b);
+                let mut max = Vec3::new(b, b, b);
+                let (lo, hi) = if sign < 0.0 { (-b, -a) } else { (a, b) };
+                match axis {
+                    0 => (min.x, max.x) = (lo, hi),
+                    1 => (min.y, max.y) = (lo, hi),
+                    _ => (min.z, max.z) = (lo, hi),
+                }
+                scene.voxels.push(Voxel { min, max, mat_id: 0, tint: NO_TINT });
+            }
+        }
+
+        let mut r = Renderer::new(1, 1, 1);
+        r.scene = Some(scene);
+        r.tex_cache = vec![None];
+        r.set_gi(256);
+        r.set_min_bounces(0);
+        let stats = FrameStats::default();
+        // Medianoche: sin sol
+        let fc = r.frame_ctx(0.0, None, 0, &stats);
+        let ctx = TraceCtx::new(r.scene.as_ref().unwrap(), &r.tex_cache, 0.0);
+
+        let mut rng = Rng::new(12345);
+        let mut ts = TileStats::default();
+        let n = 20_000;
+        let mut sum = 0.0;
+        for _ in 0..n {
+            let (d, _) = sample_cosine_hemisphere(Vec3::new(0.0, 1.0, 0.0), &mut rng);
+            let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), d);
+            sum += fc.path_radiance(&ctx, &ray, &mut rng, &mut ts).x;
+        }
+        let mean = sum / n as f64;
+        assert!((mean - 2.0).abs() < 0.06, "media {}", mean);
+        // La ruleta corta antes del tope
+        assert!(ts.bounce_rays < n as u64 * 10, "rebotes {}", ts.bounce_rays);
+    }
+
+    #[test]
+    fn test_cosine_hemisphere_distribution() {
+        let n = Vec3::new(0.3, 0.8, -0.2).normalized();
+        let mut rng = Rng::new(7);
+        let samples = 40_000;
+        let bins = 10;
+        let mut hist = vec![0usize; bins];
+        let mut mean = Vec3::new(0.0, 0.0, 0.0);
+        for _ in 0..samples {
+            let (d, pdf) = sample_cosine_hemisphere(n, &mut rng);
+            let c = d.dot(n);
+            assert!(c >= -1e-9, "fuera del hemisferio");
+            assert!((pdf - c / std::f64::consts::PI).abs() < 1e-9);
+            mean = mean + d;
+            // Con densidad cos, cos^2 es uniforme en [0, 1]
+            hist[((c * c * bins as f64) as usize).min(bins - 1)] += 1;
+        }
+        let mean = mean / samples as f64;
+        // E[d] = (2/3) n
+        assert!((mean - n * (2.0 / 3.0)).length() < 0.01, "media {:?}", mean);
+
+        let expected = samples as f64 / bins as f64;
+        let chi2: f64 = hist
+            .iter()
+            .map(|&o| (o as f64 - expected).powi(2) / expected)
+            .sum();
+        // 9 grados de libertad: p = 0.001 en ~27.9
+        assert!(chi2 < 27.9, "chi2 {} hist {:?}", chi2, hist);
+    }
+
+    #[test]
+    fn test_contact_hardening_penumbra_grows_with_distance() {
+        // Losa flotando en y = 6..6.2; puntos a 0.3 y a 5.5 por debajo
+        let mut scene = Scene::new();
+        scene.materials.push(Material::new("slab", Vec3::new(0.5, 0.5, 0.5), None));
+        scene.voxels.push(Voxel {
+            min: Vec3::new(-3.0, 6.0, -3.0),
+            max: Vec3::new(3.0, 6.2, 3.0),
+            mat_id: 0,
+            tint: NO_TINT,
+        });
+        let mut r = Renderer::new(1, 1, 1);
+        r.scene = Some(scene);
+        r.tex_cache = vec![None];
+        let stats = FrameStats::default();
+        let mut ts = TileStats::default();
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let near = Vec3::new(0.0, 5.7, 0.0);
+        let far = Vec3::new(0.0, 0.5, 0.0);
+
+        let mut fc = r.frame_ctx(30.0, None, 0, &stats);
+        fc.sun_dir = up;
+        let ctx = TraceCtx::new(r.scene.as_ref().unwrap(), &r.tex_cache, 30.0);
+        assert_eq!(fc.sun_spread(&ctx, near, up, &mut ts), SUN_SPREAD);
+
+        fc.contact_hardening = true;
+        let s_near = fc.sun_spread(&ctx, near, up, &mut ts);
+        let s_far = fc.sun_spread(&ctx, far, up, &mut ts);
+        assert!(s_near < SUN_SPREAD && SUN_SPREAD < s_far, "{} {}", s_near, s_far);
+        // Sin oclusor: la apertura de siempre
+        let open = Vec3::new(10.0, 0.5, 0.0);
+        assert_eq!(fc.sun_spread(&ctx, open, up, &mut ts), SUN_SPREAD);
+    }
+
+    #[test]
+    fn test_light_flicker_is_per_light_and_steady_at_zero() {
+        let torch = Material::new("torch", Vec3::new(1.0, 0.6, 0.2), None);
+        let a = Light::new(Vec3::new(1.5, 2.5, 0.5), torch.emission(), &torch);
+        let b = Light::new(Vec3::new(-4.5, 2.5, 3.5), torch.emission(), &torch);
+
+        let n = 4000;
+        let sa: Vec<f64> = (0..n).map(|i| a.flicker(i as f64 * 0.05)).collect();
+        let sb: Vec<f64> = (0..n).map(|i| b.flicker(i as f64 * 0.05)).collect();
+        let mean = |s: &[f64]| s.iter().sum::<f64>() / s.len() as f64;
+        let (ma, mb) = (mean(&sa), mean(&sb));
+        let cov: f64 = sa.iter().zip(&sb).map(|(x, y)| (x - ma) * (y - mb)).sum();
+        let var = |s: &[f64], m: f64| s.iter().map(|x| (x - m).powi(2)).sum::<f64>();
+        let corr = cov / (var(&sa, ma) * var(&sb, mb)).sqrt();
+        assert!(corr.abs() < 0.2, "correlación {}", corr);
+
+        let lamp = Material::new("lamp", Vec3::new(1.0, 1.0, 0.9), None).with_flicker(0.0, 6.0);
+        let l = Light::new(Vec3::new(0.5, 3.0, 0.5), lamp.emission(), &lamp);
+        assert!((0..100).all(|i| l.flicker(i as f64 * 0.37) == 1.0));
+    }
+
+    #[test]
+    fn test_emission_strength_scales_light_keeping_hue() {
+        let light_color = |mat: Material| {
+            let mut scene = Scene::new();
+            scene.materials.push(mat);
+            scene.voxels.push(Voxel::from_grid(0, 0, 0, 0));
+            gather_lights(&scene)[0].color
+        };
+        let hue = Vec3::new(1.0, 0.65, 0.3);
+        let torch = |k: f64| Material::new("torch", hue, None).with_emission(hue, k);
+        let (one, two) = (light_color(torch(1.0)), light_color(torch(2.0)));
+        assert_eq!(two, one * 2.0);
+        assert_eq!(one, hue);
+
+        // with_emissive sigue dando la misma luz que antes
+        let old = Material::new("torch", hue, None).with_emissive(Vec3::new(4.0, 2.6, 1.2));
+        assert_eq!(old.emissive_strength, 4.0);
+        assert!((old.emission() - Vec3::new(4.0, 2.6, 1.2)).length() < 1e-12);
+    }
+
+    #[test]
+    fn test_light_order_ignores_voxel_order() {
+        let mut scene = Scene::new();
+        scene.materials.push(Material::new("stone", Vec3::new(0.5, 0.5, 0.5), None));
+        scene.materials.push(Material::new("torch", Vec3::new(1.0, 0.8, 0.4), None)
+            .with_emissive(Vec3::new(4.0, 2.6, 1.2)));
+        for (i, j, k) in [(3, 1, 0), (0, 1, 2), (3, 0, 5), (1, 1, 1)] {
+            scene.voxels.push(Voxel::from_grid(i, j, k, 1));
+            scene.voxels.push(Voxel::from_grid(i + 10, j, k, 0));
+        }
+        let a = gather_lights(&scene);
+        scene.voxels.reverse();
+        scene.voxels.swap(1, 4);
+        let b = gather_lights(&scene);
+
+        assert_eq!(a.len(), 4);
+        for (la, lb) in a.iter().zip(&b) {
+            assert_eq!(la.pos, lb.pos);
+            assert_eq!(la.flicker_phase, lb.flicker_phase);
+        }
+        assert!(a.windows(2).all(|w| w[0].pos.x <= w[1].pos.x));
+    }
+
+    #[test]
+    fn test_light_grid_only_returns_lights_in_range() {
+        let mut scene = Scene::new();
+        scene.materials.push(Material::new("torch", Vec3::new(1.0, 0.8, 0.4), None)
+            .with_emissive(Vec3::new(4.0, 2.6, 1.2)));
+        scene.voxels.push(Voxel::from_grid(0, 2, 0, 0));
+        scene.voxels.push(Voxel::from_grid(40, 2, 0, 0));
+        scene.voxels.push(Voxel::from_grid(40, 2, 25, 0));
+        let lights = gather_lights(&scene);
+        let grid = LightGrid::new(&lights);
+
+        let near = |p: Vec3| grid.near(p).collect::<Vec<_>>();
+        assert!(near(Vec3::new(-60.0, 0.0, -60.0)).is_empty());
+        assert!(near(Vec3::new(20.0, 2.0, 12.0)).is_empty());
+        let near_first = near(Vec3::new(2.0, 1.0, 3.0));
+        assert_eq!(near_first.len(), 1);
+        assert_eq!(lights[near_first[0]].pos, Vec3::new(0.5, 2.5, 0.5));
+        // en un borde de celda sigue viendo la luz vecina
+        let edge = near(Vec3::new(38.0, 2.0, 19.9));
+        assert_eq!(edge.len(), 1);
+        assert_eq!(lights[edge[0]].pos, Vec3::new(40.5, 2.5, 25.5));
+    }
+
+    #[test]
+    fn test_caustics_brighten_floor_under_water() {
+        let mut scene = Scene::new();
+        scene.materials.push(Material::new("floor", Vec3::new(0.8, 0.8, 0.8), None));
+        scene.materials.push(
+            Material::new("water", Vec3::new(0.3, 0.55, 0.9), None).with_transparency(0.5, 1.33),
+        );
+        scene.voxels.push(Voxel {
+            min: Vec3::new(-5.0, -1.0, -5.0),
+            max: Vec3::new(5.0, 0.0, 5.0),
+            mat_id: 0,
+            tint: NO_TINT,
+        });
+        scene.voxels.push(Voxel {
+            min: Vec3::new(-1.0, 2.0, -1.0),
+            max: Vec3::new(1.0, 2.5, 1.0),
+            mat_id: 1,
+            tint: NO_TINT,
+        });
+        let mut r = Renderer::new(1, 1, 1);
+        r.scene = Some(scene);
+        r.tex_cache = vec![None, None];
+        let stats = FrameStats::default();
+        let ctx = TraceCtx::new(r.scene.as_ref().unwrap(), &r.tex_cache, 30.0);
+
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let ray = Ray::new(Vec3::new(0.0, 5.0, 0.0), -up);
+        let hit = HitInfo {
+            t: 5.0,
+            p: Vec3::new(0.0, 0.0, 0.0),
+            n: up,
+            mat_id: 0,
+            tint: NO_TINT,
+            u: 0.5,
+            v: 0.5,
+            prim: Prim::Voxel(0),
+            uv_grad: None,
+        };
+        let shade = |caustics: f64| {
+            let mut fc = r.frame_ctx(30.0, None, 0, &stats);
+            fc.sun_dir = up;
+            fc.sun_intensity = 1.0;
+            fc.caustics = caustics;
+            let mut rng = Rng::new(1);
+            let mut ts = TileStats::default();
+            fc.shade_hit(&ctx, &ray, &hit, &mut rng, &mut ts)
+        };
+        let off = shade(0.0);
+        let on = shade(2.0);
+        assert!(luminance(on) > luminance(off) * 1.2, "{:?} vs {:?}", on, off);
+    }
+
+    #[test]
+    fn test_ground_ambient_tints_downward_faces() {
+        let mut scene = Scene::new();
+        scene.materials.push(Material::new("block", Vec3::new(0.8, 0.8, 0.8), None));
+        scene.voxels.push(Voxel {
+            min: Vec3::new(-1.0, 2.0, -1.0),
+            max: Vec3::new(1.0, 3.0, 1.0),
+            mat_id: 0,
+            tint: NO_TINT,
+        });
+        let mut r = Renderer::new(1, 1, 1);
+        r.scene = Some(scene);
+        r.tex_cache = vec![None];
+        let stats = FrameStats::default();
+        let ctx = TraceCtx::new(r.scene.as_ref().unwrap(), &r.tex_cache, 30.0);
+
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let face = |y: f64, n: Vec3| {
+            let ray = Ray::new(Vec3::new(0.0, y + n.y * 5.0, 0.0), -n);
+            let hit = HitInfo {
+                t: 5.0,
+                p: Vec3::new(0.0, y, 0.0),
+                n,
+                mat_id: 0,
+                tint: NO_TINT,
+                u: 0.5,
+                v: 0.5,
+                prim: Prim::Voxel(0),
+                uv_grad: None,
+            };
+            (ray, hit)
+        };
+        let shade = |r: &Renderer, (ray, hit): &(Ray, HitInfo)| {
+            let fc = r.frame_ctx(30.0, None, 0, &stats);
+            let mut rng = Rng::new(1);
+            let mut ts = TileStats::default();
+            fc.shade_hit(&ctx, ray, hit, &mut rng, &mut ts)
+        };
+        let top = face(3.0, up);
+        let bottom = face(2.0, -up);
+        let (top0, bottom0) = (shade(&r, &top), shade(&r, &bottom));
+
+        let mut snowy = Renderer::new(1, 1, 1);
+        snowy.scene = r.scene.clone();
+        snowy.tex_cache = vec![None];
+        snowy.set_ground_ambient(Color::new(0.9, 0.9, 1.0));
+        assert_eq!(shade(&snowy, &top), top0);
+        let bottom1 = shade(&snowy, &bottom);
+        assert!(luminance(bottom1) > luminance(bottom0) * 1.5, "{:?} vs {:?}", bottom1, bottom0);
+    }
+
+    #[test]
+    fn test_voxel_tint_varies_one_material() {
+        let mut scene = Scene::new();
+        scene.materials.push(Material::new("grass", Vec3::new(0.4, 0.7, 0.3), None));
+        scene.voxels.push(Voxel::from_grid(0, 0, 0, 0));
+        scene.voxels.push(Voxel::from_grid(10, 0, 0, 0).with_tint(Vec3::new(1.0, 0.6, 0.6)));
+        let mut r = Renderer::new(1, 1, 1);
+        r.scene = Some(scene);
+        r.tex_cache = vec![None];
+        let stats = FrameStats::default();
+        let fc = r.frame_ctx(30.0, None, 0, &stats);
+        let ctx = TraceCtx::new(r.scene.as_ref().unwrap(), &r.tex_cache, 30.0);
+
+        let shade = |x: f64| {
+            let ray = Ray::new(Vec3::new(x, 5.0, 0.5), Vec3::new(0.0, -1.0, 0.0));
+            let hit = trace_scene(&ray, &ctx).expect("impacto");
+            let mut rng = Rng::new(1);
+            fc.shade_hit(&ctx, &ray, &hit, &mut rng, &mut TileStats::default())
+        };
+        let (plain, tinted) = (shade(0.5), shade(10.5));
+        assert!((plain.x - tinted.x).abs() < 1e-9, "{:?} vs {:?}", plain, tinted);
+        assert!(tinted.y < plain.y * 0.7 && tinted.z < plain.z * 0.7);
+    }
+
+    #[test]
+    fn test_alpha_blended_pane_tints_what_is_behind() {
+        let mut scene = Scene::new();
+        scene.materials.push(Material::new("wall", Vec3::new(0.9, 0.9, 0.9), None));
+        scene.materials.push(Material::new("pane", Vec3::new(0.2, 0.3, 1.0), None)
+            .with_transparency(0.5, 1.0));
+        scene.voxels.push(Voxel {
+            min: Vec3::new(-5.0, -5.0, -6.0),
+            max: Vec3::new(5.0, 5.0, -5.0),
+            mat_id: 0,
+            tint: NO_TINT,
+        });
+        scene.voxels.push(Voxel {
+            min: Vec3::new(-1.0, -1.0, -2.1),
+            max: Vec3::new(1.0, 1.0, -2.0),
+            mat_id: 1,
+            tint: NO_TINT,
+        });
+        let mut r = Renderer::new(1, 1, 1);
+        r.scene = Some(scene);
+        r.tex_cache = vec![None, None];
+        let stats = FrameStats::default();
+        let fc = r.frame_ctx(30.0, None, 0, &stats);
+
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        let shade = |scene: &Scene| {
+            let ctx = TraceCtx::new(scene, &r.tex_cache, 30.0);
+            let hit = trace_scene(&ray, &ctx).expect("impacto");
+            let mut rng = Rng::new(1);
+            (hit.mat_id, fc.shade_hit(&ctx, &ray, &hit, &mut rng, &mut TileStats::default()))
+        };
+        let scene = r.scene.clone().unwrap();
+        let (id, blended) = shade(&scene);
+        assert_eq!(id, 1);
+
+        let mut opaque = scene.clone();
+        opaque.materials[1].transparency = 0.0;
+        let (_, solid) = shade(&opaque);
+        let mut gone = scene.clone();
+        gone.voxels.pop();
+        let (_, wall) = shade(&gone);
+
+        // mitad panel, mitad pared teñida de azul
+        let want = solid * 0.5 + hadamard(wall, Vec3::new(0.2, 0.3, 1.0)) * 0.5;
+        assert!((blended - want).length() < 1e-9, "{:?} vs {:?}", blended, want);
+        assert!(blended.z > blended.x);
+
+        // con ior de vidrio no se mezcla
+        let mut glass = scene;
+        glass.materials[1].ior = 1.5;
+        assert_eq!(shade(&glass).1, solid);
+    }
+
+    #[test]
+    fn test_double_sided_backface_is_lit() {
+        let mut scene = Scene::new();
+        scene.materials.push(Material::new("one", Vec3::new(0.8, 0.8, 0.8), None));
+        scene.materials.push(Material::new("two", Vec3::new(0.8, 0.8, 0.8), None)
+            .with_double_sided(true));
+        let mut r = Renderer::new(1, 1, 1);
+        r.scene = Some(scene);
+        r.tex_cache = vec![None, None];
+        let stats = FrameStats::default();
+        let fc = r.frame_ctx(30.0, None, 0, &stats);
+        let ctx = TraceCtx::new(r.scene.as_ref().unwrap(), &r.tex_cache, 30.0);
+
+        // Cara que mira lejos del sol, vista desde el lado del sol
+        let sun = fc.sun_dir;
+        let ray = Ray::new(sun * 5.0, -sun);
+        let shade = |mat_id: usize| {
+            let hit = HitInfo {
+                t: 5.0,
+                p: Vec3::new(0.0, 0.0, 0.0),
+                n: -sun,
+                mat_id,
+                tint: NO_TINT,
+                u: 0.5,
+                v: 0.5,
+                prim: Prim::Tri(0),
+                uv_grad: None,
+            };
+            let mut rng = Rng::new(1);
+            fc.shade_hit(&ctx, &ray, &hit, &mut rng, &mut TileStats::default())
+        };
+        let (dark, lit) = (shade(0), shade(1));
+        assert!(luminance(lit) > luminance(dark) + 0.2, "{:?} vs {:?}", lit, dark);
+    }
+
+    #[test]
+    fn test_planar_reflection_mirrors_about_top_face() {
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let ray = Ray::new(Vec3::new(-2.0, 3.0, 0.0), Vec3::new(1.0, -1.0, 0.5));
+        let hit = HitInfo {
+            t: 3.0,
+            p: Vec3::new(1.0, 0.0, 1.5),
+            n: up,
+            mat_id: 0,
+            tint: NO_TINT,
+            u: 0.0,
+            v: 0.0,
+            prim: Prim::Voxel(0),
+            uv_grad: None,
+        };
+        let r = planar_reflection_ray(&ray, &hit).expect("la cara superior refleja");
+        let expected = Vec3::new(ray.d.x, -ray.d.y, ray.d.z);
+        assert!((r.d - expected).length() < 1e-12);
+        assert!((r.o - hit.p).length() < 1e-3 && r.o.y > 0.0);
+
+        // Caras laterales no
+        let side = HitInfo { n: Vec3::new(1.0, 0.0, 0.0), ..hit };
+        let sideways = Ray::new(ray.o, Vec3::new(-1.0, 0.0, 0.0));
+        assert!(planar_reflection_ray(&sideways, &side).is_none());
+
+        // Incidencia rasante refleja casi todo
+        assert!(fresnel_schlick(0.05, 1.33) > fresnel_schlick(1.0, 1.33) * 10.0);
+    }
+
+    #[test]
+    fn test_rough_reflection_blends_directions() {
+        let ray = Ray::new(Vec3::new(-2.0, 3.0, 0.0), Vec3::new(1.0, -1.0, 0.3));
+        let hit = HitInfo {
+            t: 3.0,
+            p: Vec3::new(1.0, 0.0, 0.9),
+            n: Vec3::new(0.0, 1.0, 0.0),
+            mat_id: 0,
+            tint: NO_TINT,
+            u: 0.0,
+            v: 0.0,
+            prim: Prim::Voxel(0),
+            uv_grad: None,
+        };
+        let mirror = planar_reflection_ray(&ray, &hit).unwrap();
+        // Entorno con un cambio brusco justo en la dirección espejo
+        let env = |d: Vec3| {
+            if d.z > mirror.d.z { Color::new(1.0, 0.0, 0.0) } else { Color::new(0.0, 0.0, 1.0) }
+        };
+        let average = |roughness: f64| {
+            let mut rng = Rng::new(7);
+            let n = 256;
+            let mut sum = Color::new(0.0, 0.0, 0.0);
+            let mut dirs = Vec::new();
+            for _ in 0..n {
+                let r = glossy_reflection(mirror, roughness, &mut rng);
+                assert!(r.d.y > 0.0);
+                sum = sum + env(r.d);
+                dirs.push(r.d);
+            }
+            (sum / n as f64, dirs)
+        };
+
+        let (smooth, dirs) = average(0.0);
+        assert!(dirs.iter().all(|&d| d == mirror.d));
+        assert!(smooth == Color::new(0.0, 0.0, 1.0));
+
+        let (rough, dirs) = average(0.6);
+        assert!(rough.x > 0.3 && rough.z > 0.3, "{:?}", rough);
+        let max_angle = dirs.iter().map(|d| d.dot(mirror.d).acos()).fold(0.0, f64::max);
+        assert!(max_angle > 0.1 && max_angle <= 0.36 * GLOSS_MAX_ANGLE + 1e-9);
+    }
+
+    #[test]
+    fn test_default_roughness_keeps_mirror_sharp() {
+        // El 0.5 de `Material::new` es para el PBR: el agua sin
+        // `with_roughness` refleja igual con cualquier semilla
+        let water = |m: Material| {
+            let mut scene = small_scene();
+            scene.materials[0] = m.with_planar_reflection(0.8);
+            let mut r = Renderer::new(8, 8, 1);
+            r.set_scene(&scene);
+            let stats = FrameStats::default();
+            let fc = r.frame_ctx(30.0, None, 0, &stats);
+            let p = Vec3::new(2.0, 0.0, 1.5);
+            let ray = Ray::new(Vec3::new(4.0, 1.0, 1.5), p - Vec3::new(4.0, 1.0, 1.5));
+            let up = Vec3::new(0.0, 1.0, 0.0);
+            (1..6)
+                .map(|seed| shade_hit_at(&fc, &scene, &ray, p, up, 0, &mut Rng::new(seed)))
+                .collect::<Vec<_>>()
+        };
+        let plain = Material::new("water", Vec3::new(0.3, 0.5, 0.9), None);
+        assert_eq!(plain.reflection_roughness, 0.0);
+        let sharp = water(plain.clone());
+        assert!(sharp.iter().all(|&l| l == sharp[0]), "{:?}", sharp);
+        let blurred = water(plain.with_roughness(0.8));
+        assert!(blurred.iter().any(|&l| l != blurred[0]), "{:?}", blurred);
+    }
+
+    #[test]
+    fn test_shared_edge_is_never_missed() {
+        // Quad inclinado partido por la diagonal p-q, mismo sentido de giro
+        let (p, q) = (Vec3::new(0.1, 0.3, 2.7), Vec3::new(1.3, 1.7, 3.1));
+        let a = Vec3::new(1.1, 0.2, 3.3);
+        let b = Vec3::new(0.3, 1.9, 2.9);
+        let n = Vec3::new(0.0, 0.0, -1.0);
+        let mut scene = Scene::new();
+        scene.materials.push(Material::new("mesh", Vec3::new(0.7, 0.7, 0.7), None));
+        scene.triangles.push(Tri::new(p, a, q, n, 0));
+        scene.triangles.push(Tri::new(q, b, p, n, 0));
+        let tex = [None];
+        let ctx = TraceCtx::new(&scene, &tex, 0.0);
+
+        let origins = [Vec3::new(0.0, 0.0, 0.0), Vec3::new(-0.37, 0.11, -0.5)];
+        for o in origins {
+            for i in 1..1000 {
+                let s = i as f64 / 1000.0;
+                let ray = Ray::new(o, p + (q - p) * s - o);
+                let hits = scene
+                    .triangles
+                    .iter()
+                    .filter(|t| ray_triangle_intersect(&ray, t, 1e6).is_some())
+                    .count();
+                assert!(hits >= 1, "rayo {} se coló por la arista", i);
+                let best = trace_triangles(&ray, &scene.triangles, &ctx, false);
+                assert!(best.is_some());
+            }
+        }
+    }
+
+    #[test]
+    fn test_wireframe_flags_triangle_edges() {
+        let mut scene = Scene::new();
+        scene.materials.push(Material::new("mesh", Vec3::new(0.7, 0.7, 0.7), None));
+        scene.triangles.push(Tri::new(
+            Vec3::new(-1.0, -1.0, 3.0),
+            Vec3::new(1.0, -1.0, 3.0),
+            Vec3::new(0.0, 1.0, 3.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            0,
+        ));
+        let tex = [None];
+        let ctx = TraceCtx::new(&scene, &tex, 0.0);
+        let o = Vec3::new(0.0, 0.0, 0.0);
+
+        // Casi sobre la arista inferior (y = -1)
+        let edge = trace_scene(&Ray::new(o, Vec3::new(0.0, -0.98, 3.0)), &ctx).unwrap();
+        assert!(near_edge(&ctx, &edge));
+        assert_eq!(debug_color(DebugMode::Wireframe, &ctx, Some(&edge)), WIRE_EDGE);
+
+        // Cerca del centroide
+        let center = trace_scene(&Ray::new(o, Vec3::new(0.0, -1.0 / 3.0, 3.0)), &ctx).unwrap();
+        assert!(!near_edge(&ctx, &center));
+        assert_eq!(debug_color(DebugMode::Wireframe, &ctx, Some(&center)), WIRE_FILL);
+        assert_eq!(debug_color(DebugMode::Wireframe, &ctx, None), WIRE_FILL);
+    }
+
+    #[test]
+    fn test_ray_heat_counts_more_tests_on_mesh() {
+        let mut scene = small_scene();
+        // Unos triángulos para que la prueba cubra también la malla
+        for k in 0..8 {
+            let y = 2.2 + k as f64 * 0.1;
+            scene.triangles.push(Tri::new(
+                Vec3::new(-1.0, y, -1.0),
+                Vec3::new(1.0, y, -1.0),
+                Vec3::new(0.0, y, 1.0),
+                Vec3::new(0.0, 1.0, 0.0),
+                1,
+            ));
+        }
+        let mut r = Renderer::new(32, 24, 1);
+        r.set_scene(&scene);
+        // Más horizontal que small_pose, para que entre cielo
+        r.set_camera(&CameraPose { target: Vec3::new(0.0, 2.5, 0.0), ..small_pose() });
+        r.set_debug(DebugMode::RayHeat);
+        let stats = FrameStats::default();
+        let fc = r.frame_ctx(30.0, r.camera, 0, &stats);
+        let heat = Cell::new(0);
+        let mut ctx = TraceCtx::new(r.scene.as_ref().unwrap(), &r.tex_cache, 30.0);
+        ctx.isect_tests = Some(&heat);
+        let mut ts = TileStats::default();
+        let pose = r.camera.unwrap();
+
+        // Buscar un píxel de cielo y uno sobre la malla
+        let mut sky = None;
+        let mut mesh = None;
+        for y in 0..24 {
+            for x in 0..32 {
+                let (sx, sy) = (x as f64 + 0.5, y as f64 + 0.5);
+                let ray = make_primary_ray(sx, sy, 32, 24, 1.0, DEFAULT_CLIP, &pose);
+                match trace_scene(&ray, &TraceCtx::new(&scene, &r.tex_cache, 30.0)) {
+                    None => sky = sky.or(Some((x, y))),
+                    Some(h) if matches!(h.prim, Prim::Tri(_)) => mesh = mesh.or(Some((x, y))),
+                    _ => {}
+                }
+            }
+        }
+        let cost = |(x, y): (usize, usize), ts: &mut TileStats| {
+            heat.set(0);
+            fc.sample_pixel(&ctx, &pose, (x, y), &mut Rng::new(1), 0..1, ts);
+            heat.get()
+        };
+        let sky_cost = cost(sky.expect("hay cielo"), &mut ts);
+        let mesh_cost = cost(mesh.expect("se ve la malla"), &mut ts);
+        assert!(mesh_cost > sky_cost, "malla {} cielo {}", mesh_cost, sky_cost);
+        // El cielo solo paga el rayo de cámara
+        assert_eq!(sky_cost, (scene.voxels.len() + scene.triangles.len()) as u64);
+    }
+
+    #[test]
+    fn test_normals_and_depth_views() {
+        let scene = small_scene();
+        let tex = [None, None];
+        let ctx = TraceCtx::new(&scene, &tex, 0.0);
+        let down = Vec3::new(0.0, -1.0, 0.0);
+
+        // Techo del bloque (y = 2) y piso (y = 0), ambos mirando arriba
+        let top = trace_scene(&Ray::new(Vec3::new(0.0, 5.0, 0.0), down), &ctx).unwrap();
+        let floor = trace_scene(&Ray::new(Vec3::new(3.0, 5.0, 3.0), down), &ctx).unwrap();
+
+        let n = debug_color(DebugMode::Normals, &ctx, Some(&top));
+        assert!(n.y > n.x && n.y > n.z, "{:?}", n);
+        assert_eq!(debug_color(DebugMode::Normals, &ctx, None), SKY_SENTINEL);
+
+        let depth = DebugMode::Depth { near: 1.0, far: 10.0 };
+        let near_c = debug_color(depth, &ctx, Some(&top));
+        let far_c = debug_color(depth, &ctx, Some(&floor));
+        assert!(near_c.x > far_c.x, "{:?} {:?}", near_c, far_c);
+        assert_eq!(debug_color(depth, &ctx, None), SKY_SENTINEL);
+    }
+
+    #[test]
+    fn test_adaptive_mask_marks_edges_only() {
+        // Mitad izquierda plana oscura, derecha plana clara
+        let (w, h) = (8, 4);
+        let lum: Vec<f64> = (0..w * h).map(|i| if i % w < 4 { 0.1 } else { 0.9 }).collect();
+        let mask = adaptive_mask(&lum, w, h, 0.01);
+        for y in 0..h {
+            for x in 0..w {
+                assert_eq!(mask[y * w + x], x == 3 || x == 4, "({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn test_adaptive_spends_samples_on_edges() {
+        let spp = 8;
+        let render = |scene: &Scene| {
+            let mut r = Renderer::new(32, 24, spp);
+            r.set_scene(scene);
+            r.set_camera(&small_pose());
+            r.set_adaptive(true, 0.002);
+            r.render_frame(&mut Image::new(32, 24), 30.0)
+        };
+        let px = 32 * 24;
+        // Solo cielo: casi plano
+        let flat = render(&Scene::new());
+        // Bloque sobre el piso: bordes y sombras
+        let edges = render(&small_scene());
+        assert!(flat.primary_rays < edges.primary_rays);
+        assert!(flat.primary_rays < (px * spp / 4) as u64, "{}", flat.primary_rays);
+        assert!(edges.primary_rays <= (px * spp) as u64);
+    }
+
+    #[test]
+    fn test_edge_mask_marks_silhouettes_only() {
+        // Bloque 4x4 (material 1 a distancia 5) sobre cielo, en 10x8
+        let (w, h) = (10, 8);
+        let ids: Vec<Option<(usize, f64)>> = (0..w * h)
+            .map(|i| ((3..7).contains(&(i % w)) && (2..6).contains(&(i / w))).then_some((1, 5.0)))
+            .collect();
+        let mask = edge_mask(&ids, w, (0, 0, w, h));
+        // silueta (a ambos lados del borde), interior y cielo lejano
+        assert!(mask[2 * w + 3] && mask[2 * w + 2]);
+        assert!(!mask[3 * w + 4] && !mask[4 * w + 5]);
+        assert!(!mask[0] && !mask[7 * w + 9]);
+
+        // En un plano, un salto de profundidad es borde; uno pequeño no
+        let mut plane = vec![Some((0, 5.0)); w * h];
+        plane[4 * w + 6] = Some((0, 9.0));
+        plane[w + 1] = Some((0, 5.2));
+        let mask = edge_mask(&plane, w, (0, 0, w, h));
+        assert!(mask[4 * w + 6] && mask[3 * w + 5]);
+        assert!(!mask[w + 1] && !mask[0]);
+
+        // Con región, el borde de la región no cuenta como silueta
+        let mask = edge_mask(&ids, w, (4, 3, 6, 5));
+        assert!(mask.iter().all(|&m| !m));
+    }
+
+    #[test]
+    fn test_edge_aa_samples_silhouettes() {
+        let (w, h, extra) = (32, 24, 6);
+        let render = |edge_aa: usize| {
+            let mut r = Renderer::new(w, h, 1);
+            r.set_scene(&small_scene());
+            r.set_camera(&small_pose());
+            r.set_edge_aa(edge_aa);
+            r.render_frame(&mut Image::new(w, h), 30.0).primary_rays as usize
+        };
+        let base = render(0);
+        assert_eq!(base, w * h);
+        // una pasada de guías (un rayo por píxel) y `extra` por silueta
+        let edge_pixels = (render(extra) - 2 * w * h) / extra;
+        assert!(edge_pixels > 0 && edge_pixels < w * h / 2, "{}", edge_pixels);
+    }
+
+    #[test]
+    fn test_shininess_tightens_highlight() {
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let light = Vec3::new(0.3, 1.0, 0.0).normalized();
+        let mirror = Vec3::new(-light.x, light.y, light.z);
+        // 10 grados fuera del reflejo especular
+        let (s, c) = 10f64.to_radians().sin_cos();
+        let off = Vec3::new(mirror.x * c - mirror.y * s, mirror.x * s + mirror.y * c, 0.0);
+
+        let base = Material::new("stone", Vec3::new(0.5, 0.5, 0.5), None);
+        assert!((blinn_phong(up, mirror, light, &base) - 0.15).abs() < 1e-9);
+
+        let falloff = |mat: &Material| {
+            blinn_phong(up, off, light, mat) / blinn_phong(up, mirror, light, mat)
+        };
+        let polished = base.clone().with_shininess(256.0);
+        let matte = base.clone().with_shininess(8.0);
+        assert!(falloff(&polished) < falloff(&matte) * 0.5);
+
+        // specular escala la intensidad
+        let dull = base.clone().with_specular(0.02);
+        let peak = |m: &Material| blinn_phong(up, mirror, light, m);
+        assert!((peak(&dull) - peak(&base) * 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_glass_highlight_stays_below_sun_color() {
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let light = Vec3::new(0.3, 1.0, 0.0).normalized();
+        let mirror = Vec3::new(-light.x, light.y, light.z);
+        let sun_color = DayNight::new().sun_color(TURNTABLE_TIME);
+        for mat in [Material::preset("glass"), Material::preset("matte").with_specular(1.0)] {
+            let peak = blinn_phong(up, mirror, light, &mat);
+            assert!(peak.is_finite() && peak > 0.0 && peak < 1.0, "{}: {}", mat.name, peak);
+            let highlight = hadamard(sun_color, mat.albedo) * peak;
+            assert!(highlight.x < sun_color.x && highlight.y < sun_color.y);
+            assert!(highlight.z < sun_color.z);
+        }
+    }
+
+    #[test]
+    fn test_material_specular_drives_sun_highlight() {
+        let mut scene = small_scene();
+        let glossy = Material::new("glass", Vec3::new(0.5, 0.5, 0.5), None).with_specular(0.6);
+        let stone = Material::new("stone", Vec3::new(0.5, 0.5, 0.5), None).with_specular(0.06);
+        scene.materials = vec![glossy, stone];
+        let mut r = Renderer::new(1, 1, 1);
+        r.set_scene(&scene);
+        let stats = FrameStats::default();
+        let mut fc = r.frame_ctx(35.0, None, 0, &stats);
+
+        // la cámara en el reflejo del sol sobre una cara superior
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let sun = fc.sun_dir;
+        let view = Vec3::new(-sun.x, sun.y, -sun.z);
+        let p = Vec3::new(0.5, 1.0, 0.5);
+        let ray = Ray::new(p + view * 5.0, -view);
+        let shade = |fc: &FrameCtx, mat_id: usize| {
+            shade_hit_at(fc, &scene, &ray, p, up, mat_id, &mut Rng::new(1))
+        };
+        let (hi, lo) = (shade(&fc, 0), shade(&fc, 1));
+        assert!(hi > lo + 0.1, "{} vs {}", hi, lo);
 
-                    if let Ok(mut fb_guard) = fb_cl.lock() {
-                        for (x, y, c) in tile_colors {
-                            let idx = y * w + x;
-                            fb_guard[idx] = c;
-                        }
-                    }
-                });
-                handles.push(handle);
+        // con el sol bajo el brillo se apaga de a poco, no de golpe
+        fc.sun_intensity = SPEC_FULL_SUN * 0.5;
+        let (dim_hi, dim_lo) = (shade(&fc, 0), shade(&fc, 1));
+        assert!(dim_hi > dim_lo && dim_hi - dim_lo < hi - lo);
+    }
+
+    #[test]
+    fn test_filmic_curve_defaults_and_toe() {
+        let (toe, shoulder) = (DEFAULT_FILMIC_TOE, DEFAULT_FILMIC_SHOULDER);
+        let at = |x: f64, toe: f64| tonemap_filmic(Color::new(x, x, x), toe, shoulder).x;
+        for (x, pinned) in [(0.1, 0.1388), (0.5, 0.6045), (2.0, 0.9301)] {
+            assert!((at(x, toe) - pinned).abs() < 1e-3, "{}: {}", x, at(x, toe));
+            // cerca del ACES de siempre
+            assert!((at(x, toe) - tonemap_aces(Color::new(x, x, x)).x).abs() < 0.02);
+        }
+        assert!(at(0.1, 0.8) > at(0.1, toe) + 0.02);
+        assert_eq!(at(0.0, toe), 0.0);
+
+        let mut r = Renderer::new(1, 1, 1);
+        let c = Color::new(0.5, 0.5, 0.5);
+        assert_eq!(r.tonemap(c), tonemap_aces(c));
+        r.set_filmic_params(toe, shoulder);
+        assert_eq!(r.tonemap(c), tonemap_filmic(c, toe, shoulder));
+    }
+
+    #[test]
+    fn test_pixel_aspect_widens_horizontal_fov() {
+        let pose = CameraPose {
+            eye: Vec3::new(0.0, 0.0, 0.0),
+            target: Vec3::new(0.0, 0.0, -1.0),
+            up: Vec3::new(0.0, 1.0, 0.0),
+            fov_deg: 60.0,
+        };
+        // Tangente del medio ángulo en el borde derecho y en el superior
+        let tan_half = |sx: f64, sy: f64, pa: f64| {
+            let d = make_primary_ray(sx, sy, 96, 54, pa, DEFAULT_CLIP, &pose).d;
+            (d.x.abs() / -d.z, d.y.abs() / -d.z)
+        };
+        let (h1, _) = tan_half(96.0, 27.0, 1.0);
+        let (h2, _) = tan_half(96.0, 27.0, 2.0);
+        assert!((h2 - 2.0 * h1).abs() < 1e-9, "{} vs {}", h2, h1);
+        let (_, v1) = tan_half(48.0, 0.0, 1.0);
+        let (_, v2) = tan_half(48.0, 0.0, 2.0);
+        assert!((v1 - v2).abs() < 1e-12);
+        assert!((v1 - 30f64.to_radians().tan()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_sky_cache_matches_direct_sky() {
+        // El cielo de antes, calculado entero en cada fallo
+        fn direct(fc: &FrameCtx, d: Vec3) -> Color {
+            let base = fc.sky_color;
+            let t_h = ((d.y.clamp(-1.0, 1.0) + 1.0) * 0.5).clamp(0.0, 1.0);
+            let horizon = Color::new(base.x * 1.05, base.y * 1.05, base.z * 1.05);
+            let zenith = Color::new(base.x * 0.85, base.y * 0.90, base.z * 1.0);
+            let sky = zenith * t_h + horizon * (1.0 - t_h);
+            let ang = d.dot(fc.sun_dir).clamp(-1.0, 1.0).acos();
+            let sun_disk = (0.008 - ang).max(0.0) * 80.0;
+            let sun_glow = (0.10 - ang).max(0.0) * 1.5;
+            sky + fc.sun_color * (sun_disk + sun_glow) * fc.sun_intensity
+        }
+        let r = Renderer::new(4, 4, 1);
+        let stats = FrameStats::default();
+        // sol alto: sin el enrojecimiento del horizonte
+        for time in [15.0, 30.0, 50.0] {
+            let fc = r.frame_ctx(time, None, 0, &stats);
+            let mut rng = Rng::new(7);
+            let mut dirs: Vec<Vec3> = (0..2000)
+                .map(|_| sample_cosine_hemisphere(Vec3::new(0.0, 1.0, 0.0), &mut rng).0)
+                .collect();
+            // alrededor del sol, dentro y en el borde del halo
+            let (t, b) = tangent_frame(fc.sun_dir);
+            for i in 0..200 {
+                let a = i as f64 * 0.0006;
+                dirs.push((fc.sun_dir + t * a.tan()).normalized());
+                dirs.push((fc.sun_dir + b * (a * 0.3).tan() - t * a.tan()).normalized());
             }
+            for d in dirs {
+                let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), d);
+                assert_eq!(fc.sky(&ray, 0), direct(&fc, ray.d), "{:?}", d);
+            }
+        }
+    }
+
+    #[test]
+    fn test_ao_sees_overhanging_triangles() {
+        let mut scene = Scene::new();
+        scene.materials.push(Material::new("m", Vec3::new(0.5, 0.5, 0.5), None));
+        // Alero de dos triángulos a 0.5 sobre el origen
+        let down = Vec3::new(0.0, -1.0, 0.0);
+        let (a, b) = (Vec3::new(-1.0, 0.5, -1.0), Vec3::new(1.0, 0.5, -1.0));
+        let (c, d) = (Vec3::new(1.0, 0.5, 1.0), Vec3::new(-1.0, 0.5, 1.0));
+        scene.triangles.push(Tri::new(a, b, c, down, 0));
+        scene.triangles.push(Tri::new(a, c, d, down, 0));
+        let tex = vec![None];
+        let ctx = TraceCtx::new(&scene, &tex, 0.0);
+
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let under = ao_term(Vec3::new(0.0, 0.0, 0.0), up, &ctx, 1.0);
+        let exposed = ao_term(Vec3::new(5.0, 0.0, 0.0), up, &ctx, 1.0);
+        assert_eq!(exposed, 1.0);
+        assert!(under < exposed, "{} vs {}", under, exposed);
+
+        // la bent normal se aparta del alero
+        let bent = bent_normal(Vec3::new(0.9, 0.0, 0.0), up, &ctx);
+        assert!(bent.x > 0.1, "{:?}", bent);
+    }
+
+    #[test]
+    fn test_sky_occlusion_darkens_points_under_a_roof() {
+        let mut scene = Scene::new();
+        scene.materials.push(Material::new("m", Vec3::new(0.5, 0.5, 0.5), None));
+        // Alero de 10x10 a 3 de altura sobre el origen
+        scene.voxels.push(Voxel {
+            min: Vec3::new(-5.0, 3.0, -5.0),
+            max: Vec3::new(5.0, 3.5, 5.0),
+            mat_id: 0,
+            tint: NO_TINT,
+        });
+        let tex = vec![None];
+        let ctx = TraceCtx::new(&scene, &tex, 0.0);
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let mut rng = Rng::new(7);
+        let under = sky_visibility(Vec3::new(0.0, 0.0, 0.0), up, &ctx, 64, &mut rng);
+        let open = sky_visibility(Vec3::new(20.0, 0.0, 0.0), up, &ctx, 64, &mut rng);
+        assert_eq!(open, 1.0);
+        assert!(under < 0.5, "{}", under);
+
+        // y en el render: el piso bajo el alero queda más oscuro con la opción
+        let shade = |samples: usize, x: f64| {
+            let mut r = Renderer::new(1, 1, 1);
+            r.set_sky_occlusion(samples);
+            r.set_scene(&scene);
+            let stats = FrameStats::default();
+            let fc = r.frame_ctx(30.0, None, 0, &stats);
+            let hit = HitInfo {
+                t: 1.0,
+                p: Vec3::new(x, 0.0, 0.0),
+                n: up,
+                mat_id: 0,
+                tint: NO_TINT,
+                u: 0.0,
+                v: 0.0,
+                prim: Prim::Voxel(0),
+                uv_grad: None,
+            };
+            let ray = Ray::new(Vec3::new(x, 1.0, 0.0), -up);
+            let mut ts = TileStats::default();
+            luminance(fc.shade_hit(&ctx, &ray, &hit, &mut Rng::new(1), &mut ts))
+        };
+        assert!(shade(32, 0.0) < shade(0, 0.0));
+        assert!((shade(32, 20.0) - shade(0, 20.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_ao_radius_reaches_farther_occluders() {
+        let mut scene = Scene::new();
+        scene.materials.push(Material::new("m", Vec3::new(0.5, 0.5, 0.5), None));
+        // Techo a 1.5 sobre el punto
+        scene.voxels.push(Voxel {
+            min: Vec3::new(-2.0, 1.5, -2.0),
+            max: Vec3::new(2.0, 2.0, 2.0),
+            mat_id: 0,
+            tint: NO_TINT,
+        });
+        let tex = vec![None];
+        let ctx = TraceCtx::new(&scene, &tex, 0.0);
+        let (p, up) = (Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(ao_term(p, up, &ctx, DEFAULT_AO_RADIUS), 1.0);
+        assert!(ao_term(p, up, &ctx, 2.0 * DEFAULT_AO_RADIUS) < 1.0);
+
+        let mut r = Renderer::new(1, 1, 1);
+        assert_eq!(r.ao_radius, DEFAULT_AO_RADIUS);
+        r.set_ao_radius_from_scene(0.5);
+        assert_eq!(r.ao_radius, DEFAULT_AO_RADIUS);
+        r.scene = Some(scene);
+        r.set_ao_radius_from_scene(0.5);
+        let diag = Vec3::new(4.0, 0.5, 4.0).length();
+        assert!((r.ao_radius - 0.5 * diag).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_low_sun_disk_is_warmer_and_larger() {
+        let r = Renderer::new(4, 4, 1);
+        let stats = FrameStats::default();
+        let noon = r.frame_ctx(35.0, None, 0, &stats).sky_cache;
+        let dawn = r.frame_ctx(2.0, None, 0, &stats).sky_cache;
+        assert_eq!(noon.disk_radius, SUN_DISK_RADIUS);
+        assert_eq!(noon.glow_radius, SUN_GLOW_RADIUS);
+        let warmth = |c: Color| c.x / c.z;
+        assert!(warmth(dawn.disk_color) > warmth(noon.disk_color) * 1.5);
+        assert!(dawn.disk_radius > noon.disk_radius);
+
+        let mut wide = Renderer::new(4, 4, 1);
+        wide.set_sun_disk(0.02, 0.2);
+        let c = wide.frame_ctx(35.0, None, 0, &stats).sky_cache;
+        assert_eq!((c.disk_radius, c.glow_radius), (0.02, 0.2));
+    }
+
+    #[test]
+    fn test_pick_returns_floor_hit() {
+        let mut scene = Scene::new();
+        scene.materials.push(Material::new("stone", Vec3::new(0.5, 0.5, 0.5), None));
+        scene.materials.push(Material::new("floor", Vec3::new(0.3, 0.6, 0.3), None));
+        scene.voxels.push(Voxel {
+            min: Vec3::new(-3.0, -1.0, -3.0),
+            max: Vec3::new(3.0, 0.0, 3.0),
+            mat_id: 1,
+            tint: NO_TINT,
+        });
+        let mut r = Renderer::new(16, 16, 1);
+        assert!(r.pick(8, 8).is_none());
+        r.set_scene(&scene);
+        r.set_camera(&CameraPose {
+            eye: Vec3::new(0.0, 5.0, 5.0),
+            target: Vec3::new(0.0, 0.0, 0.0),
+            up: Vec3::new(0.0, 1.0, 0.0),
+            fov_deg: 40.0,
+        });
+
+        let p = r.pick(8, 12).expect("debe dar en el piso");
+        assert_eq!(p.mat_id, 1);
+        assert!(p.point.y.abs() < 1e-9, "{:?}", p.point);
+        assert_eq!(p.normal, Vec3::new(0.0, 1.0, 0.0));
+        assert!((p.distance - (p.point - Vec3::new(0.0, 5.0, 5.0)).length()).abs() < 1e-9);
+        // arriba de la imagen, más allá del piso
+        assert!(r.pick(8, 0).is_none());
+    }
+
+    #[test]
+    fn test_in_memory_rgb_texture_shades_red() {
+        let mut scene = Scene::new();
+        let white = Material::new("white", Vec3::new(1.0, 1.0, 1.0), None);
+        scene.materials.push(white.with_texture_rgb(1, 1, vec![255, 0, 0]));
+        scene.voxels.push(Voxel::from_grid(0, 0, 0, 0));
+        let mut r = Renderer::new(8, 8, 1);
+        r.set_scene(&scene);
+        let tex = r.tex_cache[0].as_ref().expect("la textura en memoria debe cargarse");
+        assert_eq!((tex.w, tex.h), (1, 1));
+        assert_eq!(tex.data, vec![255, 0, 0, 255]);
+
+        let stats = FrameStats::default();
+        let fc = r.frame_ctx(30.0, None, 1, &stats);
+        let scene = r.scene.as_ref().unwrap();
+        let ctx = TraceCtx::new(scene, &r.tex_cache, 30.0);
+        let ray = Ray::new(Vec3::new(0.5, 5.0, 0.5), Vec3::new(0.0, -1.0, 0.0));
+        let hit = trace_scene(&ray, &ctx).expect("debe dar en el bloque");
+        assert_eq!(fc.albedo_at(&ctx, &hit), Color::new(1.0, 0.0, 0.0));
+        let c = fc.shade_hit(&ctx, &ray, &hit, &mut Rng::new(1), &mut TileStats::default());
+        assert!(c.x > 0.0 && c.y < 1e-9 && c.z < 1e-9, "debe verse rojo: {:?}", c);
+    }
+
+    #[test]
+    fn test_inside_out_voxel_is_reported_and_normalized() {
+        let mut scene = Scene::new();
+        scene.materials.push(Material::new("m", Vec3::new(0.5, 0.5, 0.5), None));
+        // min y max cruzados en x e y
+        scene.voxels.push(Voxel {
+            min: Vec3::new(1.0, 1.0, 0.0),
+            max: Vec3::new(0.0, 0.0, 1.0),
+            mat_id: 0,
+            tint: NO_TINT,
+        });
+        scene.voxels.push(Voxel {
+            min: Vec3::new(3.0, 0.0, 0.0),
+            max: Vec3::new(4.0, 0.0, 1.0),
+            mat_id: 0,
+            tint: NO_TINT,
+        });
+        assert_eq!(
+            scene.validate(),
+            vec![
+                SceneWarning::InsideOutVoxel { index: 0 },
+                SceneWarning::FlatVoxel { index: 1 },
+            ]
+        );
+
+        assert_eq!(scene.normalize_voxels(), 1);
+        assert_eq!(scene.validate(), vec![SceneWarning::FlatVoxel { index: 1 }]);
+        assert_eq!(scene.voxels[0].min, Vec3::new(0.0, 0.0, 0.0));
+        assert_eq!(scene.voxels[0].max, Vec3::new(1.0, 1.0, 1.0));
+
+        // normales hacia afuera en las caras que antes estaban cruzadas
+        let tex = vec![None];
+        let ctx = TraceCtx::new(&scene, &tex, 0.0);
+        let cases = [
+            (Vec3::new(0.5, 5.0, 0.5), Vec3::new(0.0, -1.0, 0.0), Vec3::new(0.0, 1.0, 0.0)),
+            (Vec3::new(-5.0, 0.5, 0.5), Vec3::new(1.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0)),
+            (Vec3::new(0.5, 0.5, 5.0), Vec3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 0.0, 1.0)),
+        ];
+        for (o, d, n) in cases {
+            let hit = trace_scene(&Ray::new(o, d), &ctx).expect("debe dar en el voxel");
+            assert_eq!(hit.n, n, "rayo {:?}", d);
         }
+    }
+
+    #[test]
+    fn test_stereo_eyes_are_ipd_apart_along_camera_right() {
+        let pose = CameraPose {
+            eye: Vec3::new(3.0, 4.0, 5.0),
+            target: Vec3::new(0.0, 1.0, 0.0),
+            up: Vec3::new(0.0, 1.0, 0.0),
+            fov_deg: 60.0,
+        };
+        let ipd = 0.064;
+        let (left, right) = stereo_poses(&pose, ipd);
+        let center = |p: &CameraPose| make_primary_ray(32.0, 24.0, 64, 48, 1.0, DEFAULT_CLIP, p);
+        let (rl, rr) = (center(&left), center(&right));
+
+        let axis = (pose.target - pose.eye).normalized().cross(pose.up).normalized();
+        let d = rr.o - rl.o;
+        assert!((d.dot(axis) - ipd).abs() < 1e-12, "{:?}", d);
+        assert!((d - axis * ipd).length() < 1e-12, "solo sobre el eje derecho: {:?}", d);
+        assert!((rl.d - rr.d).length() < 1e-12, "ejes paralelos");
+        // el centro de la cámara queda a mitad de camino
+        assert!(((rl.o + rr.o) * 0.5 - pose.eye).length() < 1e-12);
+
+        // side-by-side llena las dos mitades de una imagen del doble de ancho
+        let mut r = Renderer::new(8, 6, 1);
+        r.set_scene(&small_scene());
+        r.set_camera(&small_pose());
+        r.set_stereo(0.5, StereoMode::SideBySide);
+        let mut img = Image::new(16, 6);
+        r.render_frame(&mut img, 30.0);
+        assert!((0..6).any(|y| (0..8).any(|x| img.get(x, y) != img.get(x + 8, y))));
+    }
 
-        for h in handles {
-            let _ = h.join();
+    #[test]
+    fn test_voxel_soa_matches_aos_traversal() {
+        let mut rng = Rng::new(7);
+        let mut rand = |a: f64, b: f64| a + (b - a) * rng.next_f64();
+        let mut scene = Scene::new();
+        scene.materials.push(Material::new("a", Vec3::new(0.5, 0.5, 0.5), None));
+        scene.materials.push(Material::new("b", Vec3::new(0.8, 0.2, 0.2), None));
+        for i in 0..200 {
+            let min = Vec3::new(rand(-10.0, 10.0), rand(-10.0, 10.0), rand(-10.0, 10.0));
+            let size = Vec3::new(rand(0.2, 2.0), rand(0.2, 2.0), rand(0.2, 2.0));
+            scene.voxels.push(Voxel { min, max: min + size, mat_id: i % 2, tint: NO_TINT });
         }
+        let soa = VoxelSoa::new(&scene.voxels);
+        let tex = vec![None, None];
+        let aos_ctx = TraceCtx::new(&scene, &tex, 0.0);
+        let soa_ctx = TraceCtx { voxel_soa: Some(&soa), ..aos_ctx };
 
-        // Tomar el framebuffer y pasarlo al Image
-        let fb_data = fb.lock().unwrap();
-        for y in 0..self.h {
-            for x in 0..self.w {
-                let idx = y * self.w + x;
-                let mut out = fb_data[idx];
-                out = tonemap_aces(out);
-                out = gamma22(out);
-                img.set(x, y, out);
+        let mut hits = 0;
+        for _ in 0..500 {
+            let o = Vec3::new(rand(-15.0, 15.0), rand(-15.0, 15.0), rand(-15.0, 15.0));
+            let d = Vec3::new(rand(-1.0, 1.0), rand(-1.0, 1.0), rand(-1.0, 1.0)).normalized();
+            let ray = Ray::new(o, d);
+            let (a, b) = (trace_scene(&ray, &aos_ctx), trace_scene(&ray, &soa_ctx));
+            assert_eq!(a.is_some(), b.is_some());
+            if let (Some(a), Some(b)) = (a, b) {
+                hits += 1;
+                assert_eq!(a.t.to_bits(), b.t.to_bits());
+                assert_eq!((a.prim, a.mat_id, a.n), (b.prim, b.mat_id, b.n));
             }
+            let tmax = rand(0.5, 20.0);
+            assert_eq!(blocked_along(&ray, &aos_ctx, tmax), blocked_along(&ray, &soa_ctx, tmax));
         }
+        assert!(hits > 50, "pocos impactos para comparar: {}", hits);
     }
-}
 
-/* ====================== Helpers de Ray Tracing ====================== */
+    #[test]
+    fn test_animated_voxels_keep_the_soa() {
+        let mut scene = small_scene();
+        let slide = VoxelAnim::Slide { offset: Vec3::new(0.0, 0.0, 6.0), period: 4.0 };
+        scene.animate_voxel(1, slide);
+        let mut r = Renderer::new(8, 8, 1);
+        r.set_scene(&scene);
+        let stats = FrameStats::default();
+        // a mitad del periodo el bloque está corrido 6 en z; el piso no
+        let fc = r.frame_ctx(2.0, None, 0, &stats);
+        assert!(matches!(fc.voxels, Cow::Owned(_)) && fc.voxel_soa.is_some());
+        assert!(matches!(fc.scene, Some(s) if std::ptr::eq(s, r.scene.as_ref().unwrap())));
+        let ctx = fc.trace_ctx(fc.scene.unwrap());
+        let down = Vec3::new(0.0, -1.0, 0.0);
+        let moved = trace_scene(&Ray::new(Vec3::new(0.0, 5.0, 6.0), down), &ctx).unwrap();
+        assert_eq!((moved.prim, moved.t), (Prim::Voxel(1), 3.0));
+        let rest = trace_scene(&Ray::new(Vec3::new(0.0, 5.0, 0.0), down), &ctx).unwrap();
+        assert_eq!((rest.prim, rest.t), (Prim::Voxel(0), 5.0));
+        // sin animaciones no se copia nada
+        r.set_scene(&small_scene());
+        assert!(matches!(r.frame_ctx(2.0, None, 0, &stats).voxels, Cow::Borrowed(_)));
+    }
 
-#[derive(Clone, Copy)]
-struct HitInfo {
-    t: f64,
-    p: Vec3,
-    n: Vec3,
-    mat_id: usize,
-    vmin: Vec3,
-    vmax: Vec3,
-}
+    #[test]
+    fn test_flat_background_fills_misses() {
+        let mut scene = Scene::new();
+        scene.materials.push(Material::new("box", Vec3::new(0.8, 0.2, 0.2), None));
+        scene.voxels.push(Voxel::from_grid(0, 0, 0, 0));
+        let mut r = Renderer::new(12, 12, 1);
+        r.set_scene(&scene);
+        r.set_camera(&CameraPose {
+            eye: Vec3::new(0.5, 0.5, 4.0),
+            target: Vec3::new(0.5, 0.5, 0.0),
+            up: Vec3::new(0.0, 1.0, 0.0),
+            fov_deg: 40.0,
+        });
+        r.set_use_procedural_sky(false);
+        let gray = Color::new(0.18, 0.18, 0.18);
+        r.set_background(gray);
 
-fn make_primary_ray(
-    x: usize,
-    y: usize,
-    w: usize,
-    h: usize,
-    cam: &CameraPose,
-) -> Ray {
-    let aspect = w as f64 / h as f64;
-    let fov = cam.fov_deg.to_radians();
-    let scale = (fov * 0.5).tan();
+        let stats = FrameStats::default();
+        let mut fb = Vec::new();
+        let fc = r.frame_ctx(30.0, r.camera, r.frame_seed(30.0, 0), &stats);
+        r.render_linear_into(&mut fb, &fc, false, None);
+        let hits = r.center_hits(&r.frame_ctx(30.0, r.camera, 0, &stats), false);
+        let misses = hits.iter().filter(|h| h.is_none()).count();
+        assert!(misses > 0 && misses < hits.len());
+        for (c, h) in fb.iter().zip(&hits) {
+            if h.is_none() {
+                assert_eq!(*c, gray);
+            }
+        }
+    }
 
-    let px = (2.0 * ((x as f64 + 0.5) / w as f64) - 1.0) * aspect * scale;
-    let py = (1.0 - 2.0 * ((y as f64 + 0.5) / h as f64)) * scale;
+    #[test]
+    fn test_clip_planes_skip_near_and_far_geometry() {
+        let mut scene = Scene::new();
+        scene.materials.push(Material::new("wall", Vec3::new(0.8, 0.2, 0.2), None));
+        scene.materials.push(Material::new("back", Vec3::new(0.2, 0.2, 0.8), None));
+        // Pared a 1 de la cámara y bloque a 5, ambos delante
+        scene.voxels.push(Voxel {
+            min: Vec3::new(-3.0, -3.0, -1.2),
+            max: Vec3::new(3.0, 3.0, -1.0),
+            mat_id: 0,
+            tint: NO_TINT,
+        });
+        scene.voxels.push(Voxel {
+            min: Vec3::new(-3.0, -3.0, -6.0),
+            max: Vec3::new(3.0, 3.0, -5.0),
+            mat_id: 1,
+            tint: NO_TINT,
+        });
+        let pose = CameraPose {
+            eye: Vec3::new(0.0, 0.0, 0.0),
+            target: Vec3::new(0.0, 0.0, -1.0),
+            up: Vec3::new(0.0, 1.0, 0.0),
+            fov_deg: 40.0,
+        };
+        let tex = vec![None, None];
+        let ctx = TraceCtx::new(&scene, &tex, 0.0);
+        let hit = |clip: (f64, f64)| {
+            trace_scene(&make_primary_ray(2.0, 2.0, 4, 4, 1.0, clip, &pose), &ctx)
+                .map(|h| h.mat_id)
+        };
+        assert_eq!(hit(DEFAULT_CLIP), Some(0));
+        assert_eq!(hit((2.0, 1e6)), Some(1));
+        assert_eq!(hit((2.0, 4.0)), None);
 
-    let forward = (cam.target - cam.eye).normalized();
-    let right = forward.cross(cam.up).normalized();
-    let up = right.cross(forward).normalized();
+        // y de punta a punta: lo más allá de far es cielo
+        let mut r = Renderer::new(4, 4, 1);
+        r.set_scene(&scene);
+        r.set_camera(&pose);
+        r.set_clip(0.001, 0.5);
+        let mut clipped = Image::new(4, 4);
+        r.render_frame(&mut clipped, 30.0);
+        let mut r_sky = Renderer::new(4, 4, 1);
+        r_sky.set_scene(&Scene::new());
+        r_sky.set_camera(&pose);
+        let mut sky = Image::new(4, 4);
+        r_sky.render_frame(&mut sky, 30.0);
+        assert!(clipped.data == sky.data);
+    }
 
-    let dir = (forward + right * px + up * py).normalized();
+    #[test]
+    fn test_instances_of_one_mesh_hit_in_place() {
+        use std::sync::Arc;
 
-    let mut ray = Ray::new(cam.eye, dir);
-    ray.tmin = 0.001;
-    ray.tmax = 1e6;
-    ray
-}
+        // Un triángulo mirando a +Z alrededor del origen, en dos lugares
+        let z = Vec3::new(0.0, 0.0, 1.0);
+        let tri = Tri::new(
+            Vec3::new(-1.0, -1.0, 0.0),
+            Vec3::new(1.0, -1.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            z,
+            0,
+        );
+        let mesh = InstanceKind::Mesh(Arc::new(vec![tri]));
+        let mut scene = Scene::new();
+        scene.materials.push(Material::new("m", Vec3::new(0.5, 0.5, 0.5), None));
+        scene.instances.push(Instance::new(
+            mesh.clone(),
+            Mat4::translation(Vec3::new(-3.0, 0.0, 0.0)),
+        ).expect("invertible"));
+        // el segundo, girado 90° para mirar a +X y al doble de tamaño
+        scene.instances.push(Instance::new(
+            mesh,
+            Mat4::translation(Vec3::new(3.0, 0.0, -2.0))
+                * Mat4::rotation_y(90.0)
+                * Mat4::scale(Vec3::new(2.0, 2.0, 2.0)),
+        ).expect("invertible"));
+        let tex = vec![None];
+        let ctx = TraceCtx::new(&scene, &tex, 0.0);
 
-fn trace_voxels(ray: &Ray, voxels: &[Voxel]) -> Option<HitInfo> {
-    let mut closest_t = ray.tmax;
-    let mut best: Option<HitInfo> = None;
+        let a = trace_scene(&Ray::new(Vec3::new(-3.0, 0.0, 5.0), -z), &ctx).expect("primera");
+        assert_eq!(a.prim, Prim::Instance(0));
+        assert!((a.t - 5.0).abs() < 1e-9);
+        assert!((a.n - z).length() < 1e-9);
 
-    for v in voxels {
-        if let Some((t0, _t1)) = ray_box_intersect(ray, v.min, v.max, closest_t) {
-            if t0 < closest_t && t0 > ray.tmin {
-                closest_t = t0;
-                let p = ray.o + ray.d * t0;
-                let n = voxel_normal_at(p, v.min, v.max);
-                best = Some(HitInfo {
-                    t: t0,
-                    p,
-                    n,
-                    mat_id: v.mat_id,
-                    vmin: v.min,
-                    vmax: v.max,
+        let x = Vec3::new(1.0, 0.0, 0.0);
+        let b = trace_scene(&Ray::new(Vec3::new(8.0, 0.5, -2.0), -x), &ctx).expect("segunda");
+        assert_eq!(b.prim, Prim::Instance(1));
+        assert!((b.t - 5.0).abs() < 1e-9, "{}", b.t);
+        assert!((b.p - Vec3::new(3.0, 0.5, -2.0)).length() < 1e-9);
+        assert!((b.n - x).length() < 1e-9, "{:?}", b.n);
+        // fuera del triángulo escalado (medio ancho 2 en z)
+        assert!(trace_scene(&Ray::new(Vec3::new(8.0, 0.0, 0.5), -x), &ctx).is_none());
+
+        // entre las dos no hay nada, y las instancias dan sombra
+        assert!(trace_scene(&Ray::new(Vec3::new(0.0, 0.0, 5.0), -z), &ctx).is_none());
+        assert!(blocked_along(&Ray::new(Vec3::new(-3.0, 0.0, -1.0), z), &ctx, 10.0));
+        assert!(!blocked_along(&Ray::new(Vec3::new(-3.0, 0.0, -1.0), z), &ctx, 0.5));
+    }
+
+    #[test]
+    fn test_gamma_encoding() {
+        let mid = Color::new(0.18, 0.18, 0.18);
+        let mut r = Renderer::new(1, 1, 1);
+        let g = r.encode_output(mid);
+        assert!((g.x - 0.18f64.powf(1.0 / 2.2)).abs() < 1e-12);
+
+        r.set_gamma(1.0);
+        assert_eq!(r.encode_output(mid), mid);
+
+        r.set_gamma(2.2);
+        r.set_linear_output(true);
+        assert_eq!(r.encode_output(mid), mid);
+    }
+
+    #[test]
+    fn test_blocked_along_skips_closer_non_blocker() {
+        // Hojas recortadas delante y un bloque opaco detrás
+        let mut scene = cutout_scene();
+        scene.materials.push(Material::new("stone", Vec3::new(0.5, 0.5, 0.5), None));
+        scene.voxels.push(Voxel {
+            min: Vec3::new(-1.0, -1.0, 5.0),
+            max: Vec3::new(1.0, 1.0, 6.0),
+            mat_id: 1,
+            tint: NO_TINT,
+        });
+        let tex = [Some(tex_rgba(255, 255, 255, 0)), None];
+        let ctx = TraceCtx::new(&scene, &tex, 0.0);
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0));
+
+        assert!(blocked_along(&ray, &ctx, 10.0));
+        // El bloque queda fuera de rango; las hojas no cuentan
+        assert!(!blocked_along(&ray, &ctx, 4.5));
+        // Mismo veredicto que el camino de impacto más cercano
+        let mut r = ray;
+        r.tmax = 10.0;
+        assert_eq!(trace_closest(&r, &ctx, false).map(|h| h.mat_id), Some(1));
+    }
+
+    #[test]
+    fn test_shadow_through_blue_glass_is_tinted() {
+        let mut scene = Scene::new();
+        scene.materials.push(Material::new("floor", Vec3::new(0.8, 0.8, 0.8), None));
+        scene.materials.push(
+            Material::new("blue_glass", Vec3::new(0.1, 0.2, 0.9), None)
+                .with_transparency(0.5, 1.5),
+        );
+        scene.voxels.push(Voxel {
+            min: Vec3::new(-5.0, -1.0, -5.0),
+            max: Vec3::new(5.0, 0.0, 5.0),
+            mat_id: 0,
+            tint: NO_TINT,
+        });
+        scene.voxels.push(Voxel {
+            min: Vec3::new(-1.0, 2.0, -1.0),
+            max: Vec3::new(1.0, 2.2, 1.0),
+            mat_id: 1,
+            tint: NO_TINT,
+        });
+        let tex: Vec<Option<Tex>> = vec![None, None];
+        let ctx = TraceCtx::new(&scene, &tex, 0.0);
+
+        // Del piso hacia una luz encima del panel
+        let ray = Ray::new(Vec3::new(0.0, 1e-3, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        let t = shadow_transmittance(&ray, &ctx, 5.0);
+        assert!(t.z > t.x && t.z > t.y, "debe quedar teñida de azul: {:?}", t);
+        assert!(t.z > 0.0 && t.z < 1.0, "debe llegar atenuada: {:?}", t);
+
+        // El mismo panel opaco corta la luz por completo
+        scene.materials[1].transparency = 0.0;
+        let ctx = TraceCtx::new(&scene, &tex, 0.0);
+        assert_eq!(shadow_transmittance(&ray, &ctx, 5.0), Color::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_voxel_normal_picks_entry_face_at_corner() {
+        let (min, max) = (Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 1.0));
+        // Entra por +X casi en la arista con +Y; por error numérico el punto
+        // queda más cerca de +Y, pero el rayo sube y no pudo entrar por ahí
+        let p = Vec3::new(0.999, 0.9995, 0.5);
+        let d = Vec3::new(-1.0, 0.01, 0.0).normalized();
+        assert_eq!(voxel_normal_at(p, min, max, d), Vec3::new(1.0, 0.0, 0.0));
+        // bajando sí pudo entrar por +Y
+        let d = Vec3::new(-1.0, -0.01, 0.0).normalized();
+        assert_eq!(voxel_normal_at(p, min, max, d), Vec3::new(0.0, 1.0, 0.0));
+
+        // y de punta a punta por voxel_hit
+        let scene = Scene {
+            materials: vec![Material::new("m", Vec3::new(0.5, 0.5, 0.5), None)],
+            voxels: vec![Voxel { min, max, mat_id: 0, tint: NO_TINT }],
+            ..Scene::new()
+        };
+        let tex = vec![None];
+        let ctx = TraceCtx::new(&scene, &tex, 0.0);
+        let ray = Ray::new(Vec3::new(3.0, 0.97, 0.5), Vec3::new(-1.0, 0.01, 0.0));
+        let hit = trace_scene(&ray, &ctx).expect("impacto");
+        assert_eq!(hit.n, Vec3::new(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_triplanar_blends_projections_at_45_degrees() {
+        let n = Vec3::new(1.0, 1.0, 0.0).normalized();
+        let [wx, wy, wz] = triplanar_weights(n);
+        assert!((wx - 0.5).abs() < 1e-9 && (wy - 0.5).abs() < 1e-9 && wz == 0.0);
+        assert!(triplanar_weights(Vec3::new(0.1, 1.0, 0.0))[1] > 0.99);
+
+        // 2x1: negro a la izquierda, blanco a la derecha. La proyección X
+        // (u = z) cae en negro y la Y (u = x) en blanco
+        let tex = Tex { w: 2, h: 1, data: vec![0, 0, 0, 255, 255, 255, 255, 255] };
+        let c = sample_tex_triplanar(&tex, Vec3::new(0.75, 0.0, 0.25), n, 1.0);
+        assert!((c - Color::new(0.5, 0.5, 0.5)).length() < 1e-9, "{:?}", c);
+    }
+
+    #[test]
+    fn test_ray_through_sphere_center() {
+        let mut scene = small_scene();
+        scene.spheres.push(Sphere::new(Vec3::new(0.5, 3.0, 0.5), 0.75, 0));
+        let tex = vec![None; scene.materials.len()];
+        let ctx = TraceCtx::new(&scene, &tex, 0.0);
+        let d = Vec3::new(1.0, 0.0, 1.0).normalized();
+        let center = scene.spheres[0].center;
+        let ray = Ray::new(center - d * 5.0, d);
+        let hit = trace_scene(&ray, &ctx).expect("impacto");
+        assert_eq!(hit.prim, Prim::Sphere(0));
+        assert!((hit.p - (center - d * 0.75)).length() < 1e-9);
+        assert!((hit.t - 4.25).abs() < 1e-9);
+        assert!((hit.n + d).length() < 1e-9, "normal hacia afuera: {:?}", hit.n);
+
+        // y bloquea rayos de sombra que pasan por ella
+        assert!(blocked_along(&ray, &ctx, 10.0));
+        let beside = Ray::new(center + Vec3::new(0.0, 1.0, 0.0) - d * 5.0, d);
+        assert!(trace_scene(&beside, &ctx).is_none_or(|h| h.prim != Prim::Sphere(0)));
+    }
+
+    #[test]
+    fn test_ground_plane_hit_and_miss() {
+        let mut scene = Scene::new();
+        scene.materials.push(Material::new("suelo", Vec3::new(0.5, 0.5, 0.5), None));
+        scene.planes.push(Plane::ground(-1.0, 0));
+        let tex = vec![None];
+        let ctx = TraceCtx::new(&scene, &tex, 0.0);
+
+        let down = Ray::new(Vec3::new(3.0, 2.0, -7.0), Vec3::new(0.0, -1.0, 0.0));
+        let hit = trace_scene(&down, &ctx).expect("impacto");
+        assert_eq!(hit.prim, Prim::Plane(0));
+        assert!((hit.t - 3.0).abs() < 1e-12);
+        assert_eq!(hit.n, Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!((hit.u, hit.v), (3.0, -7.0));
+
+        let up = Ray::new(Vec3::new(3.0, 2.0, -7.0), Vec3::new(0.0, 1.0, 0.0));
+        assert!(trace_scene(&up, &ctx).is_none());
+        // lejos, casi rasante: sin bordes no se escapa
+        let grazing = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, -1e-4, 0.0));
+        assert!(trace_scene(&grazing, &ctx).is_some_and(|h| h.t > 9000.0));
+    }
+
+    #[test]
+    fn test_area_light_soft_shadow() {
+        // Panel de 2x2 a y = 4 mirando hacia abajo; una losa a y = 2 tapa
+        // desde x = 0.5 hacia +x
+        let mut scene = Scene::new();
+        scene.materials.push(Material::new("gris", Vec3::new(0.8, 0.8, 0.8), None));
+        scene.voxels.push(Voxel {
+            min: Vec3::new(0.5, 2.0, -4.0),
+            max: Vec3::new(9.0, 2.2, 4.0),
+            mat_id: 0,
+            tint: NO_TINT,
+        });
+        let panel = AreaLight {
+            corner: Vec3::new(-1.0, 4.0, -1.0),
+            edge_u: Vec3::new(2.0, 0.0, 0.0),
+            edge_v: Vec3::new(0.0, 0.0, 2.0),
+            color: Vec3::new(1.0, 1.0, 1.0),
+            intensity: 5.0,
+        };
+        let mut lit = scene.clone();
+        lit.area_lights.push(panel);
+
+        // aporte medio del panel en (x, 0, 0) sobre el piso
+        let light_at = |x: f64| {
+            let shade = |scene: &Scene| {
+                let mut r = Renderer::new(1, 1, 1);
+                r.set_scene(scene);
+                let stats = FrameStats::default();
+                let fc = r.frame_ctx(100.0, None, 0, &stats);
+                let ctx = TraceCtx::new(scene, &r.tex_cache, 100.0);
+                let up = Vec3::new(0.0, 1.0, 0.0);
+                let hit = HitInfo {
+                    t: 1.0,
+                    p: Vec3::new(x, 0.0, 0.0),
+                    n: up,
+                    mat_id: 0,
+                    tint: NO_TINT,
+                    u: 0.0,
+                    v: 0.0,
+                    prim: Prim::Voxel(0),
+                    uv_grad: None,
+                };
+                let ray = Ray::new(Vec3::new(x, 1.0, 0.0), -up);
+                let (mut rng, mut ts) = (Rng::new(9), TileStats::default());
+                let n = 512;
+                let sum = (0..n).fold(0.0, |acc, _| {
+                    acc + luminance(fc.shade_hit(&ctx, &ray, &hit, &mut rng, &mut ts))
                 });
+                sum / n as f64
+            };
+            shade(&lit) - shade(&scene)
+        };
+        let (under, edge, hidden) = (light_at(0.0), light_at(1.0), light_at(5.0));
+        assert!(under > 0.5, "{}", under);
+        assert!(hidden.abs() < 1e-9, "{}", hidden);
+        assert!(edge > 0.1 * under && edge < 0.9 * under, "{} vs {}", edge, under);
+    }
+
+    #[test]
+    fn test_cached_inverse_matches_per_box_inverse() {
+        // La versión de antes: la inversa se recalcula en cada caja
+        fn per_box(ray: &Ray, min: Vec3, max: Vec3, max_t: f64) -> Option<(f64, f64)> {
+            let inv = |x: f64| if x.abs() < 1e-8 { 1.0e8 } else { 1.0 / x };
+            let (ax, bx) = ((min.x - ray.o.x) * inv(ray.d.x), (max.x - ray.o.x) * inv(ray.d.x));
+            let (ay, by) = ((min.y - ray.o.y) * inv(ray.d.y), (max.y - ray.o.y) * inv(ray.d.y));
+            let (az, bz) = ((min.z - ray.o.z) * inv(ray.d.z), (max.z - ray.o.z) * inv(ray.d.z));
+            let (mut t0, mut t1) = (ax.min(bx), ax.max(bx));
+            if t0 > ay.max(by) || ay.min(by) > t1 {
+                return None;
+            }
+            (t0, t1) = (t0.max(ay.min(by)), t1.min(ay.max(by)));
+            if t0 > az.max(bz) || az.min(bz) > t1 {
+                return None;
+            }
+            (t0, t1) = (t0.max(az.min(bz)), t1.min(az.max(bz)));
+            (t0 >= ray.tmin && t0 <= max_t).then_some((t0, t1))
+        }
+
+        let mut rng = Rng::new(905);
+        let mut v3 = |s: f64| {
+            Vec3::new(rng.next_f64() - 0.5, rng.next_f64() - 0.5, rng.next_f64() - 0.5) * s
+        };
+        let boxes: Vec<(Vec3, Vec3)> = (0..64)
+            .map(|_| {
+                let c = v3(10.0);
+                (c, c + v3(2.0) + Vec3::new(1.1, 1.1, 1.1))
+            })
+            .collect();
+        let mut hits = 0;
+        for i in 0..2000 {
+            let mut d = v3(2.0);
+            // algunos rayos alineados a un eje, donde entra `safe_inv`
+            if i % 7 == 0 {
+                d = Vec3::new(0.0, d.y, 0.0);
+            }
+            let ray = Ray::new(v3(16.0), d);
+            let inv_d = inv_dir(ray.d);
+            for &(min, max) in &boxes {
+                let cached = ray_box_intersect_inv(&ray, inv_d, min, max, 50.0);
+                assert_eq!(cached, per_box(&ray, min, max, 50.0));
+                hits += cached.is_some() as usize;
             }
         }
+        assert!(hits > 100, "{}", hits);
     }
-    best
-}
 
-fn voxel_normal_at(p: Vec3, min: Vec3, max: Vec3) -> Vec3 {
-    let dxmin = (p.x - min.x).abs();
-    let dxmax = (p.x - max.x).abs();
-    let dymin = (p.y - min.y).abs();
-    let dymax = (p.y - max.y).abs();
-    let dzmin = (p.z - min.z).abs();
-    let dzmax = (p.z - max.z).abs();
+    #[test]
+    fn test_backface_culling() {
+        let mut scene = Scene::new();
+        scene.materials.push(Material::new("mesh", Vec3::new(0.7, 0.7, 0.7), None));
+        // Normal hacia +Z
+        scene.triangles.push(Tri::new(
+            Vec3::new(-1.0, -1.0, 0.0),
+            Vec3::new(1.0, -1.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            0,
+        ));
+        scene.cull_backfaces = true;
+        let tex: Vec<Option<Tex>> = vec![None];
+        let ctx = TraceCtx::new(&scene, &tex, 0.0);
+
+        let front = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let back = Ray::new(Vec3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0));
+        assert!(trace_scene(&front, &ctx).is_some());
+        assert!(trace_scene(&back, &ctx).is_none());
+        // Las sombras siguen siendo de dos caras
+        assert!(blocked_along(&back, &ctx, 10.0));
 
-    let mut best = dxmin;
-    let mut n = Vec3::new(-1.0, 0.0, 0.0);
+        scene.cull_backfaces = false;
+        let ctx = TraceCtx::new(&scene, &tex, 0.0);
+        assert!(trace_scene(&back, &ctx).is_some());
+    }
+
+    #[test]
+    fn test_emissive_triangle_becomes_light() {
+        let mut scene = small_scene();
+        scene.materials.push(
+            Material::new("lamp", Vec3::new(1.0, 0.9, 0.6), None)
+                .with_emissive(Vec3::new(3.0, 2.0, 1.0)),
+        );
+        scene.triangles.push(Tri::new(
+            Vec3::new(0.0, 3.0, 0.0),
+            Vec3::new(3.0, 3.0, 0.0),
+            Vec3::new(0.0, 3.0, 3.0),
+            Vec3::new(0.0, -1.0, 0.0),
+            2,
+        ));
 
-    if dxmax < best {
-        best = dxmax;
-        n = Vec3::new(1.0, 0.0, 0.0);
+        let mut r = Renderer::new(4, 4, 1);
+        r.set_scene(&scene);
+        assert_eq!(r.lights.len(), 1);
+        assert_eq!(r.lights[0].pos, Vec3::new(1.0, 3.0, 1.0));
+        assert_eq!(r.lights[0].color, Vec3::new(3.0, 2.0, 1.0));
     }
-    if dymin < best {
-        best = dymin;
-        n = Vec3::new(0.0, -1.0, 0.0);
+
+    #[test]
+    fn test_non_finite_pixels_are_zeroed_and_counted() {
+        let mut fb = vec![Color::new(0.5, 0.5, 0.5); 4];
+        fb[1] = Color::new(f64::NAN, 0.2, f64::INFINITY);
+        let mut clean = fb.clone();
+        assert_eq!(sanitize(&mut clean), 2);
+        assert_eq!(clean[1], Color::new(0.0, 0.2, 0.0));
+        assert_eq!(clean[0], fb[0]);
+
+        let r = Renderer::new(2, 2, 1);
+        let mut img = Image::new(2, 2);
+        let stats = FrameStats::default();
+        let out = r.resolve(&mut img, &fb, 0.0, None, &stats, Instant::now());
+        assert_eq!(out.non_finite, 2);
+        let p = img.get(1, 0);
+        assert!(p.x.is_finite() && p.y.is_finite() && p.z.is_finite());
+        assert_eq!((p.x, p.z), (0.0, 0.0));
     }
-    if dymax < best {
-        best = dymax;
-        n = Vec3::new(0.0, 1.0, 0.0);
+
+    #[test]
+    fn test_single_thread_matches_parallel() {
+        let render = |threads: Option<usize>| {
+            let mut r = Renderer::new(24, 16, 2);
+            r.tilesz = 4;
+            r.set_scene(&small_scene());
+            r.set_camera(&small_pose());
+            r.set_threads(threads);
+            let mut img = Image::new(24, 16);
+            r.render_frame(&mut img, 30.0);
+            img.data
+        };
+        let single = render(Some(1));
+        assert_eq!(single, render(None));
+        assert_eq!(single, render(Some(3)));
     }
-    if dzmin < best {
-        best = dzmin;
-        n = Vec3::new(0.0, 0.0, -1.0);
+
+    #[test]
+    fn test_stats_count_primary_rays() {
+        let mut r = Renderer::new(10, 10, 1);
+        r.set_scene(&Scene::new());
+        // Mirando al cielo en una escena vacía: ningún impacto
+        r.set_camera(&small_pose());
+        r.set_timing(true);
+        let mut img = Image::new(10, 10);
+        let stats = r.render_frame(&mut img, 30.0);
+        assert_eq!(stats.primary_rays, 100);
+        assert_eq!(stats.shadow_rays, 0);
+        assert_eq!(stats.rays, 100);
+        assert!(stats.total_ms >= stats.tonemap_ms);
     }
-    if dzmax < best {
-        best = dzmax;
-        n = Vec3::new(0.0, 0.0, 1.0);
+
+    /// Piso + un bloque, vistos desde arriba en diagonal.
+    fn small_scene() -> Scene {
+        let mut scene = Scene::new();
+        scene.materials.push(Material::new("floor", Vec3::new(0.6, 0.7, 0.5), None));
+        scene.materials.push(Material::new("block", Vec3::new(0.8, 0.4, 0.3), None));
+        scene.voxels.push(Voxel {
+            min: Vec3::new(-4.0, -1.0, -4.0),
+            max: Vec3::new(4.0, 0.0, 4.0),
+            mat_id: 0,
+            tint: NO_TINT,
+        });
+        scene.voxels.push(Voxel {
+            min: Vec3::new(-1.0, 0.0, -1.0),
+            max: Vec3::new(1.0, 2.0, 1.0),
+            mat_id: 1,
+            tint: NO_TINT,
+        });
+        scene
     }
-    n
-}
 
-/* ====================== Skybox mapping ====================== */
+    fn small_pose() -> CameraPose {
+        CameraPose {
+            eye: Vec3::new(5.0, 4.0, 6.0),
+            target: Vec3::new(0.0, 0.5, 0.0),
+            up: Vec3::new(0.0, 1.0, 0.0),
+            fov_deg: 60.0,
+        }
+    }
 
-fn dir_to_cube_uv(d: Vec3) -> (usize, f64, f64) {
-    let ax = d.x.abs();
-    let ay = d.y.abs();
-    let az = d.z.abs();
+    /// Luminancia de `fc.shade_hit` para un impacto armado a mano en `p`
+    /// (normal `n`, material `mat_id`, cara de voxel) visto por `ray`.
+    /// `scene` es la escena de `fc`.
+    fn shade_hit_at(
+        fc: &FrameCtx,
+        scene: &Scene,
+        ray: &Ray,
+        p: Vec3,
+        n: Vec3,
+        mat_id: usize,
+        rng: &mut Rng,
+    ) -> f64 {
+        let hit = HitInfo {
+            t: (p - ray.o).length(),
+            p,
+            n,
+            mat_id,
+            tint: NO_TINT,
+            u: 0.0,
+            v: 0.0,
+            prim: Prim::Voxel(0),
+            uv_grad: None,
+        };
+        luminance(fc.shade_hit(&fc.trace_ctx(scene), ray, &hit, rng, &mut TileStats::default()))
+    }
 
-    let (face, sc, tc, ma) = if ax >= ay && ax >= az {
-        if d.x > 0.0 {
-            (0usize, -d.z, -d.y, ax)
-        } else {
-            (1usize, d.z, -d.y, ax)
+    /// `shade_hit_at` con el cuadro de `r` en `time` (la escena ya puesta
+    /// con `set_scene`) y una semilla fija.
+    fn shade_point(r: &Renderer, time: f64, ray: &Ray, p: Vec3, n: Vec3, mat_id: usize) -> f64 {
+        let stats = FrameStats::default();
+        let fc = r.frame_ctx(time, None, 0, &stats);
+        shade_hit_at(&fc, r.scene.as_ref().unwrap(), ray, p, n, mat_id, &mut Rng::new(1))
+    }
+
+    fn tmp_dir(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("pg_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test_grazing_view_filters_along_compressed_axis() {
+        let mut scene = Scene::new();
+        scene.materials.push(Material::new("grass", Vec3::new(1.0, 1.0, 1.0), None));
+        scene.voxels.push(Voxel {
+            min: Vec3::new(-50.0, -1.0, -50.0),
+            max: Vec3::new(50.0, 0.0, 50.0),
+            mat_id: 0,
+            tint: NO_TINT,
+        });
+        let tex = vec![None];
+        let ctx = TraceCtx::new(&scene, &tex, 0.0);
+        // Cámara a ras del piso mirando al horizonte
+        let pose = CameraPose {
+            eye: Vec3::new(0.0, 1.0, 10.0),
+            target: Vec3::new(0.0, 0.8, 0.0),
+            up: Vec3::new(0.0, 1.0, 0.0),
+            fov_deg: 60.0,
+        };
+        let (w, h) = (64, 64);
+        let ray_at = |sx: f64, sy: f64| make_primary_ray(sx, sy, w, h, 1.0, (1e-4, 1e6), &pose);
+        let (sx, sy) = (32.5, 40.5);
+        let hit = trace_scene(&ray_at(sx, sy), &ctx).unwrap();
+        let g = uv_gradients(&ctx, &hit, &ray_at(sx + 1.0, sy), &ray_at(sx, sy + 1.0)).unwrap();
+        // en pantalla y el piso se comprime: la v (profundidad) cambia mucho más
+        let (along_x, along_y) = (g[0].hypot(g[1]), g[2].hypot(g[3]));
+        assert!(along_y > 4.0 * along_x, "{:?}", g);
+
+        let (iso_lod, iso_n, _) = aniso_footprint(g, 512, 512, 1.0);
+        let (lod, n, step) = aniso_footprint(g, 512, 512, 16.0);
+        assert_eq!(iso_n, 1);
+        assert!(n > 1);
+        // el eje corto elige el mip: más fino que el isotrópico, pero aún > 0
+        assert!(iso_lod > lod && lod > 0.0, "{} {}", iso_lod, lod);
+        // las muestras van a lo largo del eje comprimido (v)
+        assert!(step.1.abs() > step.0.abs());
+
+        let chain = mip_chain(&Tex { w: 8, h: 4, data: vec![200; 8 * 4 * 4] });
+        let sizes: Vec<_> = chain.iter().map(|t| (t.w, t.h)).collect();
+        assert_eq!(sizes, vec![(4, 2), (2, 1), (1, 1)]);
+    }
+
+    #[test]
+    fn test_portal_facing_portal_jumps_twice() {
+        let mut scene = Scene::new();
+        // A delante de la cámara; su salida queda justo frente a B, que
+        // manda a (200, 0, 0) girando 90°
+        scene.portals.push(Portal {
+            min: Vec3::new(-1.0, -1.0, -5.1),
+            max: Vec3::new(1.0, 1.0, -4.9),
+            to_pos: Vec3::new(100.0, 0.0, 0.0),
+            rot_y_deg: 0.0,
+        });
+        scene.portals.push(Portal {
+            min: Vec3::new(99.0, -1.0, -5.1),
+            max: Vec3::new(101.0, 1.0, -4.9),
+            to_pos: Vec3::new(200.0, 0.0, 0.0),
+            rot_y_deg: 90.0,
+        });
+        let tex: Vec<Option<Tex>> = Vec::new();
+        let ctx = TraceCtx::new(&scene, &tex, 0.0);
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+
+        let walk = portal_walk(&ray, &ctx, None, 4);
+        assert_eq!(walk.jumps, 2);
+        // Entra a A en z = -4.9 (0.1 delante del centro), aparece igual de
+        // delante del centro de B y sale de B girado: -Z pasa a -X
+        assert!((walk.ray.o - Vec3::new(200.1, 0.0, 0.0)).length() < 1e-9, "{:?}", walk.ray.o);
+        assert!((walk.ray.d - Vec3::new(-1.0, 0.0, 0.0)).length() < 1e-9);
+        // el marco compuesto lleva el rayo original al final
+        let carried = walk.carry(&ray);
+        assert!((carried.d - walk.ray.d).length() < 1e-9);
+
+        assert_eq!(portal_walk(&ray, &ctx, None, 1).jumps, 1);
+        assert_eq!(portal_walk(&ray, &ctx, None, 0).jumps, 0);
+    }
+
+    #[test]
+    fn test_emissive_sun_casts_shadows_away_from_it() {
+        let mut scene = small_scene();
+        scene.materials.push(
+            Material::new("sun", Vec3::new(1.0, 1.0, 1.0), None)
+                .with_emission(Vec3::new(1.0, 0.9, 0.5), 200.0),
+        );
+        // Sol bajo por +x: el bloque (-1..1, 0..2) tapa el lado -x
+        scene.voxels.push(Voxel {
+            min: Vec3::new(28.0, 5.0, -2.0),
+            max: Vec3::new(32.0, 9.0, 2.0),
+            mat_id: 2,
+            tint: NO_TINT,
+        });
+        let shade = |source: SunSource, x: f64| {
+            let mut r = Renderer::new(1, 1, 1);
+            r.set_sun_source(source);
+            r.set_scene(&scene);
+            let stats = FrameStats::default();
+            let fc = r.frame_ctx(35.0, None, 0, &stats);
+            let ctx = TraceCtx::new(&scene, &r.tex_cache, 35.0);
+            let up = Vec3::new(0.0, 1.0, 0.0);
+            let hit = HitInfo {
+                t: 1.0,
+                p: Vec3::new(x, 0.0, 0.0),
+                n: up,
+                mat_id: 0,
+                tint: NO_TINT,
+                u: 0.0,
+                v: 0.0,
+                prim: Prim::Voxel(0),
+                uv_grad: None,
+            };
+            let ray = Ray::new(Vec3::new(x, 1.0, 0.0), -up);
+            let mut ts = TileStats::default();
+            luminance(fc.shade_hit(&ctx, &ray, &hit, &mut Rng::new(3), &mut ts))
+        };
+        let (toward, away) = (shade(SunSource::Emissive, 2.0), shade(SunSource::Emissive, -2.0));
+        assert!(toward > 2.0 * away, "{} {}", toward, away);
+        // con el sol analítico a mediodía los dos lados se ven iguales
+        let (a, b) = (shade(SunSource::Analytic, 2.0), shade(SunSource::Analytic, -2.0));
+        assert!((a - b).abs() < 0.05 * a, "{} {}", a, b);
+    }
+
+    #[test]
+    fn test_max_texture_size_downscales_in_linear() {
+        // Damero blanco y negro de 256 px
+        let img = image::RgbaImage::from_fn(256, 256, |x, y| {
+            let v = if (x + y) % 2 == 0 { 255 } else { 0 };
+            image::Rgba([v, v, v, 255])
+        });
+        let dir = tmp_dir("tex");
+        fs::create_dir_all(&dir).unwrap();
+        let path = format!("{}/checker.png", dir);
+        img.save(&path).unwrap();
+
+        let full = load_tex(&path, None).unwrap();
+        assert_eq!((full.w, full.h), (256, 256));
+        let small = load_tex(&path, Some(64)).unwrap();
+        assert_eq!((small.w, small.h), (64, 64));
+        assert_eq!(small.data.len(), 64 * 64 * 4);
+        // Mitad de energía en lineal: ~186 en sRGB, no 128
+        let px = &small.data[(10 * 64 + 20) * 4..][..4];
+        assert!((185..=188).contains(&px[0]), "{:?}", px);
+        assert_eq!(px[3], 255);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_bmp_textures_keep_row_order() {
+        // 2x2: rojo arriba a la izquierda, el resto gris
+        let mut img = Image::new(2, 2);
+        for (x, y) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            img.set(x, y, Color::new(0.5, 0.5, 0.5));
         }
-    } else if ay >= ax && ay >= az {
-        if d.y > 0.0 {
-            (2usize, d.x, d.z, ay)
-        } else {
-            (3usize, d.x, -d.z, ay)
+        img.set(0, 0, Color::new(1.0, 0.0, 0.0));
+        let dir = tmp_dir("bmp_rows");
+        fs::create_dir_all(&dir).unwrap();
+        let bottom_up = format!("{}/bottom_up.bmp", dir);
+        img.save_bmp(&bottom_up);
+
+        // la misma imagen top-down: alto negativo y filas en orden inverso
+        let mut buf = fs::read(&bottom_up).unwrap();
+        buf[22..26].copy_from_slice(&(-2i32).to_le_bytes());
+        let stride = 8;
+        let (first, second) = (buf[54..54 + stride].to_vec(), buf[54 + stride..].to_vec());
+        buf.truncate(54);
+        buf.extend(second.iter().chain(&first));
+        let top_down = format!("{}/top_down.bmp", dir);
+        fs::write(&top_down, &buf).unwrap();
+
+        let red = [255, 0, 0, 255];
+        for path in [&bottom_up, &top_down] {
+            let a = load_tex(path, None).unwrap();
+            let b = bmp_tex(&Image::load_bmp(path).unwrap());
+            for t in [&a, &b] {
+                assert_eq!((t.w, t.h), (2, 2));
+                assert_eq!(t.data[..4], red, "{}", path);
+                assert_ne!(t.data[8..12], red, "{}", path);
+            }
         }
-    } else if d.z > 0.0 {
-        (4usize, d.x, -d.y, az)
-    } else {
-        (5usize, -d.x, -d.y, az)
-    };
+        let _ = fs::remove_dir_all(&dir);
+    }
 
-    let u = 0.5 * (sc / ma + 1.0);
-    let v = 0.5 * (tc / ma + 1.0);
-    (face, u, v)
-}
+    #[test]
+    fn test_render_sequence_matches_single_frames() {
+        let mut r = Renderer::new(24, 16, 2);
+        r.set_scene(&small_scene());
+        let time_fn = |f: u32| 20.0 + f as f64 * 7.0;
+        let camera_fn = |f: u32| {
+            let mut p = small_pose();
+            p.eye.x += f as f64 * 0.5;
+            p
+        };
 
-/* ====================== Texturas ====================== */
+        let seq_dir = tmp_dir("seq");
+        r.render_sequence(4, time_fn, camera_fn, &seq_dir);
 
-fn load_tex(path: &str) -> Option<Tex> {
-    let img = image::open(path).ok()?.to_rgb8();
-    let (w, h) = img.dimensions();
-    let data = img.into_raw();
+        let one_dir = tmp_dir("one");
+        fs::create_dir_all(&one_dir).unwrap();
+        let mut img = Image::new(24, 16);
+        for f in 0..4 {
+            r.set_camera(&camera_fn(f));
+            r.render_frame(&mut img, time_fn(f));
+            img.save_bmp(&format!("{}/frame_{:04}.bmp", one_dir, f));
+        }
 
-    Some(Tex {
-        w: w as usize,
-        h: h as usize,
-        data,
-    })
-}
+        for f in 0..4 {
+            let name = format!("frame_{:04}.bmp", f);
+            let a = fs::read(format!("{}/{}", seq_dir, name)).unwrap();
+            let b = fs::read(format!("{}/{}", one_dir, name)).unwrap();
+            assert!(a == b, "el cuadro {} difiere", f);
+        }
+        let _ = fs::remove_dir_all(&seq_dir);
+        let _ = fs::remove_dir_all(&one_dir);
+    }
 
-fn sample_tex_nearest(tex: &Tex, mut u: f64, mut v: f64) -> Color {
-    u = u.fract();
-    if u < 0.0 {
-        u += 1.0;
+    #[test]
+    fn test_reference_mode_drops_min_light_floor() {
+        let mut r = Renderer::new(1, 1, 1);
+        r.set_scene(&small_scene());
+        let stats = FrameStats::default();
+        let fc = r.frame_ctx(30.0, None, 0, &stats);
+        assert!(fc.min_light > 0.0);
+        assert_eq!(fc.gi.max_depth, 0);
+
+        r.set_reference_mode(true);
+        let fc = r.frame_ctx(30.0, None, 0, &stats);
+        assert_eq!(fc.min_light, 0.0);
+        assert_eq!(fc.gi.max_depth, REFERENCE_GI_DEPTH);
     }
-    v = v.fract();
-    if v < 0.0 {
-        v += 1.0;
+
+    #[test]
+    fn test_frame_callback_moves_a_voxel() {
+        let mut r = Renderer::new(24, 16, 1);
+        r.set_scene(&small_scene());
+        r.set_camera(&small_pose());
+        let block_pixels = |r: &Renderer| -> Vec<(usize, usize)> {
+            (0..16)
+                .flat_map(|y| (0..24).map(move |x| (x, y)))
+                .filter(|&(x, y)| r.pick(x, y).is_some_and(|p| p.mat_id == 1))
+                .collect()
+        };
+        // el bloque se corre 2 en x a partir de t = 1
+        let slide = |scene: &mut Scene, t: f64| {
+            if t >= 1.0 && scene.voxels[1].min.x < 0.0 {
+                scene.voxels[1].min.x += 2.0;
+                scene.voxels[1].max.x += 2.0;
+            }
+        };
+        let mut img = Image::new(24, 16);
+        r.render_frame_with(&mut img, 0.0, slide);
+        let before = (block_pixels(&r), img.data.clone());
+        r.render_frame_with(&mut img, 1.0, slide);
+        let after = block_pixels(&r);
+        assert!(!before.0.is_empty() && !after.is_empty());
+        assert_ne!(before.0, after);
+        assert_ne!(before.1, img.data);
+        assert_eq!(r.scene.as_ref().unwrap().voxels[1].min.x, 1.0);
     }
 
-    let x = (u * tex.w as f64)
-        .floor()
-        .clamp(0.0, (tex.w - 1) as f64) as usize;
-    let y = (v * tex.h as f64)
-        .floor()
-        .clamp(0.0, (tex.h - 1) as f64) as usize;
-    let idx = (y * tex.w + x) * 3;
+    #[test]
+    fn test_noop_frame_callback_keeps_accumulation() {
+        let mut r = Renderer::new(8, 6, 1);
+        r.set_scene(&small_scene());
+        r.set_camera(&small_pose());
+        let mut img = Image::new(8, 6);
+        r.accumulate_frame(&mut img, 30.0);
+        r.accumulate_frame(&mut img, 30.0);
+        assert_eq!(r.accumulated_passes(), 2);
 
-    let r = tex.data[idx] as f64 / 255.0;
-    let g = tex.data[idx + 1] as f64 / 255.0;
-    let b = tex.data[idx + 2] as f64 / 255.0;
-    Color::new(r, g, b)
-}
+        r.render_frame_with(&mut img, 30.0, |_, _| {});
+        r.accumulate_frame(&mut img, 30.0);
+        assert_eq!(r.accumulated_passes(), 3);
 
-fn tex_for_mat<'a>(mat_id: usize, cache: &'a [Option<Tex>]) -> Option<&'a Tex> {
-    if mat_id < cache.len() {
-        cache[mat_id].as_ref()
-    } else {
-        None
+        // mover un voxel sí la descarta
+        r.render_frame_with(&mut img, 30.0, |scene, _| scene.voxels[1].max.y += 1.0);
+        assert_eq!(r.accumulated_passes(), 0);
+
+        // y cambiar el color de un material también
+        r.accumulate_frame(&mut img, 30.0);
+        assert_eq!(r.accumulated_passes(), 1);
+        r.render_frame_with(&mut img, 30.0, |scene, _| {
+            scene.materials[1].albedo = Vec3::new(0.1, 0.2, 0.9);
+        });
+        assert_eq!(r.accumulated_passes(), 0);
     }
-}
 
-/* ========== UV helper (ajusta si ya lo tienes en otro lado) ========== */
+    #[test]
+    fn test_preview_scale_traces_a_quarter_of_the_pixels() {
+        let mut r = Renderer::new(24, 16, 4);
+        r.set_scene(&small_scene());
+        r.set_camera(&small_pose());
+        r.set_preview_scale(2);
+        let mut img = Image::new(24, 16);
+        for c in img.data.iter_mut() {
+            *c = Color::new(-1.0, -1.0, -1.0);
+        }
+        let stats = r.render_frame(&mut img, 30.0);
+        assert_eq!(stats.primary_rays, 12 * 8);
+        assert!(img.data.iter().all(|c| c.x >= 0.0));
+        // bloques de 2x2 iguales
+        assert_eq!(img.get(4, 6), img.get(5, 7));
+        assert_eq!((r.w, r.h, r.spp), (24, 16, 4));
 
-fn voxel_uv(_min: Vec3, _max: Vec3, p: Vec3, n: Vec3) -> (f64, f64) {
-    let (u, v) = if n.x.abs() > n.y.abs() && n.x.abs() > n.z.abs() {
-        (p.z, p.y)
-    } else if n.y.abs() > n.z.abs() {
-        (p.x, p.z)
-    } else {
-        (p.x, p.y)
-    };
-    (u as f64, v as f64)
+        r.set_preview_scale(1);
+        assert_eq!(r.render_frame(&mut img, 30.0).primary_rays, 24 * 16 * 4);
+    }
+
+    #[test]
+    fn test_scratch_buffer_reuse_matches_fresh_frames() {
+        let setup = || {
+            let mut r = Renderer::new(16, 12, 2);
+            r.set_scene(&small_scene());
+            r.set_camera(&small_pose());
+            r
+        };
+        let mut reused = setup();
+        let mut img = Image::new(16, 12);
+        for time in [20.0, 45.0] {
+            img.clear();
+            reused.render_frame(&mut img, time);
+            let mut fresh = Image::new(16, 12);
+            setup().render_frame(&mut fresh, time);
+            assert_eq!(img.data, fresh.data);
+        }
+        assert_eq!(reused.scratch.len(), 16 * 12);
+    }
+
+    #[test]
+    fn test_sliced_framebuffer_matches_collected_tiles() {
+        let mut r = Renderer::new(21, 14, 1);
+        r.set_scene(&small_scene());
+        r.tilesz = 4;
+        r.set_threads(Some(3));
+        let stats = FrameStats::default();
+        let fc = r.frame_ctx(30.0, Some(small_pose()), r.frame_seed(30.0, 0), &stats);
+        let mut sliced = Vec::new();
+        r.render_linear_into(&mut sliced, &fc, true, None);
+
+        // Referencia: cada tile a su propio Vec, volcados en serie al final
+        let mut expected = vec![Color::new(0.0, 0.0, 0.0); r.w * r.h];
+        for ty in (0..r.h).step_by(r.tilesz) {
+            for tx in (0..r.w).step_by(r.tilesz) {
+                for (x, y, c) in fc.render_tile(tx, ty, r.tilesz, SamplePlan::All) {
+                    expected[y * r.w + x] = c;
+                }
+            }
+        }
+        assert_eq!(sliced, expected);
+    }
+
+    #[test]
+    fn test_tiles_spread_over_more_threads_than_tile_rows() {
+        // 2 filas de tiles y 8 hilos: la cola reparte tiles, no filas
+        let mut r = Renderer::new(32, 8, 1);
+        r.tilesz = 4;
+        r.set_threads(Some(8));
+        let seen = Mutex::new(std::collections::HashSet::new());
+        let tile_fn = |x0: usize, y0: usize| {
+            seen.lock().unwrap().insert(thread::current().id());
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            (y0..y0 + 4).flat_map(|y| (x0..x0 + 4).map(move |x| (x, y, y * 32 + x))).collect()
+        };
+        let mut buf = vec![0usize; 32 * 8];
+        r.run_tiles(&mut buf, true, "test", None, tile_fn, |_, px, v| *px = v);
+        assert!(buf.iter().enumerate().all(|(i, &v)| v == i));
+        assert!(seen.lock().unwrap().len() > 2, "{:?}", seen.lock().unwrap().len());
+    }
+
+    #[test]
+    fn test_lighting_at_noon_is_brighter_than_dusk() {
+        let r = Renderer::new(1, 1, 1);
+        // ciclo de 140 s: mediodía en 35, el sol casi en el horizonte en 68
+        let (noon, dusk) = (r.lighting_at(35.0), r.lighting_at(68.0));
+        assert!(noon.sun_intensity > dusk.sun_intensity);
+        assert!(noon.sun_dir.y > dusk.sun_dir.y);
+        assert_eq!(noon.sun_color, r.dn.sun_color(35.0));
+    }
+
+    #[test]
+    fn test_zero_min_light_darkens_occluded_points() {
+        // Punto encerrado entre el piso y un techo bajo: sin sol directo
+        let mut scene = small_scene();
+        scene.voxels.push(Voxel {
+            min: Vec3::new(-4.0, 0.3, -4.0),
+            max: Vec3::new(4.0, 6.0, 4.0),
+            mat_id: 1,
+            tint: NO_TINT,
+        });
+        let shade = |k: Option<f64>| {
+            let mut r = Renderer::new(1, 1, 1);
+            if let Some(k) = k {
+                r.set_min_light(k);
+            }
+            r.set_scene(&scene);
+            let stats = FrameStats::default();
+            let fc = r.frame_ctx(30.0, None, 0, &stats);
+            let ctx = TraceCtx::new(&scene, &r.tex_cache, 30.0);
+            let up = Vec3::new(0.0, 1.0, 0.0);
+            let hit = HitInfo {
+                t: 0.1,
+                p: Vec3::new(2.5, 0.0, 2.5),
+                n: up,
+                mat_id: 0,
+                tint: NO_TINT,
+                u: 0.0,
+                v: 0.0,
+                prim: Prim::Voxel(0),
+                uv_grad: None,
+            };
+            let ray = Ray::new(Vec3::new(2.5, 0.1, 2.5), -up);
+            let mut ts = TileStats::default();
+            luminance(fc.shade_hit(&ctx, &ray, &hit, &mut Rng::new(2), &mut ts))
+        };
+        assert!(shade(Some(0.0)) < shade(None));
+        assert_eq!(shade(Some(DEFAULT_MIN_LIGHT)), shade(None));
+    }
+
+    #[test]
+    fn test_sidecar_records_the_camera() {
+        let mut r = Renderer::new(8, 8, 1);
+        r.set_scene(&small_scene());
+        r.set_seed(42);
+        let pose = small_pose();
+        r.set_camera(&pose);
+        let mut img = Image::new(8, 8);
+        let stats = r.render_frame(&mut img, 30.0);
+        let meta = r.frame_meta(30.0, None, &stats);
+        assert!(meta.camera.is_some_and(|c| c.eye == pose.eye));
+
+        let dir = tmp_dir("sidecar");
+        fs::create_dir_all(&dir).unwrap();
+        let frame = format!("{}/frame_0007.bmp", dir);
+        let json_path = format!("{}/frame_0007.json", dir);
+        // apagados por defecto: no escribe nada
+        r.save_sidecar(&frame, meta);
+        assert!(!Path::new(&json_path).exists());
+        r.set_sidecars(true);
+        r.save_sidecar(&frame, meta);
+        let json = fs::read_to_string(&json_path).unwrap();
+        let eye = format!("\"eye\": [{:?}, {:?}, {:?}]", pose.eye.x, pose.eye.y, pose.eye.z);
+        assert!(json.contains(&eye), "{}", json);
+        assert!(json.contains("\"seed\": 42") && json.contains("\"time\": 30.0"));
+
+        // NaN e infinitos no son JSON válido: van como null
+        let bad = FrameMeta { exposure: f64::NAN, time: f64::INFINITY, ..meta };
+        let json = bad.to_json();
+        assert!(json.contains("\"exposure\": null") && json.contains("\"time\": null"), "{}", json);
+        let _ = fs::remove_dir_all(&dir);
+    }
 }
@@ -0,0 +1,65 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::app::camera::CameraPose;
+use crate::core::vec3::Vec3;
+
+/// Lo necesario para volver a renderizar un cuadro guardado igual: se
+/// escribe como JSON al lado de la imagen (`frame_0007.bmp` ->
+/// `frame_0007.json`).
+#[derive(Clone, Copy, PartialEq)]
+pub struct FrameMeta {
+    /// None = la cámara fija por defecto del renderer
+    pub camera: Option<CameraPose>,
+    /// Hora del ciclo día/noche pasada a `render_frame`
+    pub time: f64,
+    pub spp: usize,
+    /// Semilla base (`Renderer::set_seed`); la del cuadro sale de ella y de
+    /// `time`
+    pub seed: u64,
+    pub exposure: f64,
+}
+
+/// Número JSON: `{:?}` escribe los f64 de forma que se leen de vuelta
+/// exactos; NaN e infinitos no existen en JSON y van como `null`.
+fn num(x: f64) -> String {
+    if x.is_finite() {
+        format!("{:?}", x)
+    } else {
+        "null".to_string()
+    }
+}
+
+impl FrameMeta {
+    pub fn to_json(&self) -> String {
+        let v = |v: Vec3| format!("[{}, {}, {}]", num(v.x), num(v.y), num(v.z));
+        let camera = match &self.camera {
+            Some(c) => format!(
+                "{{\"eye\": {}, \"target\": {}, \"up\": {}, \"fov_deg\": {}}}",
+                v(c.eye),
+                v(c.target),
+                v(c.up),
+                num(c.fov_deg)
+            ),
+            None => "null".to_string(),
+        };
+        format!(
+            concat!(
+                "{{\n  \"camera\": {},\n  \"time\": {},\n",
+                "  \"spp\": {},\n  \"seed\": {},\n  \"exposure\": {}\n}}\n"
+            ),
+            camera, num(self.time), self.spp, self.seed, num(self.exposure)
+        )
+    }
+
+    /// Ruta del JSON que acompaña a `frame_path`.
+    pub fn sidecar_path(frame_path: &str) -> PathBuf {
+        Path::new(frame_path).with_extension("json")
+    }
+
+    /// Escribe el JSON junto a `frame_path`.
+    pub fn save(&self, frame_path: &str) -> io::Result<()> {
+        fs::write(Self::sidecar_path(frame_path), self.to_json())
+    }
+}
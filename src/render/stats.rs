@@ -0,0 +1,111 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+/// Métricas de un `render_frame`.
+///
+/// Los conteos de rayos siempre se llevan; los tiempos por etapa
+/// (`primary_ms`, `shadow_ms`) solo con `Renderer::set_timing(true)`, porque
+/// medir cada consulta tiene su costo. Esos dos se suman entre hilos, así
+/// que pueden superar a `total_ms`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RenderStats {
+    /// Rayos lanzados en total (cámara + sombra + AO + rebotes)
+    pub rays: u64,
+    pub primary_rays: u64,
+    /// Rayos de sombra (sol y luces) y de AO
+    pub shadow_rays: u64,
+    /// Rebotes del modo GI y reflejos
+    pub bounce_rays: u64,
+    /// Trazado de rayos de cámara, sumado entre hilos
+    pub primary_ms: f64,
+    /// Rayos de sombra y AO, sumado entre hilos
+    pub shadow_ms: f64,
+    /// Pasada final de tonemap + gamma (reloj de pared)
+    pub tonemap_ms: f64,
+    /// Frame completo (reloj de pared)
+    pub total_ms: f64,
+    /// Exposición aplicada antes del tonemap (1 sin auto-exposición)
+    pub exposure: f64,
+    /// Componentes NaN/Inf del buffer lineal, puestos a 0 antes del tonemap
+    pub non_finite: u64,
+}
+
+impl RenderStats {
+    /// Suma de dos cuadros trazados juntos (p. ej. los dos ojos del
+    /// estéreo); la exposición queda la de `self`.
+    pub fn combined(self, o: RenderStats) -> RenderStats {
+        RenderStats {
+            rays: self.rays + o.rays,
+            primary_rays: self.primary_rays + o.primary_rays,
+            shadow_rays: self.shadow_rays + o.shadow_rays,
+            bounce_rays: self.bounce_rays + o.bounce_rays,
+            primary_ms: self.primary_ms + o.primary_ms,
+            shadow_ms: self.shadow_ms + o.shadow_ms,
+            tonemap_ms: self.tonemap_ms + o.tonemap_ms,
+            total_ms: self.total_ms + o.total_ms,
+            exposure: self.exposure,
+            non_finite: self.non_finite + o.non_finite,
+        }
+    }
+}
+
+/// Contadores locales de un tile, sin atómicos; al terminar el tile se
+/// vuelcan en `FrameStats`.
+#[derive(Default)]
+pub(crate) struct TileStats {
+    pub primary_rays: u64,
+    pub shadow_rays: u64,
+    pub bounce_rays: u64,
+    pub primary_ns: u64,
+    pub shadow_ns: u64,
+}
+
+/// Acumulador del frame compartido entre hilos.
+#[derive(Default)]
+pub(crate) struct FrameStats {
+    primary_rays: AtomicU64,
+    shadow_rays: AtomicU64,
+    bounce_rays: AtomicU64,
+    primary_ns: AtomicU64,
+    shadow_ns: AtomicU64,
+}
+
+impl FrameStats {
+    pub fn add(&self, t: &TileStats) {
+        self.primary_rays.fetch_add(t.primary_rays, Ordering::Relaxed);
+        self.shadow_rays.fetch_add(t.shadow_rays, Ordering::Relaxed);
+        self.bounce_rays.fetch_add(t.bounce_rays, Ordering::Relaxed);
+        self.primary_ns.fetch_add(t.primary_ns, Ordering::Relaxed);
+        self.shadow_ns.fetch_add(t.shadow_ns, Ordering::Relaxed);
+    }
+
+    pub fn finish(&self, tonemap_ms: f64, total_ms: f64) -> RenderStats {
+        let primary_rays = self.primary_rays.load(Ordering::Relaxed);
+        let shadow_rays = self.shadow_rays.load(Ordering::Relaxed);
+        let bounce_rays = self.bounce_rays.load(Ordering::Relaxed);
+        RenderStats {
+            rays: primary_rays + shadow_rays + bounce_rays,
+            primary_rays,
+            shadow_rays,
+            bounce_rays,
+            primary_ms: self.primary_ns.load(Ordering::Relaxed) as f64 / 1e6,
+            shadow_ms: self.shadow_ns.load(Ordering::Relaxed) as f64 / 1e6,
+            tonemap_ms,
+            total_ms,
+            exposure: 1.0,
+            non_finite: 0,
+        }
+    }
+}
+
+/// Ejecuta `f` y, si `enabled`, suma su duración en ns a `acc`.
+#[inline]
+pub(crate) fn timed<T>(enabled: bool, acc: &mut u64, f: impl FnOnce() -> T) -> T {
+    if !enabled {
+        return f();
+    }
+    let start = Instant::now();
+    let out = f();
+    *acc += start.elapsed().as_nanos() as u64;
+    out
+}
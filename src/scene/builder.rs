@@ -1,10 +1,44 @@
 use crate::core::vec3::Vec3;
-use crate::scene::{Material, Portal, Scene, Skybox};
-use crate::scene::voxel::Voxel;
+use crate::scene::{Material, Portal, Scene, Skybox, TextureSource};
+use crate::scene::voxel::{Voxel, NO_TINT};
 use crate::scene::mesh;
 
 fn add_box(scene: &mut Scene, min: Vec3, max: Vec3, mat_id: usize) {
-    scene.voxels.push(Voxel { min, max, mat_id });
+    scene.voxels.push(Voxel { min, max, mat_id, tint: NO_TINT });
+}
+
+/// Terreno en voxels desde una grilla de alturas `width` x `depth` (fila
+/// a fila en z, `heights[z * width + x]`). Cada celda es una columna de
+/// voxels unitarios desde y = 0 hasta `round(h * scale)`; el material de
+/// cada nivel sale de `mat_fn(nivel, alto_de_la_columna)`, p. ej. pasto en
+/// el último y tierra o piedra debajo.
+pub fn add_heightmap<F>(
+    scene: &mut Scene,
+    heights: &[f64],
+    width: usize,
+    depth: usize,
+    scale: f64,
+    mat_fn: F,
+) where
+    F: Fn(usize, usize) -> usize,
+{
+    assert!(heights.len() >= width * depth, "faltan alturas en el heightmap");
+    for z in 0..depth {
+        for x in 0..width {
+            let top = (heights[z * width + x] * scale).round().max(0.0) as usize;
+            for y in 0..top {
+                scene.voxels.push(Voxel::from_grid(x, y, z, mat_fn(y, top)));
+            }
+        }
+    }
+}
+
+/// Alturas en [0,1] desde una imagen en escala de grises (el brillo de cada
+/// píxel), con su ancho y alto, para `add_heightmap`.
+pub fn load_heightmap(path: &str) -> image::ImageResult<(Vec<f64>, usize, usize)> {
+    let img = image::open(path)?.into_luma8();
+    let heights = img.pixels().map(|p| p.0[0] as f64 / 255.0).collect();
+    Ok((heights, img.width() as usize, img.height() as usize))
 }
 
 pub fn build_minecraft_house_scene() -> Scene {
@@ -34,25 +68,30 @@ pub fn build_minecraft_house_scene() -> Scene {
         .with_uv_scale(2.0)
         .with_specular(0.04);
 
-    let glass = Material::new("glass", Vec3::new(0.95, 0.97, 1.0), Some("assets/textures/glass.jpeg"))
-        .with_uv_scale(1.0)
-        .with_specular(0.6)
-        .with_reflection(0.25);
+    let glass = Material {
+        texture: Some(TextureSource::Path("assets/textures/glass.jpeg")),
+        ..Material::preset("glass")
+    };
 
-    let water = Material::new("water", Vec3::new(0.25, 0.45, 0.95), Some("assets/textures/water.png"))
-        .with_uv_scale(6.0)
-        .animated(true)
-        .with_specular(0.12);
+    let water = Material {
+        texture: Some(TextureSource::Path("assets/textures/water.png")),
+        ..Material::preset("water")
+    }
+    .with_uv_scale(6.0)
+    .animated(true);
 
-    let torch = Material::new("torch", Vec3::new(1.00, 0.85, 0.45), None)
-        .with_emissive(Vec3::new(4.0, 2.6, 1.2));
+    let torch = Material { name: "torch", ..Material::preset("emissive_warm") };
 
-    let tree_leaves = Material::new("tree_leaves", Vec3::new(0.65, 0.85, 0.60), Some("assets/textures/tree.jpeg"))
-        .with_uv_scale(2.0)
-        .with_specular(0.02);
+    let tree_leaves = Material {
+        name: "tree_leaves",
+        texture: Some(TextureSource::Path("assets/textures/tree.jpeg")),
+        ..Material::preset("leaves")
+    }
+    .with_uv_scale(2.0);
 
     let sun = Material::new("sun", Vec3::new(1.0, 0.95, 0.85), None)
-        .with_emissive(Vec3::new(20.0, 18.0, 10.0));
+        .with_emission(Vec3::new(1.0, 0.9, 0.5), 20.0)
+        .with_flicker(0.0, 0.0);
 
     scene.materials.extend([
         grass,
@@ -283,6 +322,36 @@ pub fn build_minecraft_house_scene() -> Scene {
         Vec3::new(15.0, 1.0, 10.0),
     );
     scene.triangles.extend(tris);
+    // El conejo es una malla cerrada
+    scene.cull_backfaces = true;
 
     scene
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heightmap_columns() {
+        let mut scene = Scene::new();
+        // alturas 1, 3 / 0, 2 con escala 1
+        let (grass, dirt) = (0, 1);
+        let top_grass = |y: usize, top: usize| if y + 1 == top { grass } else { dirt };
+        add_heightmap(&mut scene, &[1.0, 3.0, 0.0, 2.2], 2, 2, 1.0, top_grass);
+        assert_eq!(scene.voxels.len(), 6);
+
+        let column = |x: f64, z: f64| -> Vec<(f64, usize)> {
+            scene
+                .voxels
+                .iter()
+                .filter(|v| v.min.x == x && v.min.z == z)
+                .map(|v| (v.min.y, v.mat_id))
+                .collect()
+        };
+        assert_eq!(column(0.0, 0.0), vec![(0.0, grass)]);
+        assert_eq!(column(1.0, 0.0), vec![(0.0, dirt), (1.0, dirt), (2.0, grass)]);
+        assert!(column(0.0, 1.0).is_empty());
+        assert_eq!(column(1.0, 1.0), vec![(0.0, dirt), (1.0, grass)]);
+    }
+}
@@ -0,0 +1,95 @@
+use std::sync::Arc;
+
+use crate::core::mat4::Mat4;
+use crate::core::vec3::Vec3;
+use crate::scene::mesh::Tri;
+use crate::scene::voxel::Voxel;
+
+/// Geometría reutilizable. El `Arc` deja compartir la misma lista entre
+/// muchas instancias (y clones de la escena) sin copiarla.
+#[derive(Clone, PartialEq)]
+pub enum InstanceKind {
+    /// Grupo de voxels en espacio local
+    Voxels(Arc<Vec<Voxel>>),
+    /// Malla cargada (p. ej. con `mesh::load_obj_triangles`) en espacio local
+    Mesh(Arc<Vec<Tri>>),
+}
+
+impl InstanceKind {
+    /// AABB (min, max) en espacio local.
+    pub fn local_bounds(&self) -> (Vec3, Vec3) {
+        let points: Vec<Vec3> = match self {
+            InstanceKind::Voxels(v) => v.iter().flat_map(|v| [v.min, v.max]).collect(),
+            InstanceKind::Mesh(t) => t.iter().flat_map(|t| [t.v0, t.v1, t.v2]).collect(),
+        };
+        points_bounds(&points)
+    }
+}
+
+/// Caja de un conjunto de puntos; vacía (min > max) si no hay ninguno.
+pub fn points_bounds(points: &[Vec3]) -> (Vec3, Vec3) {
+    let lo = Vec3::new(f64::MAX, f64::MAX, f64::MAX);
+    let hi = Vec3::new(f64::MIN, f64::MIN, f64::MIN);
+    points.iter().fold((lo, hi), |(lo, hi), p| {
+        (
+            Vec3::new(lo.x.min(p.x), lo.y.min(p.y), lo.z.min(p.z)),
+            Vec3::new(hi.x.max(p.x), hi.y.max(p.y), hi.z.max(p.z)),
+        )
+    })
+}
+
+/// Una copia colocada de `base`: los rayos se llevan a su espacio local al
+/// trazarla, así que no se duplica geometría.
+#[derive(Clone, PartialEq)]
+pub struct Instance {
+    pub base: InstanceKind,
+    transform: Mat4,
+    inverse: Mat4,
+    /// AABB en mundo (min, max) de la geometría transformada
+    bounds: (Vec3, Vec3),
+}
+
+impl Instance {
+    /// `transform` lleva de espacio local a mundo. `None` si no es invertible
+    /// (p. ej. una escala 0), porque no habría cómo llevar rayos a local.
+    pub fn new(base: InstanceKind, transform: Mat4) -> Option<Self> {
+        let inverse = transform.inverse_affine()?;
+        let (lo, hi) = base.local_bounds();
+        let corners: Vec<Vec3> = (0..8)
+            .filter(|_| lo.x <= hi.x)
+            .map(|i| {
+                let pick = |bit: usize, a: f64, b: f64| if i & bit == 0 { a } else { b };
+                let c = Vec3::new(pick(1, lo.x, hi.x), pick(2, lo.y, hi.y), pick(4, lo.z, hi.z));
+                transform.transform_point(c)
+            })
+            .collect();
+        let bounds = points_bounds(&corners);
+        Some(Self { base, transform, inverse, bounds })
+    }
+
+    pub fn transform(&self) -> &Mat4 {
+        &self.transform
+    }
+
+    /// Mundo -> local.
+    pub fn inverse(&self) -> &Mat4 {
+        &self.inverse
+    }
+
+    /// Caja en mundo que envuelve la instancia.
+    pub fn bounds(&self) -> (Vec3, Vec3) {
+        self.bounds
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_singular_transform_is_rejected() {
+        let kind = InstanceKind::Voxels(Arc::new(Vec::new()));
+        assert!(Instance::new(kind.clone(), Mat4::scale(Vec3::new(1.0, 0.0, 1.0))).is_none());
+        assert!(Instance::new(kind, Mat4::translation(Vec3::new(1.0, 2.0, 3.0))).is_some());
+    }
+}
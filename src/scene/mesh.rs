@@ -1,21 +1,39 @@
+use crate::core::mat4::Mat4;
 use crate::core::vec3::Vec3;
+use crate::scene::{Material, TextureSource};
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::sync::Mutex;
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq)]
 pub struct Tri {
     pub v0: Vec3, pub v1: Vec3, pub v2: Vec3,
     pub n:  Vec3, // normal plana
     pub mat_id: usize,
+    /// Color por vértice (`v x y z r g b` en el .obj); multiplica al albedo
+    pub colors: Option<[Vec3; 3]>,
 }
 
 impl Tri {
     #[inline]
     pub fn new(v0: Vec3, v1: Vec3, v2: Vec3, n: Vec3, mat_id: usize) -> Self {
-        Self { v0, v1, v2, n: n.normalized(), mat_id }
+        Self { v0, v1, v2, n: n.normalized(), mat_id, colors: None }
+    }
+
+    /// Color de vértice interpolado con las baricéntricas de v1 y v2;
+    /// blanco si la malla no trae colores.
+    #[inline]
+    pub fn color_at(&self, b1: f64, b2: f64) -> Vec3 {
+        match self.colors {
+            Some([c0, c1, c2]) => c0 * (1.0 - b1 - b2) + c1 * b1 + c2 * b2,
+            None => Vec3::new(1.0, 1.0, 1.0),
+        }
     }
 }
 
+#[allow(dead_code)]
 #[inline]
 fn compute_face_normal(a: Vec3, b: Vec3, c: Vec3) -> Vec3 {
     (b - a).cross(c - a).normalized()
@@ -37,21 +55,61 @@ fn fix_idx(len: usize, raw: &str) -> Option<usize> {
     }
 }
 
-// Triangulación en abanico: v[0], v[k], v[k+1]
+// Triangulación en abanico: v[0], v[k], v[k+1]. Cada vértice lleva su
+// color opcional; el triángulo solo guarda colores si los tienen los tres.
+// Devuelve cuántos triángulos se descartaron por degenerados. Con `flip`
+// se invierte el giro (v1 <-> v2), para transformaciones que espejan.
 #[inline]
-fn push_fan(vs: &[Vec3], tris: &mut Vec<Tri>, face_idx: &[usize], mat_id: usize) {
-    if face_idx.len() < 3 { return; }
-    let v0 = vs[face_idx[0]];
-    for k in 1..(face_idx.len() - 1) {
-        let v1 = vs[face_idx[k]];
-        let v2 = vs[face_idx[k + 1]];
+fn push_fan(vs: &[(Vec3, Option<Vec3>)], tris: &mut Vec<Tri>, mat_id: usize, flip: bool) -> usize {
+    if vs.len() < 3 { return 0; }
+    let mut dropped = 0;
+    let (v0, c0) = vs[0];
+    for k in 1..(vs.len() - 1) {
+        let (mut v1, mut c1) = vs[k];
+        let (mut v2, mut c2) = vs[k + 1];
+        if flip {
+            std::mem::swap(&mut v1, &mut v2);
+            std::mem::swap(&mut c1, &mut c2);
+        }
         let e1 = v1 - v0;
         let e2 = v2 - v0;
         let n = e1.cross(e2);
         let len = n.length();
-        if len <= 1e-12 { continue; } // descarta degenerados
+        // descarta degenerados (área ~0) y cualquier cosa no finita
+        if !(len > 1e-12 && len.is_finite()) {
+            dropped += 1;
+            continue;
+        }
         let n = n / len;
-        tris.push(Tri { v0, v1, v2, n, mat_id });
+        let colors = c0.zip(c1).zip(c2).map(|((c0, c1), c2)| [c0, c1, c2]);
+        tris.push(Tri { v0, v1, v2, n, mat_id, colors });
+    }
+    dropped
+}
+
+/// Resumen de lo que se descartó al cargar un .obj.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ObjStats {
+    /// Líneas `v` leídas (válidas o no)
+    pub vertices: usize,
+    /// Líneas `v` con coordenadas faltantes, ilegibles o no finitas
+    pub bad_vertices: usize,
+    /// Líneas `f` leídas
+    pub faces: usize,
+    /// Caras descartadas por referir vértices inexistentes o inválidos
+    pub dropped_faces: usize,
+    /// Triángulos descartados por área ~0
+    pub degenerate_tris: usize,
+}
+
+impl ObjStats {
+    fn report(&self, what: &str) {
+        if self.bad_vertices + self.dropped_faces + self.degenerate_tris > 0 {
+            eprintln!(
+                "{}: {} vértices inválidos, {} caras descartadas, {} triángulos degenerados",
+                what, self.bad_vertices, self.dropped_faces, self.degenerate_tris
+            );
+        }
     }
 }
 
@@ -60,49 +118,207 @@ fn push_fan(vs: &[Vec3], tris: &mut Vec<Tri>, face_idx: &[usize], mat_id: usize)
 /// - Soporta caras con >3 vértices (triangulación en abanico)
 /// - Soporta 'f' en formas: i, i/j, i//k, i/j/k
 /// - Ignora vt/vn (normales planas por cara)
+/// - Lee colores por vértice `v x y z r g b` si vienen
 /// - Aplica `scale` y `translate` a posiciones
 /// - Si el archivo no existe, devuelve `Vec::new()` sin fallar
+/// - Descarta caras con vértices inválidos y triángulos degenerados, y
+///   avisa por stderr cuántos (ver `ObjStats`)
 pub fn load_obj_triangles(path: &str, mat_id: usize, scale: f64, translate: Vec3) -> Vec<Tri> {
+    let (tris, stats) = load_obj_triangles_mtl(path, mat_id, &HashMap::new(), scale, translate);
+    stats.report(path);
+    tris
+}
+
+/// Igual que `load_obj_triangles` pero leyendo de cualquier `BufRead`
+/// (p.ej. texto OBJ en memoria para tests).
+pub fn parse_obj_triangles<R: BufRead>(reader: R, mat_id: usize, scale: f64, translate: Vec3) -> Vec<Tri> {
+    let (tris, stats) = parse_obj_triangles_mtl(reader, mat_id, &HashMap::new(), scale, translate);
+    stats.report("obj");
+    tris
+}
+
+/// Como `load_obj_triangles`, con una transformación afín cualquiera
+/// (rotar, escalar por eje, espejar). Si `xform` espeja (determinante
+/// negativo) se invierte el giro de cada cara para que las normales sigan
+/// apuntando hacia afuera; si aplasta la malla, los triángulos que quedan
+/// sin área se descartan.
+pub fn load_obj_triangles_xform(path: &str, mat_id: usize, xform: &Mat4) -> Vec<Tri> {
+    let Ok(file) = File::open(path) else {
+        return Vec::new();
+    };
+    let (tris, stats) = parse_obj_xform(BufReader::new(file), mat_id, &HashMap::new(), xform);
+    stats.report(path);
+    tris
+}
+
+/// `load_obj_triangles_xform` sobre cualquier `BufRead`.
+pub fn parse_obj_triangles_xform<R: BufRead>(reader: R, mat_id: usize, xform: &Mat4) -> Vec<Tri> {
+    let (tris, stats) = parse_obj_xform(reader, mat_id, &HashMap::new(), xform);
+    stats.report("obj");
+    tris
+}
+
+/// Como `load_obj_triangles`, pero cada `usemtl NOMBRE` cambia el material de
+/// las caras siguientes según `materials`. Las caras antes de cualquier
+/// `usemtl`, o con un nombre que no está en el mapa, usan `mat_id`.
+pub fn load_obj_triangles_mtl(
+    path: &str,
+    mat_id: usize,
+    materials: &HashMap<&str, usize>,
+    scale: f64,
+    translate: Vec3,
+) -> (Vec<Tri>, ObjStats) {
     let file = match File::open(path) {
         Ok(f) => f,
-        Err(_) => return Vec::new(), // opcional: si no existe, no truena
+        Err(_) => return (Vec::new(), ObjStats::default()), // opcional: si no existe, no truena
     };
-    let reader = BufReader::new(file);
+    parse_obj_triangles_mtl(BufReader::new(file), mat_id, materials, scale, translate)
+}
 
-    let mut vs: Vec<Vec3> = Vec::new();
+/// Núcleo del parser sobre cualquier `BufRead` (archivo, `&[u8]`, `Cursor`).
+pub fn parse_obj_triangles_mtl<R: BufRead>(
+    reader: R,
+    mat_id: usize,
+    materials: &HashMap<&str, usize>,
+    scale: f64,
+    translate: Vec3,
+) -> (Vec<Tri>, ObjStats) {
+    let xform = Mat4::translation(translate) * Mat4::scale(Vec3::new(scale, scale, scale));
+    parse_obj_xform(reader, mat_id, materials, &xform)
+}
+
+fn parse_obj_xform<R: BufRead>(
+    reader: R,
+    mat_id: usize,
+    materials: &HashMap<&str, usize>,
+    xform: &Mat4,
+) -> (Vec<Tri>, ObjStats) {
+    let flip = xform.determinant3() < 0.0;
+    // None = vértice inválido; se guarda igual para no correr los índices
+    let mut vs: Vec<Option<(Vec3, Option<Vec3>)>> = Vec::new();
     let mut tris: Vec<Tri> = Vec::new();
+    let mut stats = ObjStats::default();
+    let mut current_mat = mat_id;
 
-    for line in reader.lines().flatten() {
+    for line in reader.lines().map_while(Result::ok) {
         let s = line.trim();
         if s.is_empty() || s.starts_with('#') { continue; }
 
         if s.starts_with("v ") {
-            // vértice: v x y z
+            // vértice: v x y z [r g b] (con 4 números el cuarto es w)
             let parts: Vec<&str> = s.split_whitespace().collect();
-            if parts.len() >= 4 {
-                let x: f64 = parts[1].parse().unwrap_or(0.0);
-                let y: f64 = parts[2].parse().unwrap_or(0.0);
-                let z: f64 = parts[3].parse().unwrap_or(0.0);
-                vs.push(Vec3::new(x, y, z) * scale + translate);
+            let finite = |p: &Vec3| p.x.is_finite() && p.y.is_finite() && p.z.is_finite();
+            let color = parts.get(4..7).and_then(parse_vec3).filter(finite);
+            let v = parse_vec3(&parts[1..])
+                .map(|p| xform.transform_point(p))
+                .filter(finite)
+                .map(|p| (p, color));
+            stats.vertices += 1;
+            if v.is_none() {
+                stats.bad_vertices += 1;
             }
+            vs.push(v);
         } else if s.starts_with("f ") {
             // Cara: i, i/j, i//k, i/j/k, con N-gons
-            let mut face_idx: Vec<usize> = Vec::with_capacity(4);
-            for tok in s.split_whitespace().skip(1) {
-                // Toma el índice de posición (antes de '/')
-                let vi_str = tok.split('/').next().unwrap_or("");
-                if let Some(ix) = fix_idx(vs.len(), vi_str) {
-                    face_idx.push(ix);
+            stats.faces += 1;
+            let face: Option<Vec<(Vec3, Option<Vec3>)>> = s
+                .split_whitespace()
+                .skip(1)
+                .map(|tok| {
+                    // Toma el índice de posición (antes de '/')
+                    let vi_str = tok.split('/').next().unwrap_or("");
+                    fix_idx(vs.len(), vi_str).and_then(|ix| vs[ix])
+                })
+                .collect();
+            match face {
+                Some(face) if face.len() >= 3 => {
+                    stats.degenerate_tris += push_fan(&face, &mut tris, current_mat, flip);
                 }
+                _ => stats.dropped_faces += 1,
             }
-            if face_idx.len() >= 3 {
-                push_fan(&vs, &mut tris, &face_idx, mat_id);
-            }
+        } else if let Some(name) = s.strip_prefix("usemtl ") {
+            current_mat = materials.get(name.trim()).copied().unwrap_or(mat_id);
         }
-        // Ignoramos 'vn', 'vt', 'usemtl', 'mtllib', 'o', 'g' para mantener Tri plano
+        // Ignoramos 'vn', 'vt', 'mtllib', 'o', 'g' para mantener Tri plano
     }
 
-    tris
+    (tris, stats)
+}
+
+/* ========================= MTL ========================= */
+
+/// Carga los materiales de un .mtl como pares (nombre, Material), en orden.
+/// Lee `newmtl`, `Kd`, `Ks`, `d`/`Tr`, `Ni`, `Ke` y `map_Kd` (relativo a la
+/// carpeta del .mtl); lo demás se ignora. Si el archivo no existe, devuelve
+/// `Vec::new()` igual que `load_obj_triangles`.
+pub fn load_mtl(path: &str) -> Vec<(String, Material)> {
+    let file = match File::open(path) {
+        Ok(f) => f,
+        Err(_) => return Vec::new(),
+    };
+    let base = Path::new(path).parent().unwrap_or(Path::new(""));
+    parse_mtl(BufReader::new(file), base)
+}
+
+pub fn parse_mtl<R: BufRead>(reader: R, base_dir: &Path) -> Vec<(String, Material)> {
+    let mut out: Vec<(String, Material)> = Vec::new();
+
+    for line in reader.lines().map_while(Result::ok) {
+        let s = line.trim();
+        if s.is_empty() || s.starts_with('#') { continue; }
+
+        let parts: Vec<&str> = s.split_whitespace().collect();
+        let key = parts[0];
+        let args = &parts[1..];
+
+        if key == "newmtl" {
+            let name = args.join(" ");
+            let mat = Material::new(intern(&name), Vec3::new(0.8, 0.8, 0.8), None);
+            out.push((name, mat));
+            continue;
+        }
+
+        // Las propiedades antes del primer newmtl no tienen dueño
+        let Some((_, mat)) = out.last_mut() else { continue; };
+        match key {
+            "Kd" => if let Some(c) = parse_vec3(args) { mat.albedo = c; },
+            "Ks" => if let Some(c) = parse_vec3(args) { mat.specular = (c.x + c.y + c.z) / 3.0; },
+            "Ke" => if let Some(c) = parse_vec3(args) { *mat = mat.clone().with_emissive(c); },
+            "Ns" => if let Some(ns) = parse_f64(args) { mat.shininess = ns.max(1.0); },
+            "d" => if let Some(d) = parse_f64(args) { mat.transparency = (1.0 - d).clamp(0.0, 1.0); },
+            "Tr" => if let Some(t) = parse_f64(args) { mat.transparency = t.clamp(0.0, 1.0); },
+            "Ni" => if let Some(ior) = parse_f64(args) { mat.ior = ior; },
+            "map_Kd" => if let Some(file) = args.last() {
+                let full = base_dir.join(file);
+                mat.texture = Some(TextureSource::Path(intern(&full.to_string_lossy())));
+            },
+            _ => {} // illum, map_Bump, ...: no se usan
+        }
+    }
+    out
+}
+
+fn parse_f64(args: &[&str]) -> Option<f64> {
+    args.first()?.parse().ok()
+}
+
+fn parse_vec3(args: &[&str]) -> Option<Vec3> {
+    if args.len() < 3 { return None; }
+    Some(Vec3::new(args[0].parse().ok()?, args[1].parse().ok()?, args[2].parse().ok()?))
+}
+
+// Material guarda nombre y ruta como &'static str. Cada texto distinto se
+// guarda una sola vez: recargar el mismo .mtl no vuelve a pedir memoria.
+fn intern(s: &str) -> &'static str {
+    static SEEN: Mutex<Option<HashSet<&'static str>>> = Mutex::new(None);
+    let mut seen = SEEN.lock().unwrap_or_else(|e| e.into_inner());
+    let seen = seen.get_or_insert_with(HashSet::new);
+    if let Some(&known) = seen.get(s) {
+        return known;
+    }
+    let owned: &'static str = Box::leak(s.to_string().into_boxed_str());
+    seen.insert(owned);
+    owned
 }
 
 #[cfg(test)]
@@ -119,4 +335,244 @@ mod tests {
         assert!( (n - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-9
               || (n - Vec3::new(0.0, 0.0, -1.0)).length() < 1e-9 );
     }
+
+    #[test]
+    fn test_usemtl_switches_material() {
+        let obj = "\
+v 0 0 0
+v 1 0 0
+v 0 1 0
+v 1 1 0
+f 1 2 3
+usemtl body
+f 1 2 3
+usemtl eyes
+f 2 4 3
+usemtl unknown
+f 1 2 3
+";
+        let mats = HashMap::from([("body", 4), ("eyes", 7)]);
+        let (tris, _) = parse_obj_triangles_mtl(obj.as_bytes(), 1, &mats, 1.0, Vec3::default());
+        let ids: Vec<usize> = tris.iter().map(|t| t.mat_id).collect();
+        assert_eq!(ids, vec![1, 4, 7, 1]);
+    }
+
+    #[test]
+    fn test_parse_mtl() {
+        let mtl = "\
+# materiales del farol
+Kd 9 9 9
+newmtl wood
+Kd 0.5 0.3 0.1
+Ks 0.2 0.2 0.2
+Ns 96
+illum 2
+newmtl lamp glass
+Kd 1.0 0.9 0.7
+Ke 4.0 3.0 1.0
+d 0.25
+Ni 1.45
+map_Kd lamp.png
+";
+        let mats = parse_mtl(mtl.as_bytes(), Path::new("assets/models"));
+        assert_eq!(mats.len(), 2);
+
+        let (name, wood) = &mats[0];
+        assert_eq!(name, "wood");
+        assert_eq!(wood.albedo, Vec3::new(0.5, 0.3, 0.1));
+        assert_eq!(wood.emissive, Vec3::new(0.0, 0.0, 0.0));
+        assert!((wood.specular - 0.2).abs() < 1e-12);
+        assert_eq!(wood.shininess, 96.0);
+
+        let (name, lamp) = &mats[1];
+        assert_eq!(name, "lamp glass");
+        assert_eq!(lamp.albedo, Vec3::new(1.0, 0.9, 0.7));
+        assert_eq!(lamp.emission(), Vec3::new(4.0, 3.0, 1.0));
+        assert!((lamp.transparency - 0.75).abs() < 1e-12);
+        assert!((lamp.ior - 1.45).abs() < 1e-12);
+        assert_eq!(
+            lamp.texture_path().map(Path::new),
+            Some(Path::new("assets/models/lamp.png"))
+        );
+
+        // una segunda carga reusa los mismos textos
+        let again = parse_mtl(mtl.as_bytes(), Path::new("assets/models"));
+        assert!(std::ptr::eq(again[1].1.name, lamp.name));
+        assert!(std::ptr::eq(again[1].1.texture_path().unwrap(), lamp.texture_path().unwrap()));
+    }
+
+    const QUAD: &str = "\
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vt 1 0
+vt 1 1
+vn 0 0 1
+";
+
+    fn parse(faces: &str) -> Vec<Tri> {
+        let text = format!("{}{}", QUAD, faces);
+        parse_obj_triangles(text.as_bytes(), 0, 1.0, Vec3::default())
+    }
+
+    #[test]
+    fn test_vertex_colors() {
+        let obj = "\
+v 0 0 0 1 0 0
+v 1 0 0 0 1 0
+v 0 1 0 0 0 1
+v 1 1 0
+f 1 2 3
+f 2 4 3
+";
+        let tris = parse_obj_triangles(obj.as_bytes(), 0, 1.0, Vec3::default());
+        assert_eq!(tris.len(), 2);
+        let colors = tris[0].colors.unwrap();
+        assert_eq!(colors[1], Vec3::new(0.0, 1.0, 0.0));
+        let mid = tris[0].color_at(1.0 / 3.0, 1.0 / 3.0);
+        assert!((mid - Vec3::new(1.0, 1.0, 1.0) / 3.0).length() < 1e-12);
+        // un vértice sin color: el triángulo queda sin colores
+        assert!(tris[1].colors.is_none());
+        assert_eq!(tris[1].color_at(0.2, 0.3), Vec3::new(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn test_face_plain_indices() {
+        let tris = parse("f 1 2 3\n");
+        assert_eq!(tris.len(), 1);
+        assert_eq!(tris[0].v1, Vec3::new(1.0, 0.0, 0.0));
+        assert!((tris[0].n - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-12);
+    }
+
+    #[test]
+    fn test_face_with_texcoords() {
+        let tris = parse("f 1/1 2/2 3/3\n");
+        assert_eq!(tris.len(), 1);
+        assert_eq!(tris[0].v2, Vec3::new(1.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn test_face_with_normals_only() {
+        let tris = parse("f 1//1 2//1 3//1\n");
+        assert_eq!(tris.len(), 1);
+        assert_eq!(tris[0].v0, Vec3::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_face_full_form() {
+        let tris = parse("f 1/1/1 3/3/1 4/1/1\n");
+        assert_eq!(tris.len(), 1);
+        assert_eq!(tris[0].v2, Vec3::new(0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn test_face_negative_indices() {
+        // -1 es el último vértice leído (el 4)
+        let tris = parse("f -4 -3 -1\n");
+        assert_eq!(tris.len(), 1);
+        assert_eq!(tris[0].v0, Vec3::new(0.0, 0.0, 0.0));
+        assert_eq!(tris[0].v2, Vec3::new(0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn test_face_ngon_fan() {
+        let tris = parse("f 1 2 3 4\n");
+        assert_eq!(tris.len(), 2);
+        assert_eq!(tris[1].v0, Vec3::new(0.0, 0.0, 0.0));
+        assert_eq!(tris[1].v1, Vec3::new(1.0, 1.0, 0.0));
+        assert_eq!(tris[1].v2, Vec3::new(0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn test_scale_and_translate() {
+        let text = format!("{}f 1 2 3\n", QUAD);
+        let tris = parse_obj_triangles(text.as_bytes(), 3, 2.0, Vec3::new(0.0, 5.0, 0.0));
+        assert_eq!(tris[0].v1, Vec3::new(2.0, 5.0, 0.0));
+        assert_eq!(tris[0].mat_id, 3);
+    }
+
+    #[test]
+    fn test_mirrored_xform_keeps_normals_outward() {
+        // tetraedro cerrado con caras hacia afuera
+        let obj = "\
+v 0 0 0
+v 1 0 0
+v 0 1 0
+v 0 0 1
+f 1 3 2
+f 1 2 4
+f 1 4 3
+f 2 3 4
+";
+        let outward = |tris: &[Tri]| {
+            let c = tris.iter().fold(Vec3::default(), |acc, t| acc + t.v0 + t.v1 + t.v2)
+                / (3.0 * tris.len() as f64);
+            tris.iter().all(|t| {
+                let n_ok = t.n.x.is_finite() && t.n.y.is_finite() && t.n.z.is_finite();
+                n_ok && t.n.dot((t.v0 + t.v1 + t.v2) / 3.0 - c) > 0.0
+            })
+        };
+        let plain = parse_obj_triangles_xform(obj.as_bytes(), 0, &Mat4::identity());
+        assert!(outward(&plain));
+
+        for s in [Vec3::new(-1.0, 1.0, 1.0), Vec3::new(-2.0, -2.0, -2.0)] {
+            let xform = Mat4::translation(Vec3::new(3.0, 0.0, 0.0)) * Mat4::scale(s);
+            let tris = parse_obj_triangles_xform(obj.as_bytes(), 0, &xform);
+            assert_eq!(tris.len(), 4);
+            assert!(outward(&tris), "{:?}", s);
+        }
+        // también con la escala uniforme de siempre
+        let (tris, _) =
+            parse_obj_triangles_mtl(obj.as_bytes(), 0, &HashMap::new(), -1.0, Vec3::default());
+        assert!(outward(&tris));
+
+        // aplastada en y: todo sin área, se descarta sin NaN
+        let flat = Mat4::scale(Vec3::new(1.0, 0.0, 1.0));
+        let (tris, stats) = parse_obj_xform(obj.as_bytes(), 0, &HashMap::new(), &flat);
+        assert!(tris.len() < 4 && stats.degenerate_tris == 4 - tris.len());
+        assert!(tris.iter().all(|t| t.n.x.is_finite()));
+    }
+
+    #[test]
+    fn test_missing_file_is_empty() {
+        assert!(load_obj_triangles("no/existe.obj", 0, 1.0, Vec3::default()).is_empty());
+    }
+
+    #[test]
+    fn test_malformed_obj_is_reported_without_nans() {
+        let obj = "\
+v 0 0 0
+v 1 0 0
+v 0 1 0
+v 1 1
+v nan 0 0
+v 2 0 0
+f 1 2 3
+f 1 2 4
+f 1 2 5
+f 1 2 9
+f 1 2 x
+f 1 2 6
+f 1 2
+";
+        let (tris, stats) =
+            parse_obj_triangles_mtl(obj.as_bytes(), 0, &HashMap::new(), 1.0, Vec3::default());
+
+        assert_eq!(tris.len(), 1);
+        assert_eq!(stats.vertices, 6);
+        assert_eq!(stats.bad_vertices, 2);
+        assert_eq!(stats.faces, 7);
+        // caras 1-2-4, 1-2-5 (vértices inválidos), 1-2-9, 1-2-x y la de 2 vértices
+        assert_eq!(stats.dropped_faces, 5);
+        // 1-2-6 es colineal
+        assert_eq!(stats.degenerate_tris, 1);
+
+        for t in &tris {
+            for v in [t.v0, t.v1, t.v2, t.n] {
+                assert!(v.x.is_finite() && v.y.is_finite() && v.z.is_finite());
+            }
+        }
+    }
 }
@@ -1,6 +1,13 @@
+use std::sync::Arc;
+
+use crate::core::mat4::Mat4;
 use crate::core::vec3::Vec3;
+use crate::scene::voxel::VoxelAnim;
 
+pub mod instance;
 pub mod mesh;
+pub mod plane;
+pub mod sphere;
 pub mod voxel;
 pub mod builder;
 
@@ -9,7 +16,20 @@ pub use builder::build_minecraft_house_scene;
 
 /* ========================= Material ========================= */
 
-#[derive(Clone)]
+/// `Material::specular` por defecto; referencia para escalar el brillo.
+pub const DEFAULT_SPECULAR: f64 = 0.04;
+
+/// De dónde sale la textura de un material.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TextureSource {
+    /// Imagen en disco (cualquier formato de `image`, o BMP de 24 bpp).
+    Path(&'static str),
+    /// Píxeles RGB de 8 bits en memoria (`w * h * 3` bytes), p. ej. ruido
+    /// procedural o un atlas generado.
+    Rgb { w: usize, h: usize, data: Arc<[u8]> },
+}
+
+#[derive(Clone, PartialEq)]
 pub struct Material {
     pub name: &'static str,
 
@@ -19,20 +39,53 @@ pub struct Material {
     /// Fuerza especular (0..1)
     pub specular: f64,
 
+    /// Exponente Blinn-Phong del brillo del sol (más alto = más concentrado)
+    pub shininess: f64,
+
+    /// Intensidad del brillo con `specular` en su valor por defecto; el
+    /// brillo escala con specular / 0.04
+    pub spec_strength: f64,
+
+    /// Sombreado PBR: 0 = dieléctrico, 1 = metal (sin difusa, brillo
+    /// teñido por el albedo)
+    pub metallic: f64,
+
+    /// Rugosidad (0..1) del PBR: más alta = brillo más ancho y tenue
+    pub roughness: f64,
+
+    /// Rugosidad del espejo plano: reflejo más borroso (0 = espejo
+    /// perfecto). Solo la cambia `with_roughness`, así el 0.5 por defecto
+    /// del PBR no empaña el agua.
+    pub reflection_roughness: f64,
+
     /// Transparencia (0..1) – para refracción si la activas
     pub transparency: f64,
 
     /// Reflectividad (0..1) – para reflexión si la activas
     pub reflectivity: f64,
 
+    /// Espejo plano en las caras superiores (agua quieta): intensidad
+    /// 0..1 del reflejo, modulado por Fresnel. 0 = desactivado.
+    pub planar_reflection: f64,
+
     /// Índice de refracción (vidrio ~1.5)
     pub ior: f64,
 
-    /// Emisión (antorchas, campfires, etc.)
+    /// Color de la emisión (antorchas, campfires, etc.), con el canal
+    /// mayor en 1; el brillo va en `emissive_strength`.
     pub emissive: Vec3,
 
-    /// Ruta a textura BMP (24 bpp). Si None, usa solo albedo.
-    pub texture_path: Option<&'static str>,
+    /// Intensidad que multiplica a `emissive` (ver `emission`).
+    pub emissive_strength: f64,
+
+    /// Parpadeo de la luz emitida: amplitud (0 = luz fija) y frecuencia
+    /// en rad/s. Cada luz lleva además su propia fase aleatoria.
+    pub flicker_amp: f64,
+    pub flicker_freq: f64,
+
+    /// Textura del albedo (archivo o píxeles en memoria). Si None, usa
+    /// solo albedo.
+    pub texture: Option<TextureSource>,
 
     /// Escala de UV por material (cómo de “repetida” se ve la textura).
     /// 1.0 = 1 tile por unidad, 4.0 = 4 tiles por unidad, etc.
@@ -40,6 +93,19 @@ pub struct Material {
 
     /// Si true, aplicará animación simple a las UV (agua, lava, etc.)
     pub animated_uv: bool,
+
+    /// Umbral de recorte por alfa (0..1): si el alfa de la textura en el
+    /// impacto es menor, el rayo lo atraviesa (hojas, rejas). 0 = desactivado.
+    pub alpha_cutout: f64,
+
+    /// Iluminada por ambas caras (hojas, papel): si se ve la cara trasera
+    /// se sombrea con la normal invertida.
+    pub double_sided: bool,
+
+    /// Texturado triplanar: repeticiones por unidad de mundo de las tres
+    /// proyecciones por eje, mezcladas según la normal. 0 = UV de la
+    /// primitiva (lo de siempre).
+    pub triplanar: f64,
 }
 
 impl Material {
@@ -51,28 +117,125 @@ impl Material {
         Self {
             name,
             albedo,
-            specular: 0.04,
+            specular: DEFAULT_SPECULAR,
+            shininess: 32.0,
+            spec_strength: 0.15,
+            metallic: 0.0,
+            roughness: 0.5,
+            reflection_roughness: 0.0,
             transparency: 0.0,
             reflectivity: 0.0,
+            planar_reflection: 0.0,
             ior: 1.5,
             emissive: Vec3::new(0.0, 0.0, 0.0),
-            texture_path,
+            emissive_strength: 1.0,
+            flicker_amp: 0.2,
+            flicker_freq: 6.0,
+            texture: texture_path.map(TextureSource::Path),
             uv_scale: 1.0,
             animated_uv: false,
+            alpha_cutout: 0.0,
+            double_sided: false,
+            triplanar: 0.0,
         }
     }
 
     pub fn with_uv_scale(mut self, s: f64) -> Self { self.uv_scale = s; self }
     pub fn with_specular(mut self, k: f64) -> Self { self.specular = k; self }
-    pub fn with_emissive(mut self, e: Vec3) -> Self { self.emissive = e; self }
+    pub fn with_shininess(mut self, s: f64) -> Self { self.shininess = s; self }
+    pub fn with_spec_strength(mut self, k: f64) -> Self { self.spec_strength = k; self }
+    pub fn with_metallic(mut self, m: f64) -> Self { self.metallic = m; self }
+    pub fn with_roughness(mut self, r: f64) -> Self {
+        self.roughness = r;
+        self.reflection_roughness = r;
+        self
+    }
+    /// Emisión ya escalada: la intensidad sale del canal mayor de `e`.
+    pub fn with_emissive(self, e: Vec3) -> Self {
+        let strength = e.x.max(e.y).max(e.z);
+        self.with_emission(e, strength)
+    }
+    /// Color (se normaliza al canal mayor) e intensidad por separado.
+    pub fn with_emission(mut self, color: Vec3, strength: f64) -> Self {
+        let peak = color.x.max(color.y).max(color.z);
+        if peak > 0.0 {
+            self.emissive = color / peak;
+            self.emissive_strength = strength;
+        } else {
+            self.emissive = Vec3::new(0.0, 0.0, 0.0);
+            self.emissive_strength = 0.0;
+        }
+        self
+    }
     pub fn animated(mut self, on: bool) -> Self { self.animated_uv = on; self }
     pub fn with_reflection(mut self, r: f64) -> Self { self.reflectivity = r; self }
     pub fn with_transparency(mut self, t: f64, ior: f64) -> Self { self.transparency = t; self.ior = ior; self }
+    pub fn with_alpha_cutout(mut self, a: f64) -> Self { self.alpha_cutout = a; self }
+    pub fn with_double_sided(mut self, on: bool) -> Self { self.double_sided = on; self }
+    pub fn with_planar_reflection(mut self, k: f64) -> Self { self.planar_reflection = k; self }
+    pub fn with_triplanar(mut self, scale: f64) -> Self { self.triplanar = scale.max(0.0); self }
+    pub fn with_flicker(mut self, amp: f64, freq: f64) -> Self { self.flicker_amp = amp; self.flicker_freq = freq; self }
+
+    /// Textura generada en memoria: `data` son `w * h` píxeles RGB de 8 bits,
+    /// fila a fila desde arriba.
+    pub fn with_texture_rgb(mut self, w: usize, h: usize, data: Vec<u8>) -> Self {
+        self.texture = Some(TextureSource::Rgb { w, h, data: data.into() });
+        self
+    }
+
+    /// Ruta de la textura, si se carga de archivo.
+    pub fn texture_path(&self) -> Option<&'static str> {
+        match self.texture {
+            Some(TextureSource::Path(p)) => Some(p),
+            _ => None,
+        }
+    }
+
+    /// Radiancia emitida: color por intensidad.
+    pub fn emission(&self) -> Vec3 {
+        self.emissive * self.emissive_strength
+    }
+
+    /// Material ya ajustado por nombre (ver `PRESETS`), sin textura; se
+    /// puede seguir afinando con los `with_*`. Un nombre desconocido da
+    /// `matte`.
+    pub fn preset(name: &str) -> Self {
+        match name {
+            "glass" => Material::new("glass", Vec3::new(0.95, 0.97, 1.0), None)
+                .with_specular(0.6)
+                .with_reflection(0.25)
+                .with_transparency(0.6, 1.5),
+            "water" => Material::new("water", Vec3::new(0.25, 0.45, 0.95), None)
+                .with_specular(0.12)
+                .with_transparency(0.5, 1.33)
+                .with_planar_reflection(0.8)
+                .with_roughness(0.0),
+            "metal_polished" => Material::new("metal_polished", Vec3::new(0.9, 0.9, 0.92), None)
+                .with_metallic(1.0)
+                .with_roughness(0.1)
+                .with_reflection(0.6),
+            "metal_rough" => Material::new("metal_rough", Vec3::new(0.75, 0.75, 0.78), None)
+                .with_metallic(1.0)
+                .with_roughness(0.6),
+            "emissive_warm" => Material::new("emissive_warm", Vec3::new(1.0, 0.85, 0.45), None)
+                .with_emission(Vec3::new(1.0, 0.65, 0.3), 4.0),
+            "leaves" => Material::new("leaves", Vec3::new(0.65, 0.85, 0.6), None)
+                .with_specular(0.02)
+                .with_double_sided(true),
+            _ => Material::new("matte", Vec3::new(0.7, 0.7, 0.7), None)
+                .with_specular(0.02)
+                .with_roughness(1.0),
+        }
+    }
 }
 
+/// Nombres de `Material::preset`; no cambian entre versiones.
+pub const PRESETS: [&str; 7] =
+    ["glass", "water", "metal_polished", "metal_rough", "emissive_warm", "leaves", "matte"];
+
 /* ========================= Skybox ========================= */
 
-#[derive(Clone, Default)]
+#[derive(Clone, Default, PartialEq)]
 pub struct Skybox {
     pub right:  Option<&'static str>, // +X
     pub left:   Option<&'static str>, // -X
@@ -84,7 +247,7 @@ pub struct Skybox {
 
 /* ========================= Portales ========================= */
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct Portal {
     pub min: Vec3,
     pub max: Vec3,
@@ -94,15 +257,90 @@ pub struct Portal {
     pub rot_y_deg: f64,
 }
 
+impl Portal {
+    /// Marco de entrada -> salida: gira `rot_y_deg` alrededor del centro de
+    /// la caja y lleva ese centro a `to_pos`. Sirve igual para puntos y
+    /// direcciones, así que la vista queda coherente, no solo desplazada.
+    pub fn transform(&self) -> Mat4 {
+        let center = (self.min + self.max) * 0.5;
+        Mat4::translation(self.to_pos)
+            * Mat4::rotation_y(self.rot_y_deg)
+            * Mat4::translation(-center)
+    }
+
+    pub fn contains(&self, p: Vec3) -> bool {
+        (self.min.x..=self.max.x).contains(&p.x)
+            && (self.min.y..=self.max.y).contains(&p.y)
+            && (self.min.z..=self.max.z).contains(&p.z)
+    }
+}
+
+/* ======================= Luces de área ======================= */
+
+/// Panel de luz rectangular (ventana, plafón): `corner + a*edge_u +
+/// b*edge_v` con a, b en [0,1]. Emite solo hacia `edge_u x edge_v`. No es
+/// geometría: no se ve ni tapa nada, solo ilumina.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AreaLight {
+    pub corner: Vec3,
+    pub edge_u: Vec3,
+    pub edge_v: Vec3,
+    pub color: Vec3,
+    pub intensity: f64,
+}
+
+impl AreaLight {
+    /// Normal unitaria del lado que emite.
+    pub fn normal(&self) -> Vec3 {
+        self.edge_u.cross(self.edge_v).normalized()
+    }
+
+    pub fn area(&self) -> f64 {
+        self.edge_u.cross(self.edge_v).length()
+    }
+
+    pub fn radiance(&self) -> Vec3 {
+        self.color * self.intensity
+    }
+
+    /// Punto del panel para (a, b) en [0,1]².
+    pub fn point(&self, a: f64, b: f64) -> Vec3 {
+        self.corner + self.edge_u * a + self.edge_v * b
+    }
+}
+
 /* ========================= Scene ========================= */
 
-#[derive(Clone)]
+/// Problema de autoría encontrado por `Scene::validate`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SceneWarning {
+    /// Voxel con `min > max` en algún eje (min y max cruzados); la caja
+    /// se intersecta igual pero las normales salen mal. Lo arregla
+    /// `Scene::normalize_voxels`.
+    InsideOutVoxel { index: usize },
+    /// Voxel sin volumen: `min == max` en algún eje.
+    FlatVoxel { index: usize },
+}
+
+#[derive(Clone, Default, PartialEq)]
 pub struct Scene {
     pub materials: Vec<Material>,
     pub voxels: Vec<voxel::Voxel>,
     pub triangles: Vec<mesh::Tri>,
+    /// Copias transformadas de grupos de voxels o mallas compartidas
+    pub instances: Vec<instance::Instance>,
+    pub spheres: Vec<sphere::Sphere>,
+    /// Planos infinitos; vacío por defecto (el suelo de siempre es un voxel)
+    pub planes: Vec<plane::Plane>,
     pub skybox: Skybox,
     pub portals: Vec<Portal>,
+    /// Paneles de luz muestreados explícitamente en cada impacto
+    pub area_lights: Vec<AreaLight>,
+    /// Ignorar caras traseras de triángulos en los rayos de cámara (mallas
+    /// cerradas). Los rayos de sombra siguen siendo de dos caras.
+    pub cull_backfaces: bool,
+    /// Voxels animados: (índice en `voxels`, movimiento)
+    pub voxel_anims: Vec<(usize, VoxelAnim)>,
 }
 
 impl Scene {
@@ -111,10 +349,109 @@ impl Scene {
             materials: Vec::new(),
             voxels: Vec::new(),
             triangles: Vec::new(),
+            instances: Vec::new(),
+            spheres: Vec::new(),
+            planes: Vec::new(),
             skybox: Skybox::default(),
             portals: Vec::new(),
+            area_lights: Vec::new(),
+            cull_backfaces: false,
+            voxel_anims: Vec::new(),
         }
     }
 
     pub fn new_empty() -> Self { Self::new() }
+
+    /// Anima el voxel `index`; `voxels[index]` queda como posición de reposo.
+    /// Si es emisivo, su luz se queda en el reposo (las luces salen de
+    /// `Renderer::set_scene`).
+    pub fn animate_voxel(&mut self, index: usize, anim: VoxelAnim) {
+        self.voxel_anims.push((index, anim));
+    }
+
+    /// Revisa la escena en busca de errores de autoría (por ahora, voxels
+    /// con `min >= max` en algún eje). No cambia nada.
+    pub fn validate(&self) -> Vec<SceneWarning> {
+        let mut out = Vec::new();
+        for (index, v) in self.voxels.iter().enumerate() {
+            let axes = [(v.min.x, v.max.x), (v.min.y, v.max.y), (v.min.z, v.max.z)];
+            if axes.iter().any(|&(a, b)| a > b) {
+                out.push(SceneWarning::InsideOutVoxel { index });
+            } else if axes.iter().any(|&(a, b)| a == b) {
+                out.push(SceneWarning::FlatVoxel { index });
+            }
+        }
+        out
+    }
+
+    /// Intercambia min y max en los ejes cruzados de cada voxel. Devuelve
+    /// cuántos voxels cambió; los planos (`FlatVoxel`) se quedan como están.
+    pub fn normalize_voxels(&mut self) -> usize {
+        let mut fixed = 0;
+        for v in &mut self.voxels {
+            let (min, max) = (v.min, v.max);
+            v.min = Vec3::new(min.x.min(max.x), min.y.min(max.y), min.z.min(max.z));
+            v.max = Vec3::new(min.x.max(max.x), min.y.max(max.y), min.z.max(max.z));
+            if v.min != min {
+                fixed += 1;
+            }
+        }
+        fixed
+    }
+
+    /// Los voxels animados en el instante `time`: (índice en `voxels`,
+    /// voxel movido). El resto de la escena no cambia con el tiempo.
+    pub fn animated_voxels(&self, time: f64) -> impl Iterator<Item = (usize, voxel::Voxel)> + '_ {
+        self.voxel_anims.iter().filter_map(move |&(i, anim)| {
+            let mut v = self.voxels.get(i)?.clone();
+            let d = anim.offset(time);
+            v.min = v.min + d;
+            v.max = v.max + d;
+            Some((i, v))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_animated_voxel_moves_with_time() {
+        let mut scene = Scene::new();
+        scene.voxels.push(voxel::Voxel::from_grid(0, 0, 0, 0));
+        scene.voxels.push(voxel::Voxel::from_grid(3, 0, 0, 0));
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        scene.animate_voxel(1, VoxelAnim::Bob { axis: up, amplitude: 0.5, freq: 0.25, phase: 0.0 });
+
+        let at = |t: f64| scene.animated_voxels(t).collect::<Vec<_>>();
+        let (a, b) = (at(0.0), at(1.0));
+        // solo sale el animado; un cuarto de ciclo: sube toda la amplitud
+        assert_eq!((a.len(), a[0].0, b[0].0), (1, 1, 1));
+        let d = b[0].1.min - a[0].1.min;
+        assert!((d - up * 0.5).length() < 1e-12, "{:?}", d);
+        assert_eq!(b[0].1.max - b[0].1.min, Vec3::new(1.0, 1.0, 1.0));
+        assert_eq!(Scene::new().animated_voxels(5.0).count(), 0);
+
+        let slide = VoxelAnim::Slide { offset: Vec3::new(2.0, 0.0, 0.0), period: 4.0 };
+        assert_eq!(slide.offset(2.0), Vec3::new(2.0, 0.0, 0.0));
+        assert_eq!(slide.offset(1.0), Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(slide.offset(4.0), Vec3::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_presets() {
+        let glass = Material::preset("glass");
+        assert!(glass.transparency > 0.0);
+        assert!((glass.ior - 1.5).abs() < 1e-9);
+
+        let unknown = Material::preset("no-existe");
+        assert_eq!(unknown.name, "matte");
+        assert_eq!(unknown.transparency, 0.0);
+        assert_eq!(unknown.emission(), Vec3::new(0.0, 0.0, 0.0));
+        // cada nombre listado tiene su propio preset
+        for name in PRESETS {
+            assert_eq!(Material::preset(name).name, name);
+        }
+    }
 }
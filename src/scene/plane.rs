@@ -0,0 +1,28 @@
+use crate::core::vec3::Vec3;
+
+/// Plano infinito (suelo analítico): sin bordes, así el horizonte del
+/// cielo procedural se junta con un suelo de verdad.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Plane {
+    /// Cualquier punto del plano
+    pub point: Vec3,
+    /// Normal unitaria; el lado hacia el que apunta es el de "arriba"
+    pub normal: Vec3,
+    pub mat_id: usize,
+}
+
+impl Plane {
+    pub fn new(point: Vec3, normal: Vec3, mat_id: usize) -> Self {
+        Self { point, normal: normal.normalized(), mat_id }
+    }
+
+    /// Suelo horizontal a la altura `y`.
+    pub fn ground(y: f64, mat_id: usize) -> Self {
+        Self::new(Vec3::new(0.0, y, 0.0), Vec3::new(0.0, 1.0, 0.0), mat_id)
+    }
+
+    /// UV planares: X y Z de mundo, como las caras superiores de los voxels.
+    pub fn uv(p: Vec3) -> (f64, f64) {
+        (p.x, p.z)
+    }
+}
@@ -0,0 +1,29 @@
+use crate::core::vec3::Vec3;
+
+/// Esfera analítica (planetas, pelotas, el disco del sol). Comparte
+/// materiales y sombreado con voxels y triángulos.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sphere {
+    pub center: Vec3,
+    pub radius: f64,
+    pub mat_id: usize,
+}
+
+impl Sphere {
+    pub fn new(center: Vec3, radius: f64, mat_id: usize) -> Self {
+        Self { center, radius: radius.abs(), mat_id }
+    }
+
+    /// Normal exterior unitaria en `p` (un punto de la superficie).
+    pub fn normal_at(&self, p: Vec3) -> Vec3 {
+        (p - self.center) / self.radius
+    }
+
+    /// UV esféricas de la normal `n`: u = longitud alrededor de +Y, v = 0
+    /// en el polo sur y 1 en el norte.
+    pub fn uv(n: Vec3) -> (f64, f64) {
+        let u = 0.5 + n.z.atan2(n.x) / std::f64::consts::TAU;
+        let v = 0.5 + n.y.clamp(-1.0, 1.0).asin() / std::f64::consts::PI;
+        (u, v)
+    }
+}
@@ -1,12 +1,18 @@
 use crate::core::vec3::Vec3;
 
+/// Tinte neutro: el albedo del material tal cual.
+pub const NO_TINT: Vec3 = Vec3::new(1.0, 1.0, 1.0);
+
 /// Voxel axis-aligned (cubo unidad), definido por celda (i,j,k) y material.
 /// Aquí guardamos el AABB en espacio mundo para facilitar intersecciones.
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct Voxel {
     pub min: Vec3,
     pub max: Vec3,
     pub mat_id: usize,
+    /// Se multiplica con el albedo del material: variaciones baratas
+    /// (pasto más claro u oscuro) sin un material por bloque
+    pub tint: Vec3,
 }
 
 impl Voxel {
@@ -15,6 +21,41 @@ impl Voxel {
         let (x0,y0,z0) = (i as f64, j as f64, k as f64);
         let min = Vec3::new(x0, y0, z0);
         let max = Vec3::new(x0+1.0, y0+1.0, z0+1.0);
-        Self { min, max, mat_id }
+        Self { min, max, mat_id, tint: NO_TINT }
+    }
+
+    pub fn with_tint(mut self, tint: Vec3) -> Self {
+        self.tint = tint;
+        self
+    }
+}
+
+/// Movimiento de un voxel en función del `time` del frame (ver
+/// `Scene::animate_voxel`). Desplaza la caja entera, sin rotarla.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VoxelAnim {
+    /// Flota: `axis * amplitude * sin(2π freq t + phase)`
+    Bob { axis: Vec3, amplitude: f64, freq: f64, phase: f64 },
+    /// Ida y vuelta lineal hasta `offset` cada `period` (pistón, puerta)
+    Slide { offset: Vec3, period: f64 },
+}
+
+impl VoxelAnim {
+    /// Desplazamiento respecto de la posición de reposo en `time`.
+    pub fn offset(&self, time: f64) -> Vec3 {
+        match *self {
+            VoxelAnim::Bob { axis, amplitude, freq, phase } => {
+                let w = std::f64::consts::TAU * freq * time + phase;
+                axis * (amplitude * w.sin())
+            }
+            VoxelAnim::Slide { offset, period } => {
+                if period <= 0.0 {
+                    return Vec3::new(0.0, 0.0, 0.0);
+                }
+                // triangular: 0 -> 1 en medio período, 1 -> 0 en el otro
+                let f = (time / period).rem_euclid(1.0);
+                offset * (1.0 - (2.0 * f - 1.0).abs())
+            }
+        }
     }
 }